@@ -1,11 +1,18 @@
-use std::thread;
+use std::sync::{Arc, Mutex, mpsc, atomic::{AtomicBool, Ordering}};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::io::{Error, ErrorKind};
-use paho_mqtt::{Client, ConnectOptionsBuilder, CreateOptionsBuilder, message::Message};
+use paho_mqtt::{Client, ConnectOptionsBuilder, CreateOptionsBuilder, SslOptions, SslOptionsBuilder, message::Message};
 use tokio::sync::watch;
+use crate::events::{Event, EventType, Marshaler};
+use regex::Regex;
 use tokio::sync::watch::Receiver;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity, Error as TransportError};
 
 /**
-    This is to manage the connection to the Chirpstack Application Server.
+    This is to manage the connection to the Chirpstack Application Server.<br/>
+    All service clients are built from one channel, which is connected when it is used first
+    and shared by all clones of the connection.
 */
 #[derive(Clone)]
 pub struct ChirpstackConnection {
@@ -13,6 +20,14 @@ pub struct ChirpstackConnection {
     api_token: String,
     /// The URI on which the Chirpstack Application Server is reached.
     uri: String,
+    /// The TLS options; `None` if the connection is not encrypted.
+    tls: Option<ChirpstackTls>,
+    /// The timeout of every request; `None` if there is none.
+    timeout: Option<Duration>,
+    /// The interval of the HTTP2 keep-alive pings; `None` if no pings are sent.
+    keep_alive: Option<Duration>,
+    /// The channel, which is shared by all service clients; `None` until it is used first.
+    channel: Arc<Mutex<Option<Channel>>>,
 }
 
 impl ChirpstackConnection {
@@ -24,6 +39,10 @@ impl ChirpstackConnection {
         let con = ChirpstackConnection {
             api_token: api_token.to_string(),
             uri: uri.to_string(),
+            tls: None,
+            timeout: None,
+            keep_alive: None,
+            channel: Arc::new(Mutex::new(None)),
         };
         return con;
     }
@@ -36,6 +55,7 @@ impl ChirpstackConnection {
     /// Sets a new uri. <br/> The uri where the Chirpstack Application Server can be reached.
     pub fn change_uri(&mut self, uri: &str)  {
         self.uri = uri.to_string();
+        self.reset_channel();
     }
 
     /// Gets a copy of API token.
@@ -48,6 +68,151 @@ impl ChirpstackConnection {
         self.api_token = api_token.to_string();
     }
 
+    /// Gets the TLS options; `None` if the connection is not encrypted.
+    pub fn get_tls(&self) -> Option<&ChirpstackTls> {
+        return self.tls.as_ref();
+    }
+
+    /// Sets the TLS options, if the Chirpstack Application Server is reached via https.<br/>
+    /// For an example see this [link](./index.html#grpc-connection).
+    pub fn set_tls(&mut self, tls: ChirpstackTls) {
+        self.tls = Some(tls);
+        self.reset_channel();
+    }
+
+    /// Removes the TLS options, so that the connection is not encrypted.
+    pub fn remove_tls(&mut self) {
+        self.tls = None;
+        self.reset_channel();
+    }
+
+    /// Gets the timeout of every request; `None` if there is none.
+    pub fn get_timeout(&self) -> Option<Duration> {
+        return self.timeout;
+    }
+
+    /// Sets the timeout of every request, which includes connecting to the Chirpstack Application Server.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+        self.reset_channel();
+    }
+
+    /// Gets the interval of the HTTP2 keep-alive pings; `None` if no pings are sent.
+    pub fn get_keep_alive(&self) -> Option<Duration> {
+        return self.keep_alive;
+    }
+
+    /// Sets the interval of the HTTP2 keep-alive pings, which keep an idle connection open
+    /// and detect a broken one.
+    pub fn set_keep_alive(&mut self, keep_alive: Option<Duration>) {
+        self.keep_alive = keep_alive;
+        self.reset_channel();
+    }
+
+    /// Gets the channel, which is shared by all service clients, e.g.
+    /// `DeviceServiceClient::new(connection.get_channel()?)`.<br/>
+    /// The channel is created when it is used first and connects when the first request is sent;
+    /// if the connection is lost, it is connected again with the next request.
+    /// Must be called within a tokio runtime.
+    pub fn get_channel(&self) -> Result<Channel, TransportError> {
+        let mut channel = self.channel.lock().unwrap();
+        if let Some(channel) = channel.as_ref() {
+            return Ok(channel.clone());
+        }
+        let mut endpoint = Endpoint::new(self.uri.clone())?;
+        if let Some(tls) = &self.tls {
+            endpoint = endpoint.tls_config(tls.create_config())?;
+        }
+        if let Some(timeout) = self.timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        if let Some(keep_alive) = self.keep_alive {
+            endpoint = endpoint.http2_keep_alive_interval(keep_alive).keep_alive_while_idle(true);
+        }
+        #[cfg(not(feature = "v4"))]
+        let new_channel = endpoint.connect_lazy()?;
+        #[cfg(feature = "v4")]
+        let new_channel = endpoint.connect_lazy();
+        *channel = Some(new_channel.clone());
+        Ok(new_channel)
+    }
+
+    /// Replaces the shared channel, after the options changed.
+    /// Clones, which were made before, keep using the old one.
+    fn reset_channel(&mut self) {
+        self.channel = Arc::new(Mutex::new(None));
+    }
+
+}
+
+/**
+    TLS options of the connection to the Chirpstack Application Server.
+*/
+#[derive(Clone)]
+pub struct ChirpstackTls {
+    /// The certificates of the certificate authorities, which are trusted, in PEM format.
+    ca_certificate: Option<String>,
+    /// The certificate and the private key of the client in PEM format.
+    identity: Option<(String, String)>,
+    /// The domain name, which is verified with the certificate of the server.
+    domain_name: Option<String>,
+}
+
+impl ChirpstackTls {
+
+    /// Creates new TLS options, which trust the certificate authorities of the system
+    /// and verify the host name of the uri.
+    pub fn new() -> Self {
+        return ChirpstackTls {
+            ca_certificate: None,
+            identity: None,
+            domain_name: None,
+        }
+    }
+
+    /// Reads the certificates of the certificate authorities, which are trusted, from a file in PEM format,
+    /// e.g. of a private certificate authority.
+    pub fn set_ca_file(&mut self, ca_file: &str) -> Result<(), Error> {
+        let ca_certificate = std::fs::read_to_string(ca_file)?;
+        self.ca_certificate = Some(ca_certificate);
+        Ok(())
+    }
+
+    /// Reads the certificate and the private key of the client from files in PEM format,
+    /// if the Chirpstack Application Server requires client certificates.
+    pub fn set_client_cert(&mut self, cert_file: &str, key_file: &str) -> Result<(), Error> {
+        let cert = std::fs::read_to_string(cert_file)?;
+        let key = std::fs::read_to_string(key_file)?;
+        self.identity = Some((cert, key));
+        Ok(())
+    }
+
+    /// Gets the domain name, which is verified with the certificate of the server;
+    /// `None` if the host name of the uri is verified.
+    pub fn get_domain_name(&self) -> Option<String> {
+        return self.domain_name.clone();
+    }
+
+    /// Sets the domain name, which is verified with the certificate of the server,
+    /// if it differs from the host name of the uri.
+    pub fn set_domain_name(&mut self, domain_name: &str) {
+        self.domain_name = Some(domain_name.to_string());
+    }
+
+    /// Creates the TLS configuration of the channel.
+    fn create_config(&self) -> ClientTlsConfig {
+        let mut config = ClientTlsConfig::new();
+        if let Some(ca_certificate) = &self.ca_certificate {
+            config = config.ca_certificate(Certificate::from_pem(ca_certificate));
+        }
+        if let Some((cert, key)) = &self.identity {
+            config = config.identity(Identity::from_pem(cert, key));
+        }
+        if let Some(domain_name) = &self.domain_name {
+            config = config.domain_name(domain_name);
+        }
+        return config;
+    }
 }
 
 /**
@@ -60,6 +225,22 @@ pub struct Mqtt {
     username: String,
     /// The password to access the broker.
     password: String,
+    /// The TLS options; `None` if the connection is not encrypted.
+    ssl: Option<MqttSsl>,
+    /// The id of the client, which has to be unique at the broker.
+    client_id: String,
+    /// Indicator if a random suffix is added to the client id.
+    random_suffix: bool,
+    /// The quality of service of the subscriptions.
+    qos: i32,
+    /// Indicator if the session is removed by the broker, when the client disconnects.
+    clean_session: bool,
+    /// The template of the topics of the events.
+    topic_template: TopicTemplate,
+    /// The types of the events, which are received by [`Mqtt::start_receiving_events`].
+    event_types: Vec<EventType>,
+    /// The encoding of the events.
+    marshaler: Marshaler,
 }
 
 impl Mqtt {
@@ -73,6 +254,14 @@ impl Mqtt {
             uri: uri.to_string(),
             username: username.to_string(),
             password: password.to_string(),
+            ssl: None,
+            client_id: "elorapi".to_string(),
+            random_suffix: false,
+            qos: 0,
+            clean_session: true,
+            topic_template: TopicTemplate::default(),
+            event_types: vec![EventType::Up],
+            marshaler: Marshaler::Json,
         }
     }
 
@@ -98,62 +287,322 @@ impl Mqtt {
         self.password = password.to_string();
     }
 
+    /// Gets the TLS options; `None` if the connection is not encrypted.
+    pub fn get_ssl(&self) -> Option<&MqttSsl> {
+        return self.ssl.as_ref();
+    }
+
+    /// Sets the TLS options, so that the connection is encrypted.<br/>
+    /// The uri has to use the scheme `ssl`, e.g. `ssl://localhost:8883`.
+    /// For an example see this [link](./index.html#tls).
+    pub fn set_ssl(&mut self, ssl: MqttSsl) {
+        self.ssl = Some(ssl);
+    }
+
+    /// Removes the TLS options, so that the connection is not encrypted.
+    pub fn remove_ssl(&mut self) {
+        self.ssl = None;
+    }
+
+    /// Gets the id of the client.
+    pub fn get_client_id(&self) -> String {
+        return self.client_id.clone();
+    }
+
+    /// Sets the id of the client, which has to be unique at the broker,
+    /// otherwise two clients with the same id disconnect each other.
+    /// The default is `elorapi`.<br/>
+    /// If `random_suffix` is true, a random suffix is added to the id, which is picked once when a client is created.
+    /// A reconnection of the client keeps the id, but every client, e.g. of a new receiver, gets another suffix,
+    /// therefore this should not be used with persistent sessions.
+    /// For an example see this [link](./index.html#client-id-quality-of-service-and-topics).
+    pub fn set_client_id(&mut self, client_id: &str, random_suffix: bool) {
+        self.client_id = client_id.to_string();
+        self.random_suffix = random_suffix;
+    }
 
-    /// Connects to the mqtt broker and publishes all messages to a `tokio::sync::watch` channel
+    /// Gets the quality of service of the subscriptions.
+    pub fn get_qos(&self) -> i32 {
+        return self.qos;
+    }
+
+    /// Sets the quality of service of the subscriptions, which is 0, 1 or 2.
+    /// The default is 0.
+    pub fn set_qos(&mut self, qos: i32) -> Result<(), Error> {
+        if !(0..=2).contains(&qos) {
+            return Err(Error::new(ErrorKind::InvalidInput, "The quality of service has to be 0, 1 or 2"));
+        }
+        self.qos = qos;
+        Ok(())
+    }
+
+    /// Checks if the session is removed by the broker, when the client disconnects.
+    pub fn is_clean_session(&self) -> bool {
+        return self.clean_session;
+    }
+
+    /// Sets if the session is removed by the broker, when the client disconnects.
+    /// The default is true.<br/>
+    /// With a persistent session and a quality of service of at least 1,
+    /// the broker keeps the messages, which arrive while the client is disconnected.
+    pub fn set_clean_session(&mut self, clean_session: bool) {
+        self.clean_session = clean_session;
+    }
+
+    /// Gets the template of the topics of the events.
+    pub fn get_topic_template(&self) -> &TopicTemplate {
+        return &self.topic_template;
+    }
+
+    /// Sets the template of the topics of the events,
+    /// if the Mqtt integration of the Chirpstack Application Server is not configured with the default topics.
+    pub fn set_topic_template(&mut self, topic_template: TopicTemplate) {
+        self.topic_template = topic_template;
+    }
+
+    /// Gets the types of the events, which are received by [`Mqtt::start_receiving_events`].
+    pub fn get_event_types(&self) -> &[EventType] {
+        return &self.event_types;
+    }
+
+    /// Sets the types of the events, which are received by [`Mqtt::start_receiving_events`].
+    /// The default are only uplinks.
+    pub fn set_event_types(&mut self, event_types: Vec<EventType>) {
+        self.event_types = event_types;
+    }
+
+    /// Gets the encoding of the events.
+    pub fn get_marshaler(&self) -> Marshaler {
+        return self.marshaler;
+    }
+
+    /// Sets the encoding of the events, which is configured for the Mqtt integration of the Chirpstack Application Server.
+    /// The default is JSON.<br/>
+    /// Events encoded with protobuf are converted to JSON, before they are handed to the rules.
+    /// For an example see this [link](./index.html#protobuf-events).
+    pub fn set_marshaler(&mut self, marshaler: Marshaler) {
+        self.marshaler = marshaler;
+    }
+
+
+    /// Connects to the mqtt broker and publishes all messages to a `tokio::sync::watch` channel.<br/>
+    /// The topics of the messages are always in the default format of the Chirpstack Application Server,
+    /// also if a different [`TopicTemplate`] is used.
+    /// To observe the connection or to stop receiving, use [`Mqtt::start`].
     pub fn start_receiving(&self) -> Result<Receiver<Message>, Error> {
-        let res = self.create_client();
+        let handle = self.start()?;
+        Ok(handle.get_receiver())
+    }
+
+    /// Connects to the mqtt broker and publishes all messages to a `tokio::sync::watch` channel,
+    /// like [`Mqtt::start_receiving`].<br/>
+    /// If the connection is lost, it is reconnected with exponential backoff and the topics are subscribed again.
+    /// The returned handle gives access to the messages and the state of the connection, and stops receiving.
+    /// For an example see this [link](./index.html#reconnection-and-shutdown).
+    pub fn start(&self) -> Result<MqttHandle, Error> {
+        let res = self.create_client(None);
         let client = match res {
             Ok(client) => client,
             Err(e) => return Err(Error::new(ErrorKind::Other, "There was a problem with the creation of the mqtt client: ".to_owned() + &e.to_string())),
         };
-        let mut topics = Vec::new();
-        let topic = "application/+/device/+/event/up";
-        topics.push(topic);
-        let result = client.subscribe_many(topics.as_slice(), &[0]);
+        let topics = vec![self.topic_template.subscription("up")];
+        let qos = vec![self.qos];
+        let (tx, rx) = watch::channel(Message::new("", "", 0));
+        let topic_template = self.topic_template.clone();
+        let marshaler = self.marshaler;
+        let (state, stop, thread) = Mqtt::consume(client, topics, qos, move |message| {
+            println!("Message: {}", message.topic());
+            let message = match marshaler.to_json(topic_template.normalize(message)) {
+                Ok(message) => message,
+                Err(e) => {
+                    println!("Decoding error: {}", e.to_string());
+                    return true;
+                },
+            };
+            if let Err(e) = tx.send(message) {
+                println!("Sending error: {}", e.to_string());
+            }
+            true
+        })?;
+        Ok(MqttHandle {
+            receiver: rx,
+            state,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Connects to the mqtt broker with an own client and sends all acknowledgements of confirmed downlinks
+    /// to a `std::sync::mpsc` channel, so that none is lost.<br/>
+    /// The receiver is used by an [`AckTracker`](crate::rules::AckTracker).
+    /// Receiving is stopped, when the receiver is dropped.
+    pub fn start_receiving_acks(&self) -> Result<mpsc::Receiver<Message>, Error> {
+        let res = self.create_client(Some("ack"));
+        let client = match res {
+            Ok(client) => client,
+            Err(e) => return Err(Error::new(ErrorKind::Other, "There was a problem with the creation of the mqtt client: ".to_owned() + &e.to_string())),
+        };
+        let topics = vec![self.topic_template.subscription("ack")];
+        // acknowledgements should not be lost, therefore the quality of service is at least 1
+        let qos = vec![self.qos.max(1)];
+        let (tx, rx) = mpsc::channel();
+        let topic_template = self.topic_template.clone();
+        let marshaler = self.marshaler;
+        Mqtt::consume(client, topics, qos, move |message| {
+            match marshaler.to_json(topic_template.normalize(message)) {
+                // the receiver was dropped, if the message can not be sent
+                Ok(message) => tx.send(message).is_ok(),
+                Err(e) => {
+                    println!("Decoding error: {}", e.to_string());
+                    true
+                },
+            }
+        })?;
+        Ok(rx)
+    }
+
+    /// Connects to the mqtt broker with an own client, subscribes to all chosen [event types](Mqtt::set_event_types)
+    /// and sends the parsed events to a `std::sync::mpsc` channel.<br/>
+    /// Messages which can not be parsed are skipped.
+    /// Receiving is stopped, when the receiver is dropped.
+    /// For an example see this [link](./index.html#receiving-events).
+    pub fn start_receiving_events(&self) -> Result<mpsc::Receiver<Event>, Error> {
+        if self.event_types.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "At least one event type has to be chosen!"));
+        }
+        let res = self.create_client(Some("events"));
+        let client = match res {
+            Ok(client) => client,
+            Err(e) => return Err(Error::new(ErrorKind::Other, "There was a problem with the creation of the mqtt client: ".to_owned() + &e.to_string())),
+        };
+        let topics: Vec<String> = self.event_types.iter().map(|event_type| self.topic_template.subscription(event_type.get_name())).collect();
+        let qos = vec![self.qos; topics.len()];
+        let (tx, rx) = mpsc::channel();
+        let topic_template = self.topic_template.clone();
+        let marshaler = self.marshaler;
+        Mqtt::consume(client, topics, qos, move |message| {
+            match Event::decode(&topic_template.normalize(message), marshaler) {
+                // the receiver was dropped, if the event can not be sent
+                Ok(event) => tx.send(event).is_ok(),
+                Err(e) => {
+                    println!("Event error: {}", e.to_string());
+                    true
+                },
+            }
+        })?;
+        Ok(rx)
+    }
+
+    /// Subscribes the `client` to the `topics` with the quality of service `qos`
+    /// and hands all messages to `deliver` in a new thread, until `deliver` returns false or receiving is stopped.<br/>
+    /// If the connection is lost, it is reconnected with exponential backoff, starting with 1 sec up to 1 min,
+    /// and the topics are subscribed again.<br/>
+    /// Returns the receiver of the state of the connection, the indicator to stop and the thread.
+    fn consume<F>(client: Client, topics: Vec<String>, qos: Vec<i32>, mut deliver: F) -> Result<(Receiver<ConnectionState>, Arc<AtomicBool>, JoinHandle<()>), Error>
+        where F: FnMut(Message) -> bool + Send + 'static {
+        // the consumer has to be started before subscribing, so that no message is lost
+        let consumer = client.start_consuming();
+        let result = client.subscribe_many(topics.as_slice(), qos.as_slice());
         if let Err(e) = result {
             return Err(Error::new(ErrorKind::Other, "There was a problem with the subscription to mqtt: ".to_owned() + &e.to_string()));
         }
-        let (tx, rx) = watch::channel(Message::new("", "", 0));
+        let (state_tx, state_rx) = watch::channel(ConnectionState::Connected);
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
 
-        thread::spawn(move|| {
-            'mqtt_loop: loop {
-                if !client.is_connected() {
-                    let res = client.reconnect().unwrap();
-                    println!("{:?}", res.reason_code().to_string());
-                }
-
-                let receiver = client.start_consuming();
-                let rec = receiver.recv();
-                println!("Message received!");
+        let thread = thread::spawn(move|| {
+            'mqtt_loop: while !thread_stop.load(Ordering::Relaxed) {
+                // the timeout is needed to check regularly, if receiving is stopped
+                let rec = consumer.recv_timeout(Duration::from_secs(1));
                 let message = match rec {
-                    Ok(mes) => mes,
+                    Ok(Some(mes)) => mes,
+                    // the connection was lost
+                    Ok(None) => {
+                        let _ = state_tx.send(ConnectionState::Disconnected);
+                        let mut backoff = Duration::from_secs(1);
+                        let mut attempt = 1;
+                        loop {
+                            let _ = state_tx.send(ConnectionState::Reconnecting(attempt));
+                            println!("Reconnecting to mqtt, attempt {}...", attempt);
+                            let res = client.reconnect()
+                                .and_then(|_| client.subscribe_many(topics.as_slice(), qos.as_slice()));
+                            match res {
+                                Ok(_) => {
+                                    let _ = state_tx.send(ConnectionState::Connected);
+                                    continue 'mqtt_loop
+                                }
+                                Err(e) => println!("Reconnection error: {}", e.to_string()),
+                            }
+                            // wait in short steps, so that receiving can be stopped
+                            let until = Instant::now() + backoff;
+                            while Instant::now() < until {
+                                if thread_stop.load(Ordering::Relaxed) {
+                                    break 'mqtt_loop
+                                }
+                                thread::sleep(Duration::from_millis(100));
+                            }
+                            backoff = (backoff * 2).min(Duration::from_secs(60));
+                            attempt += 1;
+                        }
+                    }
                     Err(e) => {
+                        if e.is_timeout() {
+                            continue 'mqtt_loop
+                        }
                         println!("RecvError: {}", e.to_string());
-                        continue 'mqtt_loop
+                        break 'mqtt_loop
                     }
                 };
-                println!("Message: {}", message.clone().unwrap().topic());
-                if message.is_some() {
-                    if let Err(e) = tx.send(message.unwrap()) {
-                        println!("Sending error: {}", e.to_string());
-                        continue 'mqtt_loop
-                    }
+                if !deliver(message) {
+                    break 'mqtt_loop
                 }
             }
+            if client.is_connected() {
+                let _ = client.disconnect(None);
+            }
+            let _ = state_tx.send(ConnectionState::Stopped);
         });
 
-        Ok(rx)
+        Ok((state_rx, stop, thread))
+    }
+
+    /// Connects to the mqtt broker with an own client, which is used to publish messages.<br/>
+    /// The publisher can be cloned and is used by [`Action`](crate::rules::Action)s.
+    /// For an example see this [link](../rules/index.html#creating-mqtt-publish-actions).
+    pub fn create_publisher(&self) -> Result<MqttPublisher, Error> {
+        let res = self.create_client(Some("publisher"));
+        let client = match res {
+            Ok(client) => client,
+            Err(e) => return Err(Error::new(ErrorKind::Other, "There was a problem with the creation of the mqtt client: ".to_owned() + &e.to_string())),
+        };
+        Ok(MqttPublisher {
+            client: Arc::new(client),
+        })
     }
 
     /// Creates a new client and connects to the Mqtt Broker.
-    fn create_client(&self) -> Result<Client, Error> {
-        let option = CreateOptionsBuilder::new().server_uri(self.uri.clone()).client_id("elorapi").finalize();
+    /// The `purpose` is added to the client id, so that the clients of one mqtt do not disconnect each other.
+    fn create_client(&self, purpose: Option<&str>) -> Result<Client, Error> {
+        let mut client_id = self.client_id.clone();
+        if let Some(purpose) = purpose {
+            client_id = client_id + "-" + purpose;
+        }
+        if self.random_suffix {
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.subsec_nanos()).unwrap_or_default();
+            client_id = format!("{}-{:x}{:x}", client_id, std::process::id(), nanos);
+        }
+        let option = CreateOptionsBuilder::new().server_uri(self.uri.clone()).client_id(client_id).finalize();
         let client_resp = Client::new(option);
         let client = match client_resp {
             Ok(a) => a,
             Err(_) => return Err(Error::new(ErrorKind::NotConnected, "Something went wrong!")),
         };
-        let connection_option = ConnectOptionsBuilder::new().user_name(self.username.clone()).password(self.password.clone()).finalize();
+        let mut connection_option_builder = ConnectOptionsBuilder::new();
+        connection_option_builder.user_name(self.username.clone()).password(self.password.clone()).clean_session(self.clean_session);
+        if let Some(ssl) = &self.ssl {
+            connection_option_builder.ssl_options(ssl.create_options()?);
+        }
+        let connection_option = connection_option_builder.finalize();
         let conn_resp = client.connect(connection_option);
         let server_response = match conn_resp {
             Ok(a) => a,
@@ -164,4 +613,292 @@ impl Mqtt {
         }
         Ok(client)
     }
-}
\ No newline at end of file
+}
+
+/**
+    State of the connection to a Mqtt broker.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionState {
+    /// The client is connected and receives messages.
+    Connected,
+    /// The connection was lost.
+    Disconnected,
+    /// The client tries to reconnect, with the number of the attempt.
+    Reconnecting(u32),
+    /// Receiving is stopped.
+    Stopped,
+}
+
+/**
+    Handle of a running Mqtt connection, which is created with [`Mqtt::start`].
+*/
+pub struct MqttHandle {
+    /// The receiver of the messages.
+    receiver: Receiver<Message>,
+    /// The receiver of the state of the connection.
+    state: Receiver<ConnectionState>,
+    /// Indicator to stop receiving.
+    stop: Arc<AtomicBool>,
+    /// The thread which receives the messages.
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MqttHandle {
+
+    /// Gets a receiver of the messages, which is used by rules.
+    pub fn get_receiver(&self) -> Receiver<Message> {
+        return self.receiver.clone();
+    }
+
+    /// Gets a receiver of the state of the connection, which is changed with every connection loss and reconnection.
+    pub fn get_state_receiver(&self) -> Receiver<ConnectionState> {
+        return self.state.clone();
+    }
+
+    /// Gets the current state of the connection.
+    pub fn get_state(&self) -> ConnectionState {
+        return *self.state.borrow();
+    }
+
+    /// Stops receiving, disconnects from the broker and waits until the thread is finished.
+    pub fn shutdown(mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/**
+    This is to publish messages to a Mqtt broker.
+    It is created with [`Mqtt::create_publisher`].
+*/
+#[derive(Clone)]
+pub struct MqttPublisher {
+    /// The connected client, which is shared by all clones.
+    client: Arc<Client>,
+}
+
+impl MqttPublisher {
+
+    /// Creates a publisher with a client, which is not connected, to test the actions without a broker.
+    #[cfg(test)]
+    pub(crate) fn unconnected() -> Self {
+        let option = CreateOptionsBuilder::new().server_uri("tcp://localhost:1883").client_id("elorapi-test").finalize();
+        return MqttPublisher {
+            client: Arc::new(Client::new(option).unwrap()),
+        };
+    }
+
+    /// Publishes the `payload` to the `topic` with the quality of service `qos`.<br/>
+    /// If the connection to the broker was lost, it is reconnected first.
+    pub fn publish(&self, topic: &str, payload: &str, qos: i32) -> Result<(), Error> {
+        if !self.client.is_connected() {
+            if let Err(e) = self.client.reconnect() {
+                return Err(Error::new(ErrorKind::NotConnected, "There was a problem with the reconnection to mqtt: ".to_owned() + &e.to_string()));
+            }
+        }
+        let message = Message::new(topic, payload, qos);
+        if let Err(e) = self.client.publish(message) {
+            return Err(Error::new(ErrorKind::Other, "There was a problem with publishing to mqtt: ".to_owned() + &e.to_string()));
+        }
+        Ok(())
+    }
+}
+
+/**
+    Template of the topics of the events, which are published by the Mqtt integration of the Chirpstack Application Server.
+
+    The template contains the placeholders `{application_id}`, `{dev_eui}` and `{event}`,
+    e.g. the default template is `application/{application_id}/device/{dev_eui}/event/{event}`.
+*/
+#[derive(Clone, Debug)]
+pub struct TopicTemplate {
+    /// The template with placeholders.
+    template: String,
+    /// Regular expression, which matches the topics of the template.
+    regex: Regex,
+}
+
+impl TopicTemplate {
+
+    /// Creates a new template.<br/>
+    /// Returns an error if one of the placeholders is missing.
+    pub fn new(template: &str) -> Result<Self, Error> {
+        if !template.contains("{application_id}") || !template.contains("{dev_eui}") || !template.contains("{event}") {
+            return Err(Error::new(ErrorKind::InvalidInput, "The template has to contain {application_id}, {dev_eui} and {event}"));
+        }
+        let pattern = regex::escape(template)
+            .replace("\\{application_id\\}", "(?P<application_id>[^/]+)")
+            .replace("\\{dev_eui\\}", "(?P<dev_eui>[^/]+)")
+            .replace("\\{event\\}", "(?P<event>[^/]+)");
+        let regex = match Regex::new(&("^".to_owned() + &pattern + "$")) {
+            Ok(regex) => regex,
+            Err(e) => return Err(Error::new(ErrorKind::InvalidInput, e)),
+        };
+        return Ok(TopicTemplate {
+            template: template.to_string(),
+            regex,
+        })
+    }
+
+    /// Gets the template with placeholders.
+    pub fn get_template(&self) -> String {
+        return self.template.clone();
+    }
+
+    /// Gets the topic to subscribe to all devices for the `event`, e.g. `up`.
+    pub fn subscription(&self, event: &str) -> String {
+        return self.template
+            .replace("{application_id}", "+")
+            .replace("{dev_eui}", "+")
+            .replace("{event}", event);
+    }
+
+    /// Changes the topic of the `message` into the default format of the Chirpstack Application Server,
+    /// if it matches the template.
+    pub fn normalize(&self, message: Message) -> Message {
+        let topic = match self.regex.captures(message.topic()) {
+            Some(captures) => "application/".to_owned() + &captures["application_id"] + "/device/" + &captures["dev_eui"] + "/event/" + &captures["event"],
+            None => return message,
+        };
+        if topic == message.topic() {
+            return message;
+        }
+        return Message::new(topic, message.payload().to_vec(), message.qos());
+    }
+}
+
+impl Default for TopicTemplate {
+    fn default() -> Self {
+        return TopicTemplate::new("application/{application_id}/device/{dev_eui}/event/{event}").unwrap();
+    }
+}
+
+/**
+    TLS options of the connection to a Mqtt broker.
+*/
+#[derive(Clone)]
+pub struct MqttSsl {
+    /// The file with the certificates of the certificate authorities, which are trusted, in PEM format.
+    ca_file: Option<String>,
+    /// The file with the certificate of the client in PEM format.
+    client_cert_file: Option<String>,
+    /// The file with the private key of the client in PEM format.
+    client_key_file: Option<String>,
+    /// The password of the private key of the client.
+    client_key_password: Option<String>,
+    /// Indicator if the host name of the broker is verified with its certificate.
+    verify_hostname: bool,
+}
+
+impl MqttSsl {
+
+    /// Creates new TLS options, which trust the certificate authorities of the system
+    /// and verify the host name of the broker.
+    pub fn new() -> Self {
+        return MqttSsl {
+            ca_file: None,
+            client_cert_file: None,
+            client_key_file: None,
+            client_key_password: None,
+            verify_hostname: true,
+        }
+    }
+
+    /// Gets the file with the certificates of the certificate authorities, which are trusted.
+    pub fn get_ca_file(&self) -> Option<String> {
+        return self.ca_file.clone();
+    }
+
+    /// Sets the file with the certificates of the certificate authorities, which are trusted, in PEM format,
+    /// e.g. of a private certificate authority.
+    pub fn set_ca_file(&mut self, ca_file: &str) {
+        self.ca_file = Some(ca_file.to_string());
+    }
+
+    /// Gets the file with the certificate of the client.
+    pub fn get_client_cert_file(&self) -> Option<String> {
+        return self.client_cert_file.clone();
+    }
+
+    /// Gets the file with the private key of the client.
+    pub fn get_client_key_file(&self) -> Option<String> {
+        return self.client_key_file.clone();
+    }
+
+    /// Sets the files with the certificate and the private key of the client in PEM format,
+    /// if the broker requires client certificates.
+    /// The `password` is needed, if the private key is encrypted.
+    pub fn set_client_cert(&mut self, cert_file: &str, key_file: &str, password: Option<&str>) {
+        self.client_cert_file = Some(cert_file.to_string());
+        self.client_key_file = Some(key_file.to_string());
+        self.client_key_password = password.map(|password| password.to_string());
+    }
+
+    /// Checks if the host name of the broker is verified with its certificate.
+    pub fn is_verify_hostname(&self) -> bool {
+        return self.verify_hostname;
+    }
+
+    /// Sets if the host name of the broker is verified with its certificate.
+    pub fn set_verify_hostname(&mut self, verify_hostname: bool) {
+        self.verify_hostname = verify_hostname;
+    }
+
+    /// Creates the options for the mqtt client.
+    fn create_options(&self) -> Result<SslOptions, Error> {
+        let mut builder = SslOptionsBuilder::new();
+        if let Some(ca_file) = &self.ca_file {
+            if let Err(e) = builder.trust_store(ca_file) {
+                return Err(Error::new(ErrorKind::InvalidInput, "There was a problem with the ca file: ".to_owned() + &e.to_string()));
+            }
+        }
+        if let Some(client_cert_file) = &self.client_cert_file {
+            if let Err(e) = builder.key_store(client_cert_file) {
+                return Err(Error::new(ErrorKind::InvalidInput, "There was a problem with the client certificate: ".to_owned() + &e.to_string()));
+            }
+        }
+        if let Some(client_key_file) = &self.client_key_file {
+            if let Err(e) = builder.private_key(client_key_file) {
+                return Err(Error::new(ErrorKind::InvalidInput, "There was a problem with the client key: ".to_owned() + &e.to_string()));
+            }
+        }
+        if let Some(client_key_password) = &self.client_key_password {
+            builder.private_key_password(client_key_password);
+        }
+        builder.verify(self.verify_hostname);
+        Ok(builder.finalize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topic_template() {
+        assert_eq!(TopicTemplate::new("site-a/{dev_eui}/{event}").unwrap_err().kind(), ErrorKind::InvalidInput);
+
+        let template = TopicTemplate::new("site-a/{application_id}/{dev_eui}/{event}").unwrap();
+        assert_eq!(template.subscription("up"), "site-a/+/+/up");
+
+        // a topic of the template is changed into the default topic
+        let message = template.normalize(Message::new("site-a/1/0102030405060708/up", "{}", 1));
+        assert_eq!(message.topic(), "application/1/device/0102030405060708/event/up");
+        assert_eq!(message.payload_str(), "{}");
+        assert_eq!(message.qos(), 1);
+
+        // other topics are not changed
+        let message = template.normalize(Message::new("site-b/1/0102030405060708/up", "{}", 0));
+        assert_eq!(message.topic(), "site-b/1/0102030405060708/up");
+        let message = template.normalize(Message::new("site-a/1/0102030405060708/event/up", "{}", 0));
+        assert_eq!(message.topic(), "site-a/1/0102030405060708/event/up");
+
+        let template = TopicTemplate::default();
+        assert_eq!(template.subscription("ack"), "application/+/device/+/event/ack");
+        let message = template.normalize(Message::new("application/1/device/0102030405060708/event/up", "{}", 0));
+        assert_eq!(message.topic(), "application/1/device/0102030405060708/event/up");
+    }
+}
@@ -1,12 +1,25 @@
 use std::borrow::BorrowMut;
+#[cfg(not(feature = "v4"))]
 use std::collections::HashMap;
 use std::io;
 use std::io::{ErrorKind};
+#[cfg(not(feature = "v4"))]
 use prost_types::Duration;
 use serde_derive::Deserialize;
+#[cfg(not(feature = "v4"))]
 use chirpstack_api::as_pb::external::api::{device_profile_service_client::DeviceProfileServiceClient, device_service_client::DeviceServiceClient};
+#[cfg(not(feature = "v4"))]
 use chirpstack_api::as_pb::external::api::{DeviceProfileListItem, ListDeviceProfileRequest, GetDeviceProfileRequest, CreateDeviceProfileRequest, GetDeviceResponse, DeviceListItem, ListDeviceRequest, GetDeviceRequest};
+#[cfg(not(feature = "v4"))]
 use chirpstack_api::as_pb::external::api::DeviceProfile as ChirpstackDeviceProfile;
+#[cfg(feature = "v4")]
+use chirpstack_api::api::{device_profile_service_client::DeviceProfileServiceClient, device_service_client::DeviceServiceClient};
+#[cfg(feature = "v4")]
+use chirpstack_api::api::{DeviceProfileListItem, ListDeviceProfilesRequest, GetDeviceProfileRequest, CreateDeviceProfileRequest, GetDeviceResponse, DeviceListItem, ListDevicesRequest, GetDeviceRequest, CodecRuntime};
+#[cfg(feature = "v4")]
+use chirpstack_api::api::DeviceProfile as ChirpstackDeviceProfile;
+#[cfg(feature = "v4")]
+use chirpstack_api::common::{MacVersion, Region, RegParamsRevision};
 use serde_json::Value;
 use tonic::transport::{Error};
 use tonic::transport::channel::Channel;
@@ -64,7 +77,7 @@ impl DeviceProfileContainer {
     /// Establishes a new connection to the Chirpstack Server to manage device profiles.
     /// Adds resulting client to the container.
     pub async fn establish_connection(&mut self, connection: ChirpstackConnection) -> Result<(), Error> {
-        let my_client = DeviceProfileServiceClient::connect(connection.get_uri()).await?;
+        let my_client = DeviceProfileServiceClient::new(connection.get_channel()?);
         self.client = Option::from(my_client);
         Ok(())
     }
@@ -73,6 +86,7 @@ impl DeviceProfileContainer {
     /// for a specific organization and application, and adds them to the container.
     /// For an example go to the [_loading existing device profile_](./index.html#loading-existing-device-profile)
     /// paragraph's second pullet point.
+    #[cfg(not(feature = "v4"))]
     pub async fn load_chirpstack_device_profiles(&mut self, limit:i64, organization_id: i64, application_id: i64, connection: ChirpstackConnection) -> Result<(), io::Error> {
         if self.client.is_none() {
             return Err(io::Error::new(ErrorKind::NotFound, "No client where found!"));
@@ -103,6 +117,41 @@ impl DeviceProfileContainer {
         Ok(())
     }
 
+    /// Loads a specific number of Chirpstack [`DeviceProfileListItem`](https://docs.rs/chirpstack_api/4.0.0/chirpstack_api/api/struct.DeviceProfileListItem.html)s
+    /// of a specific tenant, and adds them to the container.
+    /// For an example go to the [_loading existing device profile_](./index.html#loading-existing-device-profile)
+    /// paragraph's second pullet point.
+    #[cfg(feature = "v4")]
+    pub async fn load_chirpstack_device_profiles(&mut self, limit: u32, tenant_id: &str, connection: ChirpstackConnection) -> Result<(), io::Error> {
+        if self.client.is_none() {
+            return Err(io::Error::new(ErrorKind::NotFound, "No client where found!"));
+        }
+        let list_request = ListDeviceProfilesRequest {
+            limit,
+            offset: 0,
+            search: "".to_string(),
+            tenant_id: tenant_id.to_string(),
+        };
+
+        let mut request = Request::new(list_request);
+        let token = connection.get_api_token().parse::<MetadataValue<_>>();
+        let token:MetadataValue<_> = match token {
+            Ok(t) => t,
+            Err(e) => {return Err(io::Error::new(ErrorKind::InvalidData, e))}
+        };
+        request.metadata_mut().insert("authorization", token.clone());
+
+        let response= self.client.as_mut().unwrap().list(request).await;
+        let response = match response {
+            Ok(r) => r,
+            Err(e) => {return Err(io::Error::new(ErrorKind::InvalidData, e))}
+        };
+        for i in &response.get_ref().result {
+            self.chirpstack_device_profiles.push(i.clone());
+        }
+        Ok(())
+    }
+
     /// Gets all Chirpstack [`DeviceProfileListItem`](https://docs.rs/chirpstack_api/3.11.1/chirpstack_api/as_pb/external/api/struct.DeviceProfileListItem.html)s.
     /// Sometimes also referred to as _chirpstack device profile_ or the representation of these.
     pub fn get_chirpstack_device_profiles(&self) -> Vec<DeviceProfileListItem> {
@@ -161,6 +210,48 @@ impl DeviceProfile {
         };
     }
 
+    /// Gets the id of the respective device profile in Chirpstack.
+    pub fn get_id(&self) -> String {
+        return self.id.clone();
+    }
+
+    /// Gets the region of the loaded Chirpstack device profile, e.g. `EU868`.
+    pub fn get_region(&self) -> Option<String> {
+        let dev_prof = self.dev_prof.as_ref()?;
+        #[cfg(not(feature = "v4"))]
+        return Some(dev_prof.rf_region.clone());
+        #[cfg(feature = "v4")]
+        return Region::from_i32(dev_prof.region).map(|region| format!("{:?}", region).to_uppercase());
+    }
+
+    /// Gets the data rate of the RX2 receive window of the loaded Chirpstack device profile.
+    pub fn get_rx2_data_rate(&self) -> Option<u32> {
+        let dev_prof = self.dev_prof.as_ref()?;
+        #[cfg(not(feature = "v4"))]
+        return Some(dev_prof.rx_datarate_2);
+        #[cfg(feature = "v4")]
+        return Some(dev_prof.abp_rx2_dr);
+    }
+
+    /// Checks if the loaded Chirpstack device profile has a payload codec, which can encode downlinks;
+    /// `None` if no Chirpstack device profile is loaded.
+    pub fn has_payload_encoder(&self) -> Option<bool> {
+        let dev_prof = self.dev_prof.as_ref()?;
+        #[cfg(not(feature = "v4"))]
+        let has_encoder = match dev_prof.payload_codec.as_str() {
+            "" | "NONE" => false,
+            "CUSTOM_JS" => !dev_prof.payload_encoder_script.is_empty(),
+            _ => true,
+        };
+        #[cfg(feature = "v4")]
+        let has_encoder = match CodecRuntime::from_i32(dev_prof.payload_codec_runtime) {
+            None | Some(CodecRuntime::None) => false,
+            Some(CodecRuntime::Js) => !dev_prof.payload_codec_script.is_empty(),
+            _ => true,
+        };
+        return Some(has_encoder);
+    }
+
     /// Loads respective Chirpstack device profile via the device profile id
     /// and creates a new `DeviceProfile` without [`Downlink`] or [`Uplink`].
     pub async fn load_device_profile(device_profile_id: &str, connection: ChirpstackConnection) -> Result<Self, io::Error> {
@@ -221,6 +312,7 @@ impl DeviceProfile {
     }
 
     /// Creates `DeviceProfile`, without `dev_prof` and `client`, out of a json file.
+    #[cfg(not(feature = "v4"))]
     pub fn read_specification(file: &str, network_server_id: i64, organization_id: i64) -> Result<DeviceProfile, io::Error> {
         if network_server_id < 1 {
             return Err(io::Error::new(ErrorKind::InvalidData, "Network server id must be greater than 0."));
@@ -230,31 +322,7 @@ impl DeviceProfile {
         }
         let file_text = std::fs::read_to_string(&file)?;
         let device_profile = serde_json::from_str::<Value>(&file_text)?;
-
-        let mut uplink_payloads: Vec<String> = Vec::new();
-        let up_payloads = device_profile["uplink"]["payloads"].as_array().ok_or(io::Error::new(ErrorKind::Other, "Error while reading uplink payloads"))?;
-        let up_length = up_payloads.len();
-        for j in 0..up_length {
-            uplink_payloads.push(up_payloads[j].as_str().ok_or(io::Error::new(ErrorKind::Other, "Problem with uplink payload"))?.to_string());
-        }
-        let uplink: Uplink = Uplink::new(uplink_payloads);
-
-        let mut downlink_payloads: Vec<DownlinkPayload> = vec![];
-        let payloads = device_profile["downlink"]["payloads"].as_array().ok_or(io::Error::new(ErrorKind::Other, "Error while reading downlink payloads"))?;
-        let length = payloads.len();
-        let mut i = 0;
-        while i < length {
-            let down_pay = &device_profile["downlink"]["payloads"][i];
-            let command_name: String = down_pay["command_name"].as_str().ok_or(io::Error::new(ErrorKind::Other, "Problem with command name"))?.to_string();
-            let description: String = down_pay["description"].as_str().ok_or(io::Error::new(ErrorKind::Other, "Problem with description"))?.to_string();
-            let configurable: bool = down_pay["configurable"].as_bool().ok_or(io::Error::new(ErrorKind::Other, "No configurable field"))?;
-            let hex_code: String = down_pay["hex_code"].as_str().ok_or(io::Error::new(ErrorKind::Other, "Problem with hex_code"))?.to_string();
-            downlink_payloads.push(DownlinkPayload::new(&command_name, &description, configurable, &hex_code));
-            i += 1;
-        }
-        let hex_pre_bytes = device_profile["downlink"]["hex_pre_byte"].as_str().ok_or(io::Error::new(ErrorKind::Other, "Problem with hex_pre_bytes"))?.to_string();
-        let combined_work_load_count = device_profile["downlink"]["combined_work_load_count"].as_bool().ok_or(io::Error::new(ErrorKind::Other, "No combined_workload_count field"))?;
-        let downlink = Downlink::new(&hex_pre_bytes, combined_work_load_count, downlink_payloads);
+        let (uplink, downlink) = DeviceProfile::read_links(&device_profile)?;
 
         let mut factory_preset_freqs_vec: Vec<u32> = vec![];
         let pres_freq_vec = device_profile["device_profile"]["factory_preset_freqs"].as_array().ok_or(io::Error::new(ErrorKind::Other, "No factory_preset_freqs field"))?;
@@ -314,9 +382,124 @@ impl DeviceProfile {
         Ok(final_device_profile)
     }
 
+    /// Creates `DeviceProfile`, without `dev_prof` and `client`, out of a json file for the tenant with `tenant_id`.<br/>
+    /// The fields of the device profile specification file are mapped to the device profile of Chirpstack version 4,
+    /// e.g. `rf_region` to `region` and `rx_datarate_2` to `abp_rx2_dr`.<br/>
+    /// The codec script is read from the field `payload_codec_script`.
+    /// Returns an error of kind `InvalidData`, if the file contains a `payload_decoder_script` or `payload_encoder_script`
+    /// of Chirpstack version 3, because they are not compatible with version 4,
+    /// or if the `payload_codec` is `CUSTOM_JS` without a `payload_codec_script`.
+    #[cfg(feature = "v4")]
+    pub fn read_specification(file: &str, tenant_id: &str) -> Result<DeviceProfile, io::Error> {
+        if tenant_id.is_empty() {
+            return Err(io::Error::new(ErrorKind::InvalidData, "Tenant id must not be empty."));
+        }
+        let file_text = std::fs::read_to_string(&file)?;
+        let device_profile = serde_json::from_str::<Value>(&file_text)?;
+        let (uplink, downlink) = DeviceProfile::read_links(&device_profile)?;
+
+        let spec = &device_profile["device_profile"];
+        let read_u32 = |field: &str| -> Result<u32, io::Error> {
+            Ok(spec[field].as_u64().ok_or(io::Error::new(ErrorKind::Other, "Problem with ".to_owned() + field))? as u32)
+        };
+        let read_bool = |field: &str| -> Result<bool, io::Error> {
+            spec[field].as_bool().ok_or(io::Error::new(ErrorKind::Other, "Problem with ".to_owned() + field))
+        };
+        let read_str = |field: &str| -> Result<String, io::Error> {
+            Ok(spec[field].as_str().ok_or(io::Error::new(ErrorKind::Other, "Problem with ".to_owned() + field))?.to_string())
+        };
+
+        let region = DeviceProfile::enum_value(&read_str("rf_region")?, Region::from_i32).ok_or(io::Error::new(ErrorKind::InvalidData, "Unknown rf_region"))?;
+        // the mac versions are named like LORAWAN_1_0_3 in Chirpstack version 4
+        let mac_version = DeviceProfile::enum_value(&("LORAWAN".to_owned() + &read_str("mac_version")?), MacVersion::from_i32).ok_or(io::Error::new(ErrorKind::InvalidData, "Unknown mac_version"))?;
+        let reg_params_revision = DeviceProfile::enum_value(&read_str("reg_params_revision")?, RegParamsRevision::from_i32).ok_or(io::Error::new(ErrorKind::InvalidData, "Unknown reg_params_revision"))?;
+        let payload_codec_runtime = match read_str("payload_codec")?.as_str() {
+            "" => CodecRuntime::None as i32,
+            "CUSTOM_JS" => CodecRuntime::Js as i32,
+            codec => DeviceProfile::enum_value(codec, CodecRuntime::from_i32).ok_or(io::Error::new(ErrorKind::InvalidData, "Unknown payload_codec"))?,
+        };
+        // the functions of the scripts of Chirpstack version 3 have other names and arguments,
+        // therefore version 4 needs its own script with the functions decodeUplink and encodeDownlink
+        let has_script = |field: &str| spec[field].as_str().map_or(false, |script| !script.is_empty());
+        if has_script("payload_decoder_script") || has_script("payload_encoder_script") {
+            return Err(io::Error::new(ErrorKind::InvalidData, "The payload_decoder_script and payload_encoder_script of Chirpstack version 3 are not supported, use payload_codec_script instead"));
+        }
+        let payload_codec_script = spec["payload_codec_script"].as_str().unwrap_or_default().to_string();
+        if payload_codec_runtime == CodecRuntime::Js as i32 && payload_codec_script.is_empty() {
+            return Err(io::Error::new(ErrorKind::InvalidData, "The payload_codec CUSTOM_JS needs a payload_codec_script"));
+        }
+
+        let device = ChirpstackDeviceProfile {
+            id: "".to_string(),
+            tenant_id: tenant_id.to_string(),
+            name: read_str("name")?,
+            region,
+            mac_version,
+            reg_params_revision,
+            adr_algorithm_id: read_str("adr_algorithm_id")?,
+            payload_codec_runtime,
+            payload_codec_script,
+            uplink_interval: read_u32("uplink_interval")?,
+            supports_otaa: read_bool("supports_join")?,
+            supports_class_b: read_bool("supports_class_b")?,
+            supports_class_c: read_bool("supports_class_c")?,
+            class_b_timeout: read_u32("class_b_timeout")?,
+            class_b_ping_slot_period: read_u32("ping_slot_period")?,
+            class_b_ping_slot_dr: read_u32("ping_slot_dr")?,
+            class_b_ping_slot_freq: read_u32("ping_slot_freq")?,
+            class_c_timeout: read_u32("class_c_timeout")?,
+            abp_rx1_delay: read_u32("rx_delay_1")?,
+            abp_rx1_dr_offset: read_u32("rx_dr_offset_1")?,
+            abp_rx2_dr: read_u32("rx_datarate_2")?,
+            abp_rx2_freq: read_u32("rx_freq_2")?,
+            ..Default::default()
+        };
+        let mut final_device_profile = DeviceProfile::new(&device.id, Option::from(uplink), Option::from(downlink));
+        final_device_profile.dev_prof = Option::from(device);
+        Ok(final_device_profile)
+    }
+
+    /// Reads the [`Uplink`] and [`Downlink`] out of a device profile specification.
+    fn read_links(device_profile: &Value) -> Result<(Uplink, Downlink), io::Error> {
+        let mut uplink_payloads: Vec<String> = Vec::new();
+        let up_payloads = device_profile["uplink"]["payloads"].as_array().ok_or(io::Error::new(ErrorKind::Other, "Error while reading uplink payloads"))?;
+        let up_length = up_payloads.len();
+        for j in 0..up_length {
+            uplink_payloads.push(up_payloads[j].as_str().ok_or(io::Error::new(ErrorKind::Other, "Problem with uplink payload"))?.to_string());
+        }
+        let uplink: Uplink = Uplink::new(uplink_payloads);
+
+        let mut downlink_payloads: Vec<DownlinkPayload> = vec![];
+        let payloads = device_profile["downlink"]["payloads"].as_array().ok_or(io::Error::new(ErrorKind::Other, "Error while reading downlink payloads"))?;
+        let length = payloads.len();
+        let mut i = 0;
+        while i < length {
+            let down_pay = &device_profile["downlink"]["payloads"][i];
+            let command_name: String = down_pay["command_name"].as_str().ok_or(io::Error::new(ErrorKind::Other, "Problem with command name"))?.to_string();
+            let description: String = down_pay["description"].as_str().ok_or(io::Error::new(ErrorKind::Other, "Problem with description"))?.to_string();
+            let configurable: bool = down_pay["configurable"].as_bool().ok_or(io::Error::new(ErrorKind::Other, "No configurable field"))?;
+            let hex_code: String = down_pay["hex_code"].as_str().ok_or(io::Error::new(ErrorKind::Other, "Problem with hex_code"))?.to_string();
+            downlink_payloads.push(DownlinkPayload::new(&command_name, &description, configurable, &hex_code));
+            i += 1;
+        }
+        let hex_pre_bytes = device_profile["downlink"]["hex_pre_byte"].as_str().ok_or(io::Error::new(ErrorKind::Other, "Problem with hex_pre_bytes"))?.to_string();
+        let combined_work_load_count = device_profile["downlink"]["combined_work_load_count"].as_bool().ok_or(io::Error::new(ErrorKind::Other, "No combined_workload_count field"))?;
+        let downlink = Downlink::new(&hex_pre_bytes, combined_work_load_count, downlink_payloads);
+
+        Ok((uplink, downlink))
+    }
+
+    /// Gets the value of a protobuf enum of Chirpstack version 4 by its `name`,
+    /// ignoring the case and all characters which are no letters or digits, e.g. `AS923-2` for `AS923_2`.
+    #[cfg(feature = "v4")]
+    fn enum_value<T: std::fmt::Debug>(name: &str, from_i32: fn(i32) -> Option<T>) -> Option<i32> {
+        let name = name.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_uppercase();
+        return (0..64).find(|i| from_i32(*i).map_or(false, |value| format!("{:?}", value).to_uppercase() == name));
+    }
+
     /// Establishes a new connection to the Chirpstack Server to manage device profiles.
     async fn establish_connection(connection: ChirpstackConnection) -> Result<Option<DeviceProfileServiceClient<Channel>>, Error> {
-        let my_client = DeviceProfileServiceClient::connect(connection.get_uri()).await?;
+        let my_client = DeviceProfileServiceClient::new(connection.get_channel()?);
         Ok(Option::from(my_client))
     }
 
@@ -342,11 +525,18 @@ impl DeviceProfile {
             return Err(io::Error::new(ErrorKind::InvalidData, "No device profile was found."));
         }
         let dev_prof = self.dev_prof.clone().unwrap();
-        if dev_prof.organization_id == 0 {
-            return Err(io::Error::new(ErrorKind::InvalidData, "No organization id were given."));
+        #[cfg(not(feature = "v4"))]
+        {
+            if dev_prof.organization_id == 0 {
+                return Err(io::Error::new(ErrorKind::InvalidData, "No organization id were given."));
+            }
+            if dev_prof.network_server_id == 0 {
+                return Err(io::Error::new(ErrorKind::InvalidData, "No network server id were given."));
+            }
         }
-        if dev_prof.network_server_id == 0 {
-            return Err(io::Error::new(ErrorKind::InvalidData, "No network server id were given."));
+        #[cfg(feature = "v4")]
+        if dev_prof.tenant_id.is_empty() {
+            return Err(io::Error::new(ErrorKind::InvalidData, "No tenant id were given."));
         }
 
         let client = self.client.as_mut().unwrap();
@@ -559,7 +749,7 @@ impl DeviceContainer {
 
     /// Gets a `Device` with a specific index.
     pub fn get_device(&self, index: usize) -> Result<Device, io::Error> {
-        if index > self.devices.len() {
+        if index >= self.devices.len() {
             return Err(io::Error::new(ErrorKind::InvalidData, "Index out of bounds!"));
         }
         return Ok(self.devices[index].clone());
@@ -579,7 +769,7 @@ impl DeviceContainer {
     /// Establishes a new connection to the Chirpstack Server to manage devices
     /// and adds the resulting client to the container.
     pub async fn establish_connection(&mut self, connection: ChirpstackConnection) -> Result<(), Error> {
-        let my_client = DeviceServiceClient::connect(connection.get_uri()).await?;
+        let my_client = DeviceServiceClient::new(connection.get_channel()?);
         self.client = Option::from(my_client);
         Ok(())
     }
@@ -587,6 +777,7 @@ impl DeviceContainer {
     /// Loads a specific number of Chirpstack [`DeviceListItem`](https://docs.rs/chirpstack_api/3.11.1/chirpstack_api/as_pb/external/api/struct.DeviceListItem.html)s
     /// for a specific organization and application.<br/>
     /// For an example go to [_loading a device_](./index.html#loading-a-device) paragraph.
+    #[cfg(not(feature = "v4"))]
     pub async fn load_chirpstack_device_list(&mut self, limit:i64, application_id: i64, connection: ChirpstackConnection) -> Result<(), io::Error> {
         if self.client.is_none() {
             return Err(io::Error::new(ErrorKind::NotFound, "No client where found!"));
@@ -620,6 +811,41 @@ impl DeviceContainer {
         Ok(())
     }
 
+    /// Loads a specific number of Chirpstack [`DeviceListItem`](https://docs.rs/chirpstack_api/4.0.0/chirpstack_api/api/struct.DeviceListItem.html)s
+    /// of a specific application.<br/>
+    /// For an example go to [_loading a device_](./index.html#loading-a-device) paragraph.
+    #[cfg(feature = "v4")]
+    pub async fn load_chirpstack_device_list(&mut self, limit: u32, application_id: &str, connection: ChirpstackConnection) -> Result<(), io::Error> {
+        if self.client.is_none() {
+            return Err(io::Error::new(ErrorKind::NotFound, "No client where found!"));
+        }
+        let list_request = ListDevicesRequest {
+            limit,
+            offset: 0,
+            search: "".to_string(),
+            application_id: application_id.to_string(),
+            multicast_group_id: "".to_string(),
+        };
+
+        let mut request = Request::new(list_request);
+        let token = connection.get_api_token().parse::<MetadataValue<_>>();
+        let token:MetadataValue<_> = match token {
+            Ok(t) => t,
+            Err(e) => {return Err(io::Error::new(ErrorKind::InvalidData, e))}
+        };
+        request.metadata_mut().insert("authorization", token.clone());
+
+        let response= self.client.as_mut().unwrap().list(request).await;
+        let response = match response {
+            Ok(r) => r,
+            Err(e) => {return Err(io::Error::new(ErrorKind::InvalidData, e))}
+        };
+        for i in &response.get_ref().result {
+            self.chirpstack_device_list.push(i.clone());
+        }
+        Ok(())
+    }
+
     /// Prints all Chirpstack [devices](https://docs.rs/chirpstack_api/3.11.1/chirpstack_api/as_pb/external/api/struct.DeviceListItem.html).
     pub fn print_list_items(&self) {
         println!("DeviceListItems:");
@@ -672,7 +898,7 @@ impl Device {
 
     /// Establishes a new connection to Chirpstack Server to manage devices.
     async fn establish_connection(connection: ChirpstackConnection) -> Result<Option<DeviceServiceClient<Channel>>, Error> {
-        let my_client = DeviceServiceClient::connect(connection.get_uri()).await?;
+        let my_client = DeviceServiceClient::new(connection.get_channel()?);
         Ok(Option::from(my_client))
     }
 
@@ -0,0 +1,1487 @@
+#[cfg(not(feature = "v4"))]
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+#[cfg(not(feature = "v4"))]
+use chirpstack_api::{as_pb::integration, common, gw};
+#[cfg(feature = "v4")]
+use chirpstack_api::{integration, gw};
+use chrono::{TimeZone, Utc};
+use paho_mqtt::Message;
+use prost::Message as ProtobufMessage;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+/**
+    Enum of the encodings of the events, which can be configured for the Mqtt integration of the Chirpstack Application Server.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Marshaler {
+    /// The events are encoded as JSON, which is the default.
+    Json,
+    /// The events are encoded with protobuf.
+    Protobuf,
+}
+
+impl Marshaler {
+    /// Converts a Mqtt `message`, which is encoded with this marshaler, to a message encoded as JSON.
+    /// The event type is given by the last part of the topic.<br/>
+    /// Messages encoded as JSON are returned unchanged.
+    pub fn to_json(&self, message: Message) -> Result<Message, io::Error> {
+        if *self == Marshaler::Json {
+            return Ok(message);
+        }
+        let event = Event::decode(&message, *self)?;
+        let payload = match &event {
+            Event::Up(event) => serde_json::to_string(event)?,
+            Event::Join(event) => serde_json::to_string(event)?,
+            Event::Ack(event) => serde_json::to_string(event)?,
+            Event::TxAck(event) => serde_json::to_string(event)?,
+            Event::Status(event) => serde_json::to_string(event)?,
+            Event::Error(event) => serde_json::to_string(event)?,
+            Event::Location(event) => serde_json::to_string(event)?,
+        };
+        return Ok(Message::new(message.topic(), payload, message.qos()));
+    }
+}
+
+/**
+    Enum of the event types, which are published by the Mqtt integration of the Chirpstack Application Server.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventType {
+    /// Uplink of a device.
+    Up,
+    /// Join of a device.
+    Join,
+    /// Acknowledgement of a confirmed downlink.
+    Ack,
+    /// Acknowledgement of the transmission of a downlink by a gateway.
+    TxAck,
+    /// Status of a device, e.g. its battery level.
+    Status,
+    /// Error, e.g. of the payload codec.
+    Error,
+    /// Location of a device, which is resolved by the geolocation server.
+    Location,
+}
+
+impl EventType {
+    /// Gets all event types.
+    pub fn all() -> Vec<EventType> {
+        return vec![EventType::Up, EventType::Join, EventType::Ack, EventType::TxAck, EventType::Status, EventType::Error, EventType::Location];
+    }
+
+    /// Gets the name of the event type, which is the last part of the topic.
+    pub fn get_name(&self) -> &'static str {
+        return match self {
+            EventType::Up => "up",
+            EventType::Join => "join",
+            EventType::Ack => "ack",
+            EventType::TxAck => "txack",
+            EventType::Status => "status",
+            #[cfg(not(feature = "v4"))]
+            EventType::Error => "error",
+            // errors are published as log events in Chirpstack version 4
+            #[cfg(feature = "v4")]
+            EventType::Error => "log",
+            EventType::Location => "location",
+        };
+    }
+
+    /// Gets the event type with the `name`; `None` if there is none.
+    pub fn from_name(name: &str) -> Option<EventType> {
+        return EventType::all().into_iter().find(|event_type| event_type.get_name() == name);
+    }
+}
+
+/**
+    Tagged event, which is published by the Mqtt integration of the Chirpstack Application Server.
+*/
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// Uplink of a device.
+    Up(UplinkEvent),
+    /// Join of a device.
+    Join(JoinEvent),
+    /// Acknowledgement of a confirmed downlink.
+    Ack(AckEvent),
+    /// Acknowledgement of the transmission of a downlink by a gateway.
+    TxAck(TxAckEvent),
+    /// Status of a device.
+    Status(StatusEvent),
+    /// Error, e.g. of the payload codec.
+    Error(ErrorEvent),
+    /// Location of a device.
+    Location(LocationEvent),
+}
+
+impl Event {
+    /// Parses the JSON payload of a Mqtt `message`.
+    /// The event type is given by the last part of the topic.<br/>
+    /// Returns an error if the event type is unknown or the payload does not match it.
+    pub fn parse(message: &Message) -> Result<Event, io::Error> {
+        let name = message.topic().rsplit('/').next().unwrap_or_default();
+        let event_type = match EventType::from_name(name) {
+            Some(event_type) => event_type,
+            None => return Err(io::Error::new(ErrorKind::InvalidData, "Unknown event type: ".to_owned() + name)),
+        };
+        let payload = message.payload_str();
+        let event = match event_type {
+            EventType::Up => Event::Up(serde_json::from_str(&payload)?),
+            EventType::Join => Event::Join(serde_json::from_str(&payload)?),
+            EventType::Ack => Event::Ack(serde_json::from_str(&payload)?),
+            EventType::TxAck => Event::TxAck(serde_json::from_str(&payload)?),
+            EventType::Status => Event::Status(serde_json::from_str(&payload)?),
+            EventType::Error => Event::Error(serde_json::from_str(&payload)?),
+            EventType::Location => Event::Location(serde_json::from_str(&payload)?),
+        };
+        return Ok(event);
+    }
+
+    /// Decodes the payload of a Mqtt `message`, which is encoded with the `marshaler`.
+    /// The event type is given by the last part of the topic.<br/>
+    /// Returns an error if the event type is unknown or the payload does not match it.
+    pub fn decode(message: &Message, marshaler: Marshaler) -> Result<Event, io::Error> {
+        if marshaler == Marshaler::Json {
+            return Event::parse(message);
+        }
+        let name = message.topic().rsplit('/').next().unwrap_or_default();
+        let event_type = match EventType::from_name(name) {
+            Some(event_type) => event_type,
+            None => return Err(io::Error::new(ErrorKind::InvalidData, "Unknown event type: ".to_owned() + name)),
+        };
+        let payload = message.payload();
+        let decode_error = |e: prost::DecodeError| io::Error::new(ErrorKind::InvalidData, "Event could not be decoded: ".to_owned() + &e.to_string());
+        let event = match event_type {
+            EventType::Up => Event::Up(UplinkEvent::from(integration::UplinkEvent::decode(payload).map_err(decode_error)?)),
+            EventType::Join => Event::Join(JoinEvent::from(integration::JoinEvent::decode(payload).map_err(decode_error)?)),
+            EventType::Ack => Event::Ack(AckEvent::from(integration::AckEvent::decode(payload).map_err(decode_error)?)),
+            EventType::TxAck => Event::TxAck(TxAckEvent::from(integration::TxAckEvent::decode(payload).map_err(decode_error)?)),
+            EventType::Status => Event::Status(StatusEvent::from(integration::StatusEvent::decode(payload).map_err(decode_error)?)),
+            #[cfg(not(feature = "v4"))]
+            EventType::Error => Event::Error(ErrorEvent::from(integration::ErrorEvent::decode(payload).map_err(decode_error)?)),
+            #[cfg(feature = "v4")]
+            EventType::Error => Event::Error(ErrorEvent::from(integration::LogEvent::decode(payload).map_err(decode_error)?)),
+            EventType::Location => Event::Location(LocationEvent::from(integration::LocationEvent::decode(payload).map_err(decode_error)?)),
+        };
+        return Ok(event);
+    }
+
+    /// Gets the type of the event.
+    pub fn get_type(&self) -> EventType {
+        return match self {
+            Event::Up(_) => EventType::Up,
+            Event::Join(_) => EventType::Join,
+            Event::Ack(_) => EventType::Ack,
+            Event::TxAck(_) => EventType::TxAck,
+            Event::Status(_) => EventType::Status,
+            Event::Error(_) => EventType::Error,
+            Event::Location(_) => EventType::Location,
+        };
+    }
+
+    /// Gets the device, which the event is about.
+    pub fn get_device(&self) -> &EventDevice {
+        return match self {
+            Event::Up(event) => &event.device,
+            Event::Join(event) => &event.device,
+            Event::Ack(event) => &event.device,
+            Event::TxAck(event) => &event.device,
+            Event::Status(event) => &event.device,
+            Event::Error(event) => &event.device,
+            Event::Location(event) => &event.device,
+        };
+    }
+}
+
+/**
+    The device of an [`Event`], which is part of every event.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct EventDevice {
+    /// Id of the application.
+    #[cfg_attr(not(feature = "v4"), serde(rename = "applicationID", default))]
+    #[cfg_attr(feature = "v4", serde(rename = "applicationId", default))]
+    application_id: String,
+    /// Name of the application.
+    #[serde(rename = "applicationName", default)]
+    application_name: String,
+    /// Name of the device.
+    #[serde(rename = "deviceName", default)]
+    device_name: String,
+    /// The dev_eui, encoded in base64 with Chirpstack version 3 and in hex with version 4;
+    /// `None` if the event has none.
+    #[cfg_attr(not(feature = "v4"), serde(rename = "devEUI", default))]
+    #[cfg_attr(feature = "v4", serde(rename = "devEui", default))]
+    dev_eui: Option<String>,
+    /// The tags of the device.
+    #[serde(default)]
+    tags: serde_json::Map<String, Value>,
+}
+
+impl EventDevice {
+    /// Gets the id of the application.
+    pub fn get_application_id(&self) -> String {
+        return self.application_id.clone();
+    }
+
+    /// Gets the name of the application.
+    pub fn get_application_name(&self) -> String {
+        return self.application_name.clone();
+    }
+
+    /// Gets the name of the device.
+    pub fn get_device_name(&self) -> String {
+        return self.device_name.clone();
+    }
+
+    /// Gets the dev_eui in hex; `None` if the event has none.
+    pub fn get_dev_eui(&self) -> Option<String> {
+        return self.dev_eui.as_ref().map(|dev_eui| id_to_hex(dev_eui, 8));
+    }
+
+    /// Gets the tags of the device.
+    pub fn get_tags(&self) -> &serde_json::Map<String, Value> {
+        return &self.tags;
+    }
+}
+
+/**
+    Information about the reception of an uplink by a gateway.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RxInfo {
+    /// The id of the gateway, encoded in base64 or hex.
+    #[cfg_attr(not(feature = "v4"), serde(rename = "gatewayID", default))]
+    #[cfg_attr(feature = "v4", serde(rename = "gatewayId", default))]
+    gateway_id: String,
+    /// The time at which the gateway received the uplink.
+    #[serde(default)]
+    time: Option<String>,
+    /// The received signal strength in dBm; `None` if the gateway did not report it.
+    #[serde(default)]
+    rssi: Option<i32>,
+    /// The signal to noise ratio in dB.
+    #[cfg_attr(not(feature = "v4"), serde(rename = "loRaSNR", default))]
+    #[cfg_attr(feature = "v4", serde(rename = "snr", default))]
+    lora_snr: f64,
+    /// The channel on which the uplink was received.
+    #[serde(default)]
+    channel: u32,
+}
+
+impl RxInfo {
+    /// Gets the id of the gateway in hex.
+    pub fn get_gateway_id(&self) -> String {
+        return id_to_hex(&self.gateway_id, 8);
+    }
+
+    /// Gets the time at which the gateway received the uplink.
+    pub fn get_time(&self) -> Option<String> {
+        return self.time.clone();
+    }
+
+    /// Gets the received signal strength in dBm; `None` if the gateway did not report it.
+    pub fn get_rssi(&self) -> Option<i32> {
+        return self.rssi;
+    }
+
+    /// Gets the signal to noise ratio in dB.
+    pub fn get_lora_snr(&self) -> f64 {
+        return self.lora_snr;
+    }
+
+    /// Gets the channel on which the uplink was received.
+    pub fn get_channel(&self) -> u32 {
+        return self.channel;
+    }
+}
+
+/**
+    Information about the transmission of an uplink or downlink.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TxInfo {
+    /// The frequency in Hz.
+    #[serde(default)]
+    frequency: u64,
+    /// The modulation, e.g. `LORA`.
+    #[cfg(not(feature = "v4"))]
+    #[serde(default)]
+    modulation: String,
+    /// The parameters of the LoRa modulation; `None` for other modulations.
+    #[cfg(not(feature = "v4"))]
+    #[serde(rename = "loRaModulationInfo", default)]
+    lora_modulation_info: Option<LoRaModulationInfo>,
+    /// The modulation with its parameters.
+    #[cfg(feature = "v4")]
+    #[serde(default)]
+    modulation: Option<Modulation>,
+}
+
+/// The modulation of a [`TxInfo`] with Chirpstack version 4.
+#[cfg(feature = "v4")]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Modulation {
+    /// The parameters of the LoRa modulation; `None` for other modulations.
+    #[serde(default)]
+    lora: Option<LoRaModulationInfo>,
+}
+
+impl TxInfo {
+    /// Gets the frequency in Hz.
+    pub fn get_frequency(&self) -> u64 {
+        return self.frequency;
+    }
+
+    /// Gets the modulation, e.g. `LORA`.
+    pub fn get_modulation(&self) -> String {
+        #[cfg(not(feature = "v4"))]
+        return self.modulation.clone();
+        #[cfg(feature = "v4")]
+        return match self.get_lora_modulation_info() {
+            Some(_) => "LORA".to_string(),
+            None => "".to_string(),
+        };
+    }
+
+    /// Gets the parameters of the LoRa modulation; `None` for other modulations.
+    pub fn get_lora_modulation_info(&self) -> Option<&LoRaModulationInfo> {
+        #[cfg(not(feature = "v4"))]
+        return self.lora_modulation_info.as_ref();
+        #[cfg(feature = "v4")]
+        return self.modulation.as_ref().and_then(|modulation| modulation.lora.as_ref());
+    }
+}
+
+/**
+    Parameters of the LoRa modulation.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LoRaModulationInfo {
+    /// The bandwidth in kHz with Chirpstack version 3 and in Hz with version 4.
+    #[serde(default)]
+    bandwidth: u32,
+    /// The spreading factor.
+    #[serde(rename = "spreadingFactor", default)]
+    spreading_factor: u32,
+    /// The code rate, e.g. `4/5` with Chirpstack version 3 and `CR_4_5` with version 4.
+    #[serde(rename = "codeRate", default)]
+    code_rate: String,
+}
+
+impl LoRaModulationInfo {
+    /// Gets the bandwidth in kHz.
+    pub fn get_bandwidth(&self) -> u32 {
+        #[cfg(not(feature = "v4"))]
+        return self.bandwidth;
+        #[cfg(feature = "v4")]
+        return self.bandwidth / 1000;
+    }
+
+    /// Gets the spreading factor.
+    pub fn get_spreading_factor(&self) -> u32 {
+        return self.spreading_factor;
+    }
+
+    /// Gets the code rate, e.g. `4/5` with Chirpstack version 3 and `CR_4_5` with version 4.
+    pub fn get_code_rate(&self) -> String {
+        return self.code_rate.clone();
+    }
+}
+
+/**
+    Uplink of a device.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UplinkEvent {
+    /// The device which sent the uplink.
+    #[cfg_attr(not(feature = "v4"), serde(flatten))]
+    #[cfg_attr(feature = "v4", serde(rename = "deviceInfo", default))]
+    device: EventDevice,
+    /// The gateways which received the uplink.
+    #[serde(rename = "rxInfo", default)]
+    rx_info: Vec<RxInfo>,
+    /// The transmission of the uplink.
+    #[serde(rename = "txInfo", default)]
+    tx_info: Option<TxInfo>,
+    /// The data rate.
+    #[serde(default)]
+    dr: u32,
+    /// The frame counter; `None` if the event has none.
+    #[cfg_attr(not(feature = "v4"), serde(rename = "fCnt", default))]
+    #[cfg_attr(feature = "v4", serde(rename = "fCnt", default = "zero"))]
+    f_cnt: Option<u32>,
+    /// The port.
+    #[serde(rename = "fPort", default)]
+    f_port: u32,
+    /// The payload encoded in base64; `None` if the uplink has no payload.
+    #[serde(default)]
+    data: Option<String>,
+    /// The payload decoded by the payload codec as JSON text; `None` if there is no codec.
+    #[cfg(not(feature = "v4"))]
+    #[serde(rename = "objectJSON", default)]
+    object_json: Option<String>,
+    /// The payload decoded by the payload codec; `None` if there is no codec.
+    #[cfg(feature = "v4")]
+    #[serde(default)]
+    object: Option<Value>,
+}
+
+impl UplinkEvent {
+    /// Parses the JSON `payload` of an uplink message.
+    pub fn parse(payload: &str) -> Result<UplinkEvent, io::Error> {
+        let result = serde_json::from_str::<UplinkEvent>(payload);
+        return match result {
+            Ok(event) => Ok(event),
+            Err(e) => Err(io::Error::new(ErrorKind::InvalidData, "Uplink could not be parsed: ".to_owned() + &e.to_string())),
+        };
+    }
+
+    /// Checks if the uplink has a payload.
+    pub fn has_data(&self) -> bool {
+        return self.data.as_ref().map_or(false, |data| !data.is_empty());
+    }
+
+    /// Gets the payload decoded by the payload codec.<br/>
+    /// Returns an error of kind `NotFound`, if there is none, and of kind `InvalidData`, if it is no valid JSON.
+    pub fn get_object(&self) -> Result<Value, io::Error> {
+        #[cfg(not(feature = "v4"))]
+        {
+            let text = match &self.object_json {
+                Some(text) if !text.is_empty() => text,
+                _ => return Err(io::Error::new(ErrorKind::NotFound, "Selected uplink was not found!")),
+            };
+            let result = serde_json::from_str::<Value>(text);
+            return match result {
+                Ok(object) => Ok(object),
+                Err(e) => Err(io::Error::new(ErrorKind::InvalidData, e.to_string())),
+            };
+        }
+        #[cfg(feature = "v4")]
+        return match &self.object {
+            Some(object) => Ok(object.clone()),
+            None => Err(io::Error::new(ErrorKind::NotFound, "Selected uplink was not found!")),
+        };
+    }
+
+    /// Gets the gateway with the best rssi, which received the uplink.
+    pub fn get_best_gateway(&self) -> Option<&RxInfo> {
+        return self.rx_info.iter().max_by_key(|info| info.rssi);
+    }
+
+    /// Gets the device which sent the uplink.
+    pub fn get_device(&self) -> &EventDevice {
+        return &self.device;
+    }
+
+    /// Gets the gateways which received the uplink.
+    pub fn get_rx_info(&self) -> &[RxInfo] {
+        return &self.rx_info;
+    }
+
+    /// Gets the transmission of the uplink.
+    pub fn get_tx_info(&self) -> Option<&TxInfo> {
+        return self.tx_info.as_ref();
+    }
+
+    /// Gets the data rate.
+    pub fn get_dr(&self) -> u32 {
+        return self.dr;
+    }
+
+    /// Gets the frame counter; `None` if the event has none.
+    pub fn get_f_cnt(&self) -> Option<u32> {
+        return self.f_cnt;
+    }
+
+    /// Gets the port.
+    pub fn get_f_port(&self) -> u32 {
+        return self.f_port;
+    }
+
+    /// Gets the payload encoded in base64; `None` if the uplink has no payload.
+    pub fn get_data(&self) -> Option<String> {
+        return self.data.clone();
+    }
+
+    /// Gets the payload decoded by the payload codec as JSON text; `None` if there is no codec.
+    pub fn get_object_json(&self) -> Option<String> {
+        #[cfg(not(feature = "v4"))]
+        return self.object_json.clone();
+        #[cfg(feature = "v4")]
+        return self.object.as_ref().map(|object| object.to_string());
+    }
+}
+
+/**
+    Join of a device.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JoinEvent {
+    /// The device which joined.
+    #[cfg_attr(not(feature = "v4"), serde(flatten))]
+    #[cfg_attr(feature = "v4", serde(rename = "deviceInfo", default))]
+    device: EventDevice,
+    /// The device address, encoded in base64 with Chirpstack version 3 and in hex with version 4.
+    #[serde(rename = "devAddr", default)]
+    dev_addr: String,
+    /// The gateways which received the join request.
+    #[serde(rename = "rxInfo", default)]
+    rx_info: Vec<RxInfo>,
+    /// The transmission of the join request.
+    #[serde(rename = "txInfo", default)]
+    tx_info: Option<TxInfo>,
+    /// The data rate.
+    #[serde(default)]
+    dr: u32,
+}
+
+impl JoinEvent {
+    /// Gets the device which joined.
+    pub fn get_device(&self) -> &EventDevice {
+        return &self.device;
+    }
+
+    /// Gets the device address in hex.
+    pub fn get_dev_addr(&self) -> String {
+        #[cfg(not(feature = "v4"))]
+        return id_to_hex(&self.dev_addr, 4);
+        #[cfg(feature = "v4")]
+        return self.dev_addr.to_lowercase();
+    }
+
+    /// Gets the gateways which received the join request.
+    pub fn get_rx_info(&self) -> &[RxInfo] {
+        return &self.rx_info;
+    }
+
+    /// Gets the transmission of the join request.
+    pub fn get_tx_info(&self) -> Option<&TxInfo> {
+        return self.tx_info.as_ref();
+    }
+
+    /// Gets the data rate.
+    pub fn get_dr(&self) -> u32 {
+        return self.dr;
+    }
+}
+
+/**
+    Acknowledgement of a confirmed downlink.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AckEvent {
+    /// The device which received the downlink.
+    #[cfg_attr(not(feature = "v4"), serde(flatten))]
+    #[cfg_attr(feature = "v4", serde(rename = "deviceInfo", default))]
+    device: EventDevice,
+    /// The gateways which received the uplink with the acknowledgement.
+    #[serde(rename = "rxInfo", default)]
+    rx_info: Vec<RxInfo>,
+    /// Indicator if the downlink was acknowledged.
+    #[serde(default)]
+    acknowledged: bool,
+    /// The frame counter of the downlink; `None` if the event has none.
+    #[cfg_attr(not(feature = "v4"), serde(rename = "fCnt", default))]
+    #[cfg_attr(feature = "v4", serde(rename = "fCntDown", default = "zero"))]
+    f_cnt: Option<u32>,
+    /// The id of the queue item of the downlink.
+    #[cfg(feature = "v4")]
+    #[serde(rename = "queueItemId", default)]
+    queue_item_id: String,
+}
+
+impl AckEvent {
+    /// Gets the device which received the downlink.
+    pub fn get_device(&self) -> &EventDevice {
+        return &self.device;
+    }
+
+    /// Gets the gateways which received the uplink with the acknowledgement.
+    pub fn get_rx_info(&self) -> &[RxInfo] {
+        return &self.rx_info;
+    }
+
+    /// Checks if the downlink was acknowledged.
+    pub fn is_acknowledged(&self) -> bool {
+        return self.acknowledged;
+    }
+
+    /// Gets the frame counter of the downlink; `None` if the event has none.
+    pub fn get_f_cnt(&self) -> Option<u32> {
+        return self.f_cnt;
+    }
+
+    /// Gets the id of the downlink, which is its frame counter with Chirpstack version 3
+    /// and the id of its queue item with version 4; `None` if the event has none.
+    pub fn get_downlink_id(&self) -> Option<String> {
+        #[cfg(not(feature = "v4"))]
+        return self.f_cnt.map(|f_cnt| f_cnt.to_string());
+        #[cfg(feature = "v4")]
+        return if self.queue_item_id.is_empty() { None } else { Some(self.queue_item_id.clone()) };
+    }
+}
+
+/**
+    Acknowledgement of the transmission of a downlink by a gateway.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TxAckEvent {
+    /// The device to which the downlink was sent.
+    #[cfg_attr(not(feature = "v4"), serde(flatten))]
+    #[cfg_attr(feature = "v4", serde(rename = "deviceInfo", default))]
+    device: EventDevice,
+    /// The id of the gateway, encoded in base64 or hex.
+    #[cfg_attr(not(feature = "v4"), serde(rename = "gatewayID", default))]
+    #[cfg_attr(feature = "v4", serde(rename = "gatewayId", default))]
+    gateway_id: String,
+    /// The frame counter of the downlink.
+    #[cfg_attr(not(feature = "v4"), serde(rename = "fCnt", default))]
+    #[cfg_attr(feature = "v4", serde(rename = "fCntDown", default))]
+    f_cnt: u32,
+    /// The transmission of the downlink.
+    #[serde(rename = "txInfo", default)]
+    tx_info: Option<TxInfo>,
+}
+
+impl TxAckEvent {
+    /// Gets the device to which the downlink was sent.
+    pub fn get_device(&self) -> &EventDevice {
+        return &self.device;
+    }
+
+    /// Gets the id of the gateway in hex.
+    pub fn get_gateway_id(&self) -> String {
+        return id_to_hex(&self.gateway_id, 8);
+    }
+
+    /// Gets the frame counter of the downlink.
+    pub fn get_f_cnt(&self) -> u32 {
+        return self.f_cnt;
+    }
+
+    /// Gets the transmission of the downlink.
+    pub fn get_tx_info(&self) -> Option<&TxInfo> {
+        return self.tx_info.as_ref();
+    }
+}
+
+/**
+    Status of a device, which is requested by the network server.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StatusEvent {
+    /// The device.
+    #[cfg_attr(not(feature = "v4"), serde(flatten))]
+    #[cfg_attr(feature = "v4", serde(rename = "deviceInfo", default))]
+    device: EventDevice,
+    /// The demodulation margin of the last uplink in dB.
+    #[serde(default)]
+    margin: i32,
+    /// Indicator if the device is connected to an external power source.
+    #[serde(rename = "externalPowerSource", default)]
+    external_power_source: bool,
+    /// Indicator if the device can not measure its battery level.
+    #[serde(rename = "batteryLevelUnavailable", default)]
+    battery_level_unavailable: bool,
+    /// The battery level in percent.
+    #[serde(rename = "batteryLevel", default)]
+    battery_level: f32,
+}
+
+impl StatusEvent {
+    /// Gets the device.
+    pub fn get_device(&self) -> &EventDevice {
+        return &self.device;
+    }
+
+    /// Gets the demodulation margin of the last uplink in dB.
+    pub fn get_margin(&self) -> i32 {
+        return self.margin;
+    }
+
+    /// Checks if the device is connected to an external power source.
+    pub fn is_external_power_source(&self) -> bool {
+        return self.external_power_source;
+    }
+
+    /// Gets the battery level in percent;
+    /// `None` if the device can not measure it or is connected to an external power source.
+    pub fn get_battery_level(&self) -> Option<f32> {
+        if self.battery_level_unavailable || self.external_power_source {
+            return None;
+        }
+        return Some(self.battery_level);
+    }
+}
+
+/**
+    Error, e.g. of the payload codec or of a downlink.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ErrorEvent {
+    /// The device.
+    #[cfg_attr(not(feature = "v4"), serde(flatten))]
+    #[cfg_attr(feature = "v4", serde(rename = "deviceInfo", default))]
+    device: EventDevice,
+    /// The type of the error, e.g. `UPLINK_CODEC`, which is the code of the log event with Chirpstack version 4.
+    #[cfg_attr(not(feature = "v4"), serde(rename = "type", default))]
+    #[cfg_attr(feature = "v4", serde(rename = "code", default))]
+    error_type: String,
+    /// The description of the error.
+    #[cfg_attr(not(feature = "v4"), serde(default))]
+    #[cfg_attr(feature = "v4", serde(rename = "description", default))]
+    error: String,
+    /// The frame counter of the related frame.
+    #[serde(rename = "fCnt", default)]
+    f_cnt: u32,
+}
+
+impl ErrorEvent {
+    /// Gets the device.
+    pub fn get_device(&self) -> &EventDevice {
+        return &self.device;
+    }
+
+    /// Gets the type of the error, e.g. `UPLINK_CODEC`.
+    pub fn get_error_type(&self) -> String {
+        return self.error_type.clone();
+    }
+
+    /// Gets the description of the error.
+    pub fn get_error(&self) -> String {
+        return self.error.clone();
+    }
+
+    /// Gets the frame counter of the related frame.
+    pub fn get_f_cnt(&self) -> u32 {
+        return self.f_cnt;
+    }
+}
+
+/**
+    Location of a device, which is resolved by the geolocation server.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LocationEvent {
+    /// The device.
+    #[cfg_attr(not(feature = "v4"), serde(flatten))]
+    #[cfg_attr(feature = "v4", serde(rename = "deviceInfo", default))]
+    device: EventDevice,
+    /// The resolved location.
+    #[serde(default)]
+    location: Location,
+    /// The frame counter of the uplink, which was used.
+    #[serde(rename = "fCnt", default)]
+    f_cnt: u32,
+}
+
+impl LocationEvent {
+    /// Gets the device.
+    pub fn get_device(&self) -> &EventDevice {
+        return &self.device;
+    }
+
+    /// Gets the latitude in degrees.
+    pub fn get_latitude(&self) -> f64 {
+        return self.location.latitude;
+    }
+
+    /// Gets the longitude in degrees.
+    pub fn get_longitude(&self) -> f64 {
+        return self.location.longitude;
+    }
+
+    /// Gets the altitude in meters.
+    pub fn get_altitude(&self) -> f64 {
+        return self.location.altitude;
+    }
+
+    /// Gets the accuracy in meters.
+    pub fn get_accuracy(&self) -> f64 {
+        return self.location.accuracy;
+    }
+
+    /// Gets the frame counter of the uplink, which was used.
+    pub fn get_f_cnt(&self) -> u32 {
+        return self.f_cnt;
+    }
+}
+
+/// Resolved location of a [`LocationEvent`].
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct Location {
+    /// The latitude in degrees.
+    #[serde(default)]
+    latitude: f64,
+    /// The longitude in degrees.
+    #[serde(default)]
+    longitude: f64,
+    /// The altitude in meters.
+    #[serde(default)]
+    altitude: f64,
+    /// The accuracy in meters, which is a whole number with Chirpstack version 3.
+    #[serde(default)]
+    accuracy: f64,
+}
+
+#[cfg(not(feature = "v4"))]
+impl EventDevice {
+    /// Creates the device of an event, which was decoded with protobuf.
+    /// The ids are encoded in base64, like in the events encoded as JSON.
+    fn from_protobuf(application_id: u64, application_name: String, device_name: String, dev_eui: &[u8], tags: HashMap<String, String>) -> Self {
+        return EventDevice {
+            application_id: application_id.to_string(),
+            application_name,
+            device_name,
+            dev_eui: Some(base64::encode(dev_eui)),
+            tags: tags.into_iter().map(|(key, value)| (key, Value::String(value))).collect(),
+        }
+    }
+}
+
+#[cfg(not(feature = "v4"))]
+impl From<gw::UplinkRxInfo> for RxInfo {
+    fn from(info: gw::UplinkRxInfo) -> Self {
+        return RxInfo {
+            gateway_id: base64::encode(&info.gateway_id),
+            // a time out of range is left out instead of panicking
+            time: info.time.and_then(|time| Utc.timestamp_opt(time.seconds, time.nanos as u32).single()).map(|time| time.to_rfc3339()),
+            rssi: Some(info.rssi),
+            lora_snr: info.lora_snr,
+            channel: info.channel,
+        }
+    }
+}
+
+#[cfg(not(feature = "v4"))]
+impl From<gw::UplinkTxInfo> for TxInfo {
+    fn from(info: gw::UplinkTxInfo) -> Self {
+        let lora_modulation_info = match info.modulation_info {
+            Some(gw::uplink_tx_info::ModulationInfo::LoraModulationInfo(lora)) => Some(LoRaModulationInfo::from(lora)),
+            _ => None,
+        };
+        return TxInfo {
+            frequency: info.frequency as u64,
+            modulation: modulation_name(info.modulation),
+            lora_modulation_info,
+        }
+    }
+}
+
+#[cfg(not(feature = "v4"))]
+impl From<gw::DownlinkTxInfo> for TxInfo {
+    fn from(info: gw::DownlinkTxInfo) -> Self {
+        let lora_modulation_info = match info.modulation_info {
+            Some(gw::downlink_tx_info::ModulationInfo::LoraModulationInfo(lora)) => Some(LoRaModulationInfo::from(lora)),
+            _ => None,
+        };
+        return TxInfo {
+            frequency: info.frequency as u64,
+            modulation: modulation_name(info.modulation),
+            lora_modulation_info,
+        }
+    }
+}
+
+#[cfg(not(feature = "v4"))]
+impl From<gw::LoRaModulationInfo> for LoRaModulationInfo {
+    fn from(info: gw::LoRaModulationInfo) -> Self {
+        return LoRaModulationInfo {
+            bandwidth: info.bandwidth,
+            spreading_factor: info.spreading_factor,
+            code_rate: info.code_rate,
+        }
+    }
+}
+
+#[cfg(not(feature = "v4"))]
+impl From<integration::UplinkEvent> for UplinkEvent {
+    fn from(event: integration::UplinkEvent) -> Self {
+        return UplinkEvent {
+            device: EventDevice::from_protobuf(event.application_id, event.application_name, event.device_name, &event.dev_eui, event.tags),
+            rx_info: event.rx_info.into_iter().map(RxInfo::from).collect(),
+            tx_info: event.tx_info.map(TxInfo::from),
+            dr: event.dr,
+            f_cnt: Some(event.f_cnt),
+            f_port: event.f_port,
+            // an uplink without payload has no data, like in the events encoded as JSON
+            data: if event.data.is_empty() { None } else { Some(base64::encode(&event.data)) },
+            object_json: if event.object_json.is_empty() { None } else { Some(event.object_json) },
+        }
+    }
+}
+
+#[cfg(not(feature = "v4"))]
+impl From<integration::JoinEvent> for JoinEvent {
+    fn from(event: integration::JoinEvent) -> Self {
+        return JoinEvent {
+            device: EventDevice::from_protobuf(event.application_id, event.application_name, event.device_name, &event.dev_eui, event.tags),
+            dev_addr: base64::encode(&event.dev_addr),
+            rx_info: event.rx_info.into_iter().map(RxInfo::from).collect(),
+            tx_info: event.tx_info.map(TxInfo::from),
+            dr: event.dr,
+        }
+    }
+}
+
+#[cfg(not(feature = "v4"))]
+impl From<integration::AckEvent> for AckEvent {
+    fn from(event: integration::AckEvent) -> Self {
+        return AckEvent {
+            device: EventDevice::from_protobuf(event.application_id, event.application_name, event.device_name, &event.dev_eui, event.tags),
+            rx_info: Vec::new(),
+            acknowledged: event.acknowledged,
+            f_cnt: Some(event.f_cnt),
+        }
+    }
+}
+
+#[cfg(not(feature = "v4"))]
+impl From<integration::TxAckEvent> for TxAckEvent {
+    fn from(event: integration::TxAckEvent) -> Self {
+        return TxAckEvent {
+            device: EventDevice::from_protobuf(event.application_id, event.application_name, event.device_name, &event.dev_eui, event.tags),
+            gateway_id: base64::encode(&event.gateway_id),
+            f_cnt: event.f_cnt,
+            tx_info: event.tx_info.map(TxInfo::from),
+        }
+    }
+}
+
+#[cfg(not(feature = "v4"))]
+impl From<integration::StatusEvent> for StatusEvent {
+    fn from(event: integration::StatusEvent) -> Self {
+        return StatusEvent {
+            device: EventDevice::from_protobuf(event.application_id, event.application_name, event.device_name, &event.dev_eui, event.tags),
+            margin: event.margin,
+            external_power_source: event.external_power_source,
+            battery_level_unavailable: event.battery_level_unavailable,
+            battery_level: event.battery_level,
+        }
+    }
+}
+
+#[cfg(not(feature = "v4"))]
+impl From<integration::ErrorEvent> for ErrorEvent {
+    fn from(event: integration::ErrorEvent) -> Self {
+        let error_type = match integration::ErrorType::from_i32(event.r#type) {
+            Some(error_type) => screaming_snake_case(&format!("{:?}", error_type)),
+            None => event.r#type.to_string(),
+        };
+        return ErrorEvent {
+            device: EventDevice::from_protobuf(event.application_id, event.application_name, event.device_name, &event.dev_eui, event.tags),
+            error_type,
+            error: event.error,
+            f_cnt: event.f_cnt,
+        }
+    }
+}
+
+#[cfg(not(feature = "v4"))]
+impl From<integration::LocationEvent> for LocationEvent {
+    fn from(event: integration::LocationEvent) -> Self {
+        let location = match event.location {
+            Some(location) => Location {
+                latitude: location.latitude,
+                longitude: location.longitude,
+                altitude: location.altitude,
+                accuracy: location.accuracy as f64,
+            },
+            None => Location::default(),
+        };
+        return LocationEvent {
+            device: EventDevice::from_protobuf(event.application_id, event.application_name, event.device_name, &event.dev_eui, event.tags),
+            location,
+            f_cnt: event.f_cnt,
+        }
+    }
+}
+
+/// Gets the name of a protobuf modulation, like in the events encoded as JSON, e.g. `LORA`.
+#[cfg(not(feature = "v4"))]
+fn modulation_name(modulation: i32) -> String {
+    return match common::Modulation::from_i32(modulation) {
+        Some(modulation) => format!("{:?}", modulation).to_uppercase(),
+        None => modulation.to_string(),
+    };
+}
+
+#[cfg(feature = "v4")]
+impl From<Option<integration::DeviceInfo>> for EventDevice {
+    fn from(device_info: Option<integration::DeviceInfo>) -> Self {
+        let device_info = device_info.unwrap_or_default();
+        return EventDevice {
+            application_id: device_info.application_id,
+            application_name: device_info.application_name,
+            device_name: device_info.device_name,
+            // a missing device info has no dev_eui, like in the events encoded as JSON
+            dev_eui: if device_info.dev_eui.is_empty() { None } else { Some(device_info.dev_eui) },
+            tags: device_info.tags.into_iter().map(|(key, value)| (key, Value::String(value))).collect(),
+        }
+    }
+}
+
+#[cfg(feature = "v4")]
+impl From<gw::UplinkRxInfo> for RxInfo {
+    fn from(info: gw::UplinkRxInfo) -> Self {
+        return RxInfo {
+            gateway_id: info.gateway_id,
+            // a time out of range is left out instead of panicking
+            time: info.time.and_then(|time| Utc.timestamp_opt(time.seconds, time.nanos as u32).single()).map(|time| time.to_rfc3339()),
+            rssi: Some(info.rssi),
+            lora_snr: info.snr as f64,
+            channel: info.channel,
+        }
+    }
+}
+
+#[cfg(feature = "v4")]
+impl From<Option<gw::Modulation>> for Modulation {
+    fn from(modulation: Option<gw::Modulation>) -> Self {
+        let lora = match modulation.and_then(|modulation| modulation.parameters) {
+            Some(gw::modulation::Parameters::Lora(lora)) => Some(LoRaModulationInfo {
+                bandwidth: lora.bandwidth,
+                spreading_factor: lora.spreading_factor,
+                code_rate: gw::CodeRate::from_i32(lora.code_rate)
+                    .map(|code_rate| code_rate.as_str_name().to_string())
+                    .unwrap_or(lora.code_rate_legacy),
+            }),
+            _ => None,
+        };
+        return Modulation {
+            lora,
+        }
+    }
+}
+
+#[cfg(feature = "v4")]
+impl From<gw::UplinkTxInfo> for TxInfo {
+    fn from(info: gw::UplinkTxInfo) -> Self {
+        return TxInfo {
+            frequency: info.frequency as u64,
+            modulation: Some(Modulation::from(info.modulation)),
+        }
+    }
+}
+
+#[cfg(feature = "v4")]
+impl From<gw::DownlinkTxInfo> for TxInfo {
+    fn from(info: gw::DownlinkTxInfo) -> Self {
+        return TxInfo {
+            frequency: info.frequency as u64,
+            modulation: Some(Modulation::from(info.modulation)),
+        }
+    }
+}
+
+#[cfg(feature = "v4")]
+impl From<integration::UplinkEvent> for UplinkEvent {
+    fn from(event: integration::UplinkEvent) -> Self {
+        return UplinkEvent {
+            device: EventDevice::from(event.device_info),
+            rx_info: event.rx_info.into_iter().map(RxInfo::from).collect(),
+            tx_info: event.tx_info.map(TxInfo::from),
+            dr: event.dr,
+            f_cnt: Some(event.f_cnt),
+            f_port: event.f_port,
+            // an uplink without payload has no data, like in the events encoded as JSON
+            data: if event.data.is_empty() { None } else { Some(base64::encode(&event.data)) },
+            object: event.object.map(struct_to_value),
+        }
+    }
+}
+
+#[cfg(feature = "v4")]
+impl From<integration::JoinEvent> for JoinEvent {
+    fn from(event: integration::JoinEvent) -> Self {
+        return JoinEvent {
+            device: EventDevice::from(event.device_info),
+            dev_addr: event.dev_addr,
+            rx_info: Vec::new(),
+            tx_info: None,
+            dr: 0,
+        }
+    }
+}
+
+#[cfg(feature = "v4")]
+impl From<integration::AckEvent> for AckEvent {
+    fn from(event: integration::AckEvent) -> Self {
+        return AckEvent {
+            device: EventDevice::from(event.device_info),
+            rx_info: Vec::new(),
+            acknowledged: event.acknowledged,
+            f_cnt: Some(event.f_cnt_down),
+            queue_item_id: event.queue_item_id,
+        }
+    }
+}
+
+#[cfg(feature = "v4")]
+impl From<integration::TxAckEvent> for TxAckEvent {
+    fn from(event: integration::TxAckEvent) -> Self {
+        return TxAckEvent {
+            device: EventDevice::from(event.device_info),
+            gateway_id: event.gateway_id,
+            f_cnt: event.f_cnt_down,
+            tx_info: event.tx_info.map(TxInfo::from),
+        }
+    }
+}
+
+#[cfg(feature = "v4")]
+impl From<integration::StatusEvent> for StatusEvent {
+    fn from(event: integration::StatusEvent) -> Self {
+        return StatusEvent {
+            device: EventDevice::from(event.device_info),
+            margin: event.margin,
+            external_power_source: event.external_power_source,
+            battery_level_unavailable: event.battery_level_unavailable,
+            battery_level: event.battery_level,
+        }
+    }
+}
+
+#[cfg(feature = "v4")]
+impl From<integration::LogEvent> for ErrorEvent {
+    fn from(event: integration::LogEvent) -> Self {
+        let error_type = match integration::LogCode::from_i32(event.code) {
+            Some(code) => screaming_snake_case(&format!("{:?}", code)),
+            None => event.code.to_string(),
+        };
+        return ErrorEvent {
+            device: EventDevice::from(event.device_info),
+            error_type,
+            error: event.description,
+            f_cnt: 0,
+        }
+    }
+}
+
+#[cfg(feature = "v4")]
+impl From<integration::LocationEvent> for LocationEvent {
+    fn from(event: integration::LocationEvent) -> Self {
+        let location = match event.location {
+            Some(location) => Location {
+                latitude: location.latitude,
+                longitude: location.longitude,
+                altitude: location.altitude,
+                accuracy: location.accuracy as f64,
+            },
+            None => Location::default(),
+        };
+        return LocationEvent {
+            device: EventDevice::from(event.device_info),
+            location,
+            f_cnt: 0,
+        }
+    }
+}
+
+/// Converts a protobuf struct, e.g. the decoded payload of an uplink with Chirpstack version 4, to JSON.
+/// The integration events of version 4 use the well-known types of pbjson-types.
+#[cfg(feature = "v4")]
+fn struct_to_value(object: pbjson_types_v4::Struct) -> Value {
+    fn to_value(value: pbjson_types_v4::Value) -> Value {
+        return match value.kind {
+            Some(pbjson_types_v4::value::Kind::BoolValue(bool)) => Value::from(bool),
+            Some(pbjson_types_v4::value::Kind::NumberValue(number)) => Value::from(number),
+            Some(pbjson_types_v4::value::Kind::StringValue(string)) => Value::from(string),
+            Some(pbjson_types_v4::value::Kind::ListValue(list)) => Value::Array(list.values.into_iter().map(to_value).collect()),
+            Some(pbjson_types_v4::value::Kind::StructValue(object)) => struct_to_value(object),
+            Some(pbjson_types_v4::value::Kind::NullValue(_)) | None => Value::Null,
+        };
+    }
+    return Value::Object(object.fields.into_iter().map(|(key, value)| (key, to_value(value))).collect());
+}
+
+/// Converts the name of a protobuf enum value, e.g. `UplinkCodec`, to the name used in the events encoded as JSON, e.g. `UPLINK_CODEC`.
+fn screaming_snake_case(name: &str) -> String {
+    let mut result = String::with_capacity(name.len() + 4);
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            result.push('_');
+        }
+        result.push(c.to_ascii_uppercase());
+    }
+    return result;
+}
+
+/// Gets 0 for a frame counter, which is missing in an event of Chirpstack version 4,
+/// because values which are 0 are omitted in the events encoded as JSON.
+#[cfg(feature = "v4")]
+fn zero() -> Option<u32> {
+    return Some(0);
+}
+
+/// Converts an id with a `length` in bytes, e.g. a dev_eui or a gateway id with 8 bytes or a device address with 4 bytes,
+/// which is encoded in base64 in the Mqtt messages, to hex.
+/// If it is already encoded in hex or can not be decoded, it is returned unchanged.<br/>
+/// The length is needed, because an id in hex can also be valid base64.
+pub fn id_to_hex(id: &str, length: usize) -> String {
+    if id.len() == 2 * length && hex::decode(id).is_ok() {
+        return id.to_lowercase();
+    }
+    return match base64::decode(id) {
+        Ok(bytes) => hex::encode(bytes),
+        Err(_) => id.to_string(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_to_hex() {
+        assert_eq!(id_to_hex("AQIDBAUGBwg=", 8), "0102030405060708");
+        assert_eq!(id_to_hex("0102030405060708", 8), "0102030405060708");
+        assert_eq!(id_to_hex("AQIDBA==", 4), "01020304");
+        // valid base64 as well, but it has the length of a device address in hex
+        assert_eq!(id_to_hex("01020304", 4), "01020304");
+        assert_eq!(id_to_hex("00ABCDEF", 4), "00abcdef");
+        assert_eq!(id_to_hex("not an id", 8), "not an id");
+    }
+
+    #[test]
+    fn missing_fields() {
+        let ack = serde_json::from_str::<AckEvent>(r#"{"acknowledged": true}"#).unwrap();
+        assert_eq!(ack.get_device().get_dev_eui(), None);
+        assert_eq!(ack.get_downlink_id(), None);
+
+        let uplink = UplinkEvent::parse(r#"{"rxInfo": [{"channel": 2}]}"#).unwrap();
+        assert_eq!(uplink.get_rx_info()[0].get_rssi(), None);
+        // values which are 0 are omitted by Chirpstack version 4
+        #[cfg(not(feature = "v4"))]
+        assert_eq!(uplink.get_f_cnt(), None);
+        #[cfg(feature = "v4")]
+        assert_eq!(uplink.get_f_cnt(), Some(0));
+    }
+
+    #[test]
+    #[cfg(not(feature = "v4"))]
+    fn uplink_v3() {
+        // the fields are not in the order of Chirpstack and the JSON text of the object contains quotes
+        let payload = r#"{
+            "objectJSON": "{\"label\":\"a \\\"quoted\\\" name\",\"temperature\":21.5}",
+            "fPort": 5,
+            "tags": {"room": "kitchen"},
+            "rxInfo": [
+                {"channel": 1, "loRaSNR": 7.5, "rssi": -90, "gatewayID": "AQEBAQEBAQE=", "time": "2022-08-01T10:00:00Z"},
+                {"rssi": -57, "loRaSNR": 10, "gatewayID": "AgICAgICAgI=", "channel": 2}
+            ],
+            "data": "AQI=",
+            "fCnt": 10,
+            "devEUI": "AQIDBAUGBwg=",
+            "txInfo": {"loRaModulationInfo": {"codeRate": "4/5", "spreadingFactor": 7, "bandwidth": 125}, "modulation": "LORA", "frequency": 868100000},
+            "deviceName": "sensor",
+            "dr": 5,
+            "applicationName": "building",
+            "applicationID": "1"
+        }"#;
+        let message = Message::new("application/1/device/0102030405060708/event/up", payload, 0);
+        let uplink = match Event::parse(&message).unwrap() {
+            Event::Up(uplink) => uplink,
+            event => panic!("Unexpected event {:?}", event),
+        };
+
+        let object = uplink.get_object().unwrap();
+        assert_eq!(object["label"], "a \"quoted\" name");
+        assert_eq!(object["temperature"], 21.5);
+        assert_eq!(uplink.get_f_cnt(), Some(10));
+        assert_eq!(uplink.get_f_port(), 5);
+        assert_eq!(uplink.get_data(), Some("AQI=".to_string()));
+
+        let device = uplink.get_device();
+        assert_eq!(device.get_dev_eui(), Some("0102030405060708".to_string()));
+        assert_eq!(device.get_application_id(), "1");
+        assert_eq!(device.get_device_name(), "sensor");
+        assert_eq!(device.get_tags()["room"], "kitchen");
+
+        let rx_info = uplink.get_rx_info();
+        assert_eq!(rx_info.len(), 2);
+        assert_eq!(rx_info[0].get_gateway_id(), "0101010101010101");
+        assert_eq!(rx_info[0].get_rssi(), Some(-90));
+        assert_eq!(rx_info[0].get_lora_snr(), 7.5);
+        assert_eq!(rx_info[0].get_time(), Some("2022-08-01T10:00:00Z".to_string()));
+        assert_eq!(uplink.get_best_gateway().unwrap().get_gateway_id(), "0202020202020202");
+
+        let modulation = uplink.get_tx_info().unwrap().get_lora_modulation_info().unwrap();
+        assert_eq!(modulation.get_bandwidth(), 125);
+        assert_eq!(modulation.get_spreading_factor(), 7);
+    }
+
+    #[test]
+    #[cfg(feature = "v4")]
+    fn uplink_v4() {
+        // the fields are not in the order of Chirpstack and a string of the object contains quotes
+        let payload = r#"{
+            "object": {"temperature": 21.5, "label": "a \"quoted\" name"},
+            "fPort": 5,
+            "rxInfo": [
+                {"channel": 1, "snr": 7.5, "rssi": -90, "gatewayId": "0101010101010101", "time": "2022-08-01T10:00:00Z", "uplinkId": 1},
+                {"uplinkId": 2, "rssi": -57, "snr": 10, "gatewayId": "0202020202020202", "channel": 2}
+            ],
+            "data": "AQI=",
+            "fCnt": 10,
+            "deviceInfo": {
+                "tags": {"room": "kitchen"},
+                "devEui": "0102030405060708",
+                "deviceName": "sensor",
+                "applicationName": "building",
+                "applicationId": "c6bc0b7c-8e4b-4a8a-9e7d-5b0c6a2b1f3e",
+                "tenantId": "52f14cd4-c6f1-4fbd-8f87-4025e1d49242"
+            },
+            "txInfo": {"modulation": {"lora": {"codeRate": "CR_4_5", "spreadingFactor": 7, "bandwidth": 125000}}, "frequency": 868100000},
+            "dr": 5,
+            "confirmed": false,
+            "deduplicationId": "3d7c3f7e-5a3e-4a5f-9b0e-3a1c3f1e2d4b"
+        }"#;
+        let message = Message::new("application/c6bc0b7c-8e4b-4a8a-9e7d-5b0c6a2b1f3e/device/0102030405060708/event/up", payload, 0);
+        let uplink = match Event::parse(&message).unwrap() {
+            Event::Up(uplink) => uplink,
+            event => panic!("Unexpected event {:?}", event),
+        };
+
+        let object = uplink.get_object().unwrap();
+        assert_eq!(object["label"], "a \"quoted\" name");
+        assert_eq!(object["temperature"], 21.5);
+        assert_eq!(uplink.get_f_cnt(), Some(10));
+        assert_eq!(uplink.get_f_port(), 5);
+        assert_eq!(uplink.get_data(), Some("AQI=".to_string()));
+
+        let device = uplink.get_device();
+        assert_eq!(device.get_dev_eui(), Some("0102030405060708".to_string()));
+        assert_eq!(device.get_application_id(), "c6bc0b7c-8e4b-4a8a-9e7d-5b0c6a2b1f3e");
+        assert_eq!(device.get_device_name(), "sensor");
+        assert_eq!(device.get_tags()["room"], "kitchen");
+
+        let rx_info = uplink.get_rx_info();
+        assert_eq!(rx_info.len(), 2);
+        assert_eq!(rx_info[0].get_gateway_id(), "0101010101010101");
+        assert_eq!(rx_info[0].get_rssi(), Some(-90));
+        assert_eq!(rx_info[0].get_lora_snr(), 7.5);
+        assert_eq!(rx_info[0].get_time(), Some("2022-08-01T10:00:00Z".to_string()));
+        assert_eq!(uplink.get_best_gateway().unwrap().get_gateway_id(), "0202020202020202");
+
+        let modulation = uplink.get_tx_info().unwrap().get_lora_modulation_info().unwrap();
+        assert_eq!(modulation.get_bandwidth(), 125);
+        assert_eq!(modulation.get_spreading_factor(), 7);
+    }
+
+    #[test]
+    #[cfg(not(feature = "v4"))]
+    fn protobuf_to_json_v3() {
+        let uplink = integration::UplinkEvent {
+            application_id: 1,
+            application_name: "building".to_string(),
+            device_name: "sensor".to_string(),
+            dev_eui: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            rx_info: vec![gw::UplinkRxInfo { gateway_id: vec![1; 8], rssi: -57, lora_snr: 7.5, channel: 1, ..Default::default() }],
+            f_cnt: 10,
+            f_port: 5,
+            data: vec![1, 2],
+            object_json: r#"{"label":"a \"quoted\" name"}"#.to_string(),
+            tags: HashMap::from([("room".to_string(), "kitchen".to_string())]),
+            ..Default::default()
+        };
+        let message = Message::new("application/1/device/0102030405060708/event/up", uplink.encode_to_vec(), 0);
+        let json = Marshaler::Protobuf.to_json(message).unwrap();
+        assert_eq!(json.topic(), "application/1/device/0102030405060708/event/up");
+        // the ids are encoded in base64 like in the events of Chirpstack encoded as JSON
+        let value = serde_json::from_str::<Value>(&json.payload_str()).unwrap();
+        assert_eq!(value["devEUI"], "AQIDBAUGBwg=");
+        assert_eq!(value["applicationID"], "1");
+        assert_eq!(value["rxInfo"][0]["gatewayID"], "AQEBAQEBAQE=");
+        assert_eq!(value["data"], "AQI=");
+
+        let uplink = match Event::parse(&json).unwrap() {
+            Event::Up(uplink) => uplink,
+            event => panic!("Unexpected event {:?}", event),
+        };
+        assert_eq!(uplink.get_device().get_dev_eui(), Some("0102030405060708".to_string()));
+        assert_eq!(uplink.get_device().get_tags()["room"], "kitchen");
+        assert_eq!(uplink.get_object().unwrap()["label"], "a \"quoted\" name");
+        assert_eq!(uplink.get_f_cnt(), Some(10));
+        assert_eq!(uplink.get_f_port(), 5);
+        assert_eq!(uplink.get_rx_info()[0].get_gateway_id(), "0101010101010101");
+        assert_eq!(uplink.get_rx_info()[0].get_rssi(), Some(-57));
+
+        let ack = integration::AckEvent {
+            dev_eui: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            acknowledged: true,
+            f_cnt: 3,
+            ..Default::default()
+        };
+        let message = Message::new("application/1/device/0102030405060708/event/ack", ack.encode_to_vec(), 0);
+        let ack = match Event::decode(&message, Marshaler::Protobuf).unwrap() {
+            Event::Ack(ack) => ack,
+            event => panic!("Unexpected event {:?}", event),
+        };
+        assert_eq!(ack.get_device().get_dev_eui(), Some("0102030405060708".to_string()));
+        assert!(ack.is_acknowledged());
+        assert_eq!(ack.get_f_cnt(), Some(3));
+
+        let message = Message::new("application/1/device/0102030405060708/event/ack", vec![0xff, 0xff], 0);
+        assert_eq!(Event::decode(&message, Marshaler::Protobuf).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    #[cfg(feature = "v4")]
+    fn protobuf_to_json_v4() {
+        let device_info = integration::DeviceInfo {
+            application_id: "c6bc0b7c-8e4b-4a8a-9e7d-5b0c6a2b1f3e".to_string(),
+            application_name: "building".to_string(),
+            device_name: "sensor".to_string(),
+            dev_eui: "0102030405060708".to_string(),
+            tags: [("room".to_string(), "kitchen".to_string())].into_iter().collect(),
+            ..Default::default()
+        };
+        let uplink = integration::UplinkEvent {
+            device_info: Some(device_info.clone()),
+            rx_info: vec![gw::UplinkRxInfo { gateway_id: "0101010101010101".to_string(), rssi: -57, snr: 7.5, channel: 1, ..Default::default() }],
+            f_cnt: 10,
+            f_port: 5,
+            data: vec![1, 2],
+            ..Default::default()
+        };
+        let message = Message::new("application/c6bc0b7c-8e4b-4a8a-9e7d-5b0c6a2b1f3e/device/0102030405060708/event/up", uplink.encode_to_vec(), 0);
+        let json = Marshaler::Protobuf.to_json(message).unwrap();
+        let value = serde_json::from_str::<Value>(&json.payload_str()).unwrap();
+        assert_eq!(value["deviceInfo"]["devEui"], "0102030405060708");
+        assert_eq!(value["rxInfo"][0]["gatewayId"], "0101010101010101");
+        assert_eq!(value["data"], "AQI=");
+
+        let uplink = match Event::parse(&json).unwrap() {
+            Event::Up(uplink) => uplink,
+            event => panic!("Unexpected event {:?}", event),
+        };
+        assert_eq!(uplink.get_device().get_dev_eui(), Some("0102030405060708".to_string()));
+        assert_eq!(uplink.get_device().get_tags()["room"], "kitchen");
+        assert_eq!(uplink.get_f_cnt(), Some(10));
+        assert_eq!(uplink.get_f_port(), 5);
+        assert_eq!(uplink.get_rx_info()[0].get_rssi(), Some(-57));
+
+        let ack = integration::AckEvent {
+            device_info: Some(device_info),
+            queue_item_id: "3d7c3f7e-5a3e-4a5f-9b0e-3a1c3f1e2d4b".to_string(),
+            acknowledged: true,
+            f_cnt_down: 3,
+            ..Default::default()
+        };
+        let message = Message::new("application/c6bc0b7c-8e4b-4a8a-9e7d-5b0c6a2b1f3e/device/0102030405060708/event/ack", ack.encode_to_vec(), 0);
+        let ack = match Event::decode(&message, Marshaler::Protobuf).unwrap() {
+            Event::Ack(ack) => ack,
+            event => panic!("Unexpected event {:?}", event),
+        };
+        assert_eq!(ack.get_device().get_dev_eui(), Some("0102030405060708".to_string()));
+        assert!(ack.is_acknowledged());
+        assert_eq!(ack.get_f_cnt(), Some(3));
+        assert_eq!(ack.get_downlink_id(), Some("3d7c3f7e-5a3e-4a5f-9b0e-3a1c3f1e2d4b".to_string()));
+
+        let message = Message::new("application/c6bc0b7c-8e4b-4a8a-9e7d-5b0c6a2b1f3e/device/0102030405060708/event/ack", vec![0xff, 0xff], 0);
+        assert_eq!(Event::decode(&message, Marshaler::Protobuf).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
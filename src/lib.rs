@@ -19,6 +19,27 @@
 //! a unique API key is required! It can be generated in the Chirpstack application server
 //! (API keys > create).
 //!
+//! # Chirpstack v4
+//! The library is built against Chirpstack version 3.11.1 by default.
+//! To use it with Chirpstack version 4, the default features have to be disabled and the `v4` feature enabled:
+//! ```toml
+//! elorapi = { version = "0.1.2", default-features = false, features = ["v4"] }
+//! ```
+//! Then the Chirpstack types in the public API, e.g. [`Device::get_chirpstack_device`](devices::Device::get_chirpstack_device),
+//! are the ones of the [v4 API](https://docs.rs/chirpstack_api/4.0.0/chirpstack_api/api/index.html).
+//! Beside that the differences are:
+//! - Applications and tenants (formerly organizations) are identified by a UUID,
+//! therefore device profiles and devices are loaded with the tenant id and application id as string
+//! and a device profile specification file is read with the tenant id instead of the network server id and organization id.
+//! - The codec of a device profile specification file is one script with the functions `decodeUplink` and `encodeDownlink`
+//! in the field `payload_codec_script`, which must not be empty with the `payload_codec` `CUSTOM_JS`;
+//! the `payload_decoder_script` and `payload_encoder_script` of version 3 have to be empty.
+//! - The events of the Mqtt integration are parsed in the v4 format; errors are received as [`EventType::Error`](events::EventType::Error)
+//! from the `log` topic.
+//! - Confirmed downlinks are tracked with the id of the queue item,
+//! therefore the frame counter of an [`AckReport`](rules::AckReport) is 0, if the downlink was not acknowledged in time.
+//! - The location of a device is not provided, therefore the location of a time condition can not be set from a device.
+//!
 //! # Diagrams
 //! In this section an overview of the [structure of a rule](#structure-of-a-rule) and an [uml diagram](#uml) of all methods is given.
 //! ## Structure of a rule
@@ -148,16 +169,147 @@
 //! }
 //! ```
 
+#[cfg(all(feature = "v3", feature = "v4"))]
+compile_error!("The features \"v3\" and \"v4\" can not be enabled at the same time.");
+#[cfg(not(any(feature = "v3", feature = "v4")))]
+compile_error!("Either the feature \"v3\" or \"v4\" has to be enabled.");
+
+// with the v4 feature, the dependencies for Chirpstack version 4 are used under the same names
+#[cfg(feature = "v4")]
+extern crate chirpstack_api_v4 as chirpstack_api;
+#[cfg(feature = "v4")]
+extern crate tonic_v4 as tonic;
+#[cfg(feature = "v4")]
+extern crate prost_v4 as prost;
+#[cfg(feature = "v4")]
+extern crate prost_types_v4 as prost_types;
+
 /// This module is for the connection to the Chirpstack server and a MQTT broker. <br/>
 /// It is necessary to establish these connections to get the full functionality of this library.
+///
+/// # Examples
+/// - [TLS](#tls)
+/// - [Client id, quality of service and topics](#client-id-quality-of-service-and-topics)
+/// - [Reconnection and shutdown](#reconnection-and-shutdown)
+/// - [Receiving events](#receiving-events)
+/// - [Protobuf events](#protobuf-events)
+/// - [gRPC connection](#grpc-connection)
+/// ## TLS
+/// If the Mqtt broker requires TLS, the certificate of a private certificate authority
+/// and a client certificate can be set.
+///```no_run
+/// use elorapi::connections::{Mqtt, MqttSsl};
+///
+/// let mut ssl = MqttSsl::new();
+/// ssl.set_ca_file("/etc/elorapi/ca.pem");
+/// ssl.set_client_cert("/etc/elorapi/client.pem", "/etc/elorapi/client.key", None);
+///
+/// let mut mqtt = Mqtt::new("ssl://broker:8883", "username", "password");
+/// mqtt.set_ssl(ssl);
+/// let receiver = mqtt.start_receiving().unwrap();
+/// ```
+/// ## Client id, quality of service and topics
+/// Every instance of elorapi needs its own client id, otherwise they disconnect each other from the broker.
+/// With a persistent session and a quality of service of 1, no uplinks are lost while elorapi is disconnected.<br/>
+/// If the Mqtt integration of the Chirpstack Application Server uses different topics,
+/// these are given by a [`TopicTemplate`](connections::TopicTemplate).
+///```no_run
+/// use elorapi::connections::{Mqtt, TopicTemplate};
+///
+/// let mut mqtt = Mqtt::new("tcp://broker:1883", "username", "password");
+/// mqtt.set_client_id("elorapi-site-a", false);
+/// mqtt.set_qos(1).unwrap();
+/// mqtt.set_clean_session(false);
+/// mqtt.set_topic_template(TopicTemplate::new("site-a/{application_id}/{dev_eui}/{event}").unwrap());
+/// let receiver = mqtt.start_receiving().unwrap();
+/// ```
+/// ## Reconnection and shutdown
+/// If the connection to the broker is lost, it is reconnected automatically with exponential backoff
+/// and the topics are subscribed again.
+/// The state of the connection can be observed with the handle, which also stops receiving.
+///```no_run
+/// use elorapi::connections::{ConnectionState, Mqtt};
+///
+/// let mqtt = Mqtt::new("tcp://broker:1883", "username", "password");
+/// let handle = mqtt.start().unwrap();
+/// // this receiver is given to the rules
+/// let receiver = handle.get_receiver();
+///
+/// let mut state = handle.get_state_receiver();
+/// tokio::spawn(async move {
+///     while state.changed().await.is_ok() {
+///         if *state.borrow() != ConnectionState::Connected {
+///             println!("Mqtt connection: {:?}", *state.borrow());
+///         }
+///     }
+/// });
+///
+/// // later on
+/// handle.shutdown();
+/// ```
+/// ## Receiving events
+/// Besides uplinks, the Chirpstack Application Server publishes joins, acknowledgements, status, errors and more.
+/// The chosen event types are received as [`Event`](events::Event)s.
+///```no_run
+/// use elorapi::connections::Mqtt;
+/// use elorapi::events::{Event, EventType};
+///
+/// let mut mqtt = Mqtt::new("tcp://broker:1883", "username", "password");
+/// mqtt.set_event_types(vec![EventType::Join, EventType::Status, EventType::Error]);
+/// let receiver = mqtt.start_receiving_events().unwrap();
+/// for event in receiver {
+///     match event {
+///         Event::Join(join) => println!("{} joined", join.get_device().get_device_name()),
+///         Event::Status(status) => println!("Battery level: {:?}", status.get_battery_level()),
+///         Event::Error(error) => println!("{}: {}", error.get_error_type(), error.get_error()),
+///         _ => {},
+///     }
+/// }
+/// ```
+/// ## Protobuf events
+/// If the Mqtt integration of the Chirpstack Application Server uses the protobuf marshaler,
+/// the same has to be set here. The events are decoded and handed to the rules as JSON.
+///```no_run
+/// use elorapi::connections::Mqtt;
+/// use elorapi::events::Marshaler;
+///
+/// let mut mqtt = Mqtt::new("tcp://broker:1883", "username", "password");
+/// mqtt.set_marshaler(Marshaler::Protobuf);
+/// let receiver = mqtt.start_receiving().unwrap();
+/// ```
+/// ## gRPC connection
+/// All containers, which are given the same [`ChirpstackConnection`](connections::ChirpstackConnection) or a clone of it,
+/// share one channel to the Chirpstack Application Server. It connects with the first request and reconnects when needed.<br/>
+/// If the Chirpstack Application Server is reached via https, TLS is set, optionally with a private certificate authority
+/// and a client certificate. A timeout and HTTP2 keep-alive pings can be set as well.
+///```no_run
+/// use std::time::Duration;
+/// use elorapi::connections::{ChirpstackConnection, ChirpstackTls};
+///
+/// let mut tls = ChirpstackTls::new();
+/// tls.set_ca_file("/etc/elorapi/ca.pem").unwrap();
+/// tls.set_client_cert("/etc/elorapi/client.pem", "/etc/elorapi/client.key").unwrap();
+///
+/// let mut connection = ChirpstackConnection::new("api_token", "https://chirpstack:8080");
+/// connection.set_tls(tls);
+/// connection.set_timeout(Some(Duration::from_secs(10)));
+/// connection.set_keep_alive(Some(Duration::from_secs(30)));
+/// ```
 pub mod connections;
 
+/// This module contains the typed events, which are published by the Mqtt integration of the Chirpstack Application Server.
+///
+/// The events are received with [`Mqtt::start_receiving_events`](connections::Mqtt::start_receiving_events).
+/// For an example see this [link](./index.html#receiving-events).
+pub mod events;
+
 /// This module is for the management of devices and device profiles.
 ///
 /// Note that it is necessary to establish a connection before loading data from or writing to the
 /// Chirsptack server. Beware that [`DeviceProfileContainer::establish_connection`](devices::DeviceProfileContainer::establish_connection)
-/// establishes another connection than [`DeviceContainer::establish_connection`](devices::DeviceContainer::establish_connection),
-/// therefor these functions must be called in their respective cases.
+/// establishes another client than [`DeviceContainer::establish_connection`](devices::DeviceContainer::establish_connection),
+/// therefor these functions must be called in their respective cases. Both clients share the channel of the
+/// [`ChirpstackConnection`](connections::ChirpstackConnection).
 ///
 /// Also note that there is a difference between a [`DeviceProfile`](devices::DeviceProfile) and
 /// a [`DeviceProfileListItem`](https://docs.rs/chirpstack_api/3.11.1/chirpstack_api/as_pb/external/api/struct.DeviceProfileListItem.html),
@@ -201,7 +353,7 @@ pub mod connections;
 /// to which the profile should be written, is necessary. The ids can be seen in the Chirpstack application server.
 ///
 /// For more information about the specification files go to the [specification files](../index.html#specification-files) paragraph.
-/// ```
+/// ```ignore
 /// use elorapi::devices::DeviceProfile;
 ///
 /// // the network server id and the organization id
@@ -224,14 +376,14 @@ pub mod connections;
 /// For this the following steps need to be done:
 /// - __Establish a device profile container connection__<br/>
 /// Note that this is another connection than the Chirpstack connection.
-/// ```
+/// ```ignore
 /// dev_prof_container.establish_connection(connection.clone()).await.unwrap();
 /// ```
 /// - __Load chirpstack device profile__<br/>
 /// Note that _Chirpstack device profiles_ should not be misunderstood as [`DeviceProfile`](devices::DeviceProfile).<br/>
 /// It is only possible to load a specific number of device profiles from a specific organization and application.
 /// Therefor the specific ids must be given (this information can be seen in the Chirpstack application).
-/// ```
+/// ```ignore
 /// // number of items and ids
 /// let limit = 10;
 /// let organization_id = 1;
@@ -245,7 +397,7 @@ pub mod connections;
 /// For this the device profile id is needed (to get this information, either print the item via
 /// [`DeviceProfileContainer::print_list_items`](devices::DeviceProfileContainer::print_list_items) or get all items
 /// via [`DeviceProfileContainer::get_chirpstack_device_profiles`](devices::DeviceProfileContainer::get_chirpstack_device_profiles) and then their id).
-///```
+///```ignore
 /// use elorapi::devices::DeviceProfile;
 ///
 /// // get the device profile id of a chirpstack device list item
@@ -261,7 +413,7 @@ pub mod connections;
 /// It is possible, for some cases even necessary,
 /// to load uplink and/or downlink specification/s via a specification file.
 /// It can be done directly after loading the device profile.
-///```
+///```ignore
 /// use elorapi::devices::DeviceProfile;
 ///
 /// // load device profile out from the server
@@ -275,7 +427,7 @@ pub mod connections;
 /// ```
 /// It is also possible to load the specification after [loading an existing device profile](#loading-existing-device-profile).
 /// For this the index of the device profile in the container is needed.
-/// ```
+/// ```ignore
 /// // get the index of the device profile
 /// let index = dev_prof_container.get_device_profile_index_via_dev_prof_id("device_profile_id").unwrap();
 /// // now read the downlink or uplink specification file
@@ -285,14 +437,14 @@ pub mod connections;
 /// It is possible to load existing devices from the Chirpstack server.<br/>
 /// For this the following steps need to be done:
 /// - __Establish a device container connection__
-///```
+///```ignore
 /// // establish a connection to the Chripstack server
 /// dev_container.establish_connection(connection.clone()).await.unwrap();
 ///```
 /// - __Loading the actual device__<br/>
 /// It is only possible to load a specific number of items form a specific application.
 /// After loading the chirpstack devices to the container, get a device list item out of it and load the device.
-///```
+///```ignore
 /// use elorapi::devices::Device;
 ///
 /// // load a limited list of devices in a specific application
@@ -321,6 +473,21 @@ pub mod devices;
 /// - [Startup](#startup)
 /// - [Creating a rule depending on a device](#creating-a-rule-depending-on-a-device)
 /// - [Creating conditions depending on time](#creating-conditions-depending-on-time)
+/// - [Creating conditions depending on sunrise and sunset](#creating-conditions-depending-on-sunrise-and-sunset)
+/// - [Creating conditions depending on the silence of a device](#creating-conditions-depending-on-the-silence-of-a-device)
+/// - [Creating conditions depending on radio metadata](#creating-conditions-depending-on-radio-metadata)
+/// - [Creating conditions with arithmetic expressions](#creating-conditions-with-arithmetic-expressions)
+/// - [Comparison operators](#comparison-operators)
+/// - [Creating scheduled rules](#creating-scheduled-rules)
+/// - [Creating delayed actions](#creating-delayed-actions)
+/// - [Creating json object downlinks](#creating-json-object-downlinks)
+/// - [Creating confirmed downlinks](#creating-confirmed-downlinks)
+/// - [Creating multicast actions](#creating-multicast-actions)
+/// - [Inspecting and flushing device queues](#inspecting-and-flushing-device-queues)
+/// - [Retrying failed downlinks](#retrying-failed-downlinks)
+/// - [Limiting the airtime of downlinks](#limiting-the-airtime-of-downlinks)
+/// - [Creating mqtt publish actions](#creating-mqtt-publish-actions)
+/// - [Creating webhook actions](#creating-webhook-actions)
 /// - [Start of a rule](#start-of-a-rule)
 /// ## Startup
 /// To use this module it is necessary to create a rule container.
@@ -343,7 +510,7 @@ pub mod devices;
 /// in the uplink message of the device in the chirpstack application sever or
 /// by using the function [`DeviceProfile::print_uplink`](devices::DeviceProfile::print_uplink)),<br/>
 /// the comparison operator and the value to compare to.
-///```
+///```ignore
 /// use elorapi::rules::{Condition, DeviceCondition, RefValue};
 ///
 /// // create first condition
@@ -362,7 +529,7 @@ pub mod devices;
 /// [`DeviceProfile::print_downlink`](devices::DeviceProfile::print_downlink)),<br/>
 /// the message encoded in hexadecimal, which should be sent,<br/>
 /// and the port to which the message should be sent to.
-///```
+///```ignore
 /// use elorapi::rules::Action;
 ///
 /// let indices = vec![0];
@@ -372,7 +539,7 @@ pub mod devices;
 /// ```
 ///  - __Create the actual rule__<br/>
 /// Here vectors for all conditions, boolean operators and actions are needed.
-///```
+///```ignore
 /// use elorapi::rules::Rule;
 ///
 /// let conditions = vec![condition_one, condition_two];
@@ -381,7 +548,7 @@ pub mod devices;
 /// let rule = Rule::new(conditons, boolean_operators, actions);
 /// ```
 ///  - __Add rule to the container__
-/// ```
+/// ```ignore
 /// rule_container.add_rule(rule);
 /// ```
 /// ## Creating conditions depending on time
@@ -394,16 +561,10 @@ pub mod devices;
 /// let timespan_start = NaiveTime::from_hms(8, 0, 0);
 /// let timespan_end = NaiveTime::from_hms(15, 0, 0);
 ///
-/// // condition monday
-/// let weekday = Some(Weekday::Mon);
-/// let time_condition = TimeCondition::new(weekday, timespan_start, timespan_end);
-/// let condition_mon = Condition::Time(time_condition);
-///
-/// // condition tuesday
-/// let condition_tue = Condition::Time(TimeCondition::new(Some(Weekday::Tue), timespan_start, timespan_end));
-///
-/// // condition wednesday
-/// let condition_tue = Condition::Time(TimeCondition::new(Some(Weekday::Wed), timespan_start, timespan_end));
+/// // condition from monday to wednesday
+/// let mut time_condition = TimeCondition::new(None, timespan_start, timespan_end);
+/// time_condition.set_weekdays(vec![Weekday::Mon, Weekday::Tue, Weekday::Wed]);
+/// let condition = Condition::Time(time_condition);
 /// ```
 /// The next example shows the condition, when you want an action to be executed everyday,
 /// but only in a specific timespan. Here between 11pm and 4pm.
@@ -415,6 +576,354 @@ pub mod devices;
 /// let timespan_end = NaiveTime::from_hms(16, 0, 0);
 /// let condition = Condition::Time(TimeCondition::new(None, timespan_start, timespan_end));
 /// ```
+/// The start and the end of the timespan are included to the minute.<br/>
+/// It is also possible to restrict a condition to a range of dates, which can be repeated every year,
+/// and to exclude single dates. In this example the condition is fulfilled every summer
+/// between 6am and 8am, but not on the first of August.
+///```
+/// use chrono::{NaiveDate, NaiveTime};
+/// use elorapi::rules::{Condition, TimeCondition};
+///
+/// let mut time_condition = TimeCondition::new(None, NaiveTime::from_hms(6, 0, 0), NaiveTime::from_hms(8, 0, 0));
+/// // the year is ignored, because the range is repeated every year
+/// time_condition.set_date_range(NaiveDate::from_ymd(2022, 6, 1), NaiveDate::from_ymd(2022, 8, 31), true);
+/// time_condition.add_exclusion(NaiveDate::from_ymd(2022, 8, 1));
+///
+/// // checks if the condition is fulfilled at a specific moment
+/// let fulfilled = time_condition.matches(&chrono::Local::now());
+/// let condition = Condition::Time(time_condition);
+/// ```
+/// By default time conditions are checked in the local time of the server.
+/// To check them independently of the server, a timezone can be set via its IANA name.
+/// The condition then follows the daylight saving time of the timezone
+/// (see [`TimeCondition::set_timezone`](rules::TimeCondition::set_timezone)).
+///```
+/// use chrono::NaiveTime;
+/// use elorapi::rules::{Condition, TimeCondition};
+///
+/// let mut time_condition = TimeCondition::new(None, NaiveTime::from_hms(6, 0, 0), NaiveTime::from_hms(8, 0, 0));
+/// time_condition.set_timezone("Europe/Berlin").unwrap();
+/// let condition = Condition::Time(time_condition);
+/// ```
+/// ## Creating conditions depending on sunrise and sunset
+/// The start and the end of the timespan can also be sunrise or sunset with an offset in minutes.
+/// They are computed for a location given in degrees or for the location of a device in Chirpstack.
+/// On days on which the sun does not rise or set, the condition is not fulfilled.<br/>
+/// In this example the condition is fulfilled from 30 minutes before sunset until sunrise.
+///```
+/// use chrono::NaiveTime;
+/// use elorapi::rules::{Condition, TimeBoundary, TimeCondition};
+/// # use elorapi::devices::Device;
+/// # fn example(device: Device) {
+///
+/// let mut time_condition = TimeCondition::new(None, NaiveTime::from_hms(0, 0, 0), NaiveTime::from_hms(0, 0, 0));
+/// time_condition.set_timespan(TimeBoundary::Sunset(-30), TimeBoundary::Sunrise(0));
+///
+/// // either the location in latitude and longitude
+/// time_condition.set_location(52.52, 13.40).unwrap();
+/// // or the location of a device, only with Chirpstack version 3
+/// # #[cfg(not(feature = "v4"))]
+/// time_condition.set_location_from_device(&device).unwrap();
+///
+/// let condition = Condition::Time(time_condition);
+/// # }
+/// ```
+/// ## Creating conditions depending on the silence of a device
+/// In this example a rule is created, which should be executed when a device has not sent
+/// an uplink message for two hours.
+/// The rule is checked every five seconds, as long as it only contains silence and time conditions.
+/// If it also contains device conditions, the silence is checked as well and the conditions on devices without a new message are not fulfilled.
+/// The messages received so far are kept, until there is a message of every device.
+///```
+/// use std::time::Duration;
+/// use elorapi::rules::{Condition, SilenceCondition};
+/// # use elorapi::devices::Device;
+/// # fn example(device: Device) {
+///
+/// let interval = Duration::from_secs(2 * 60 * 60);
+/// let condition = Condition::Silence(SilenceCondition::new(device, interval));
+/// # }
+/// ```
+/// ## Creating conditions depending on radio metadata
+/// Besides the measured data, the radio metadata of an uplink message can be used,
+/// e.g. to detect a degrading link or to use only messages sent on a specific port.
+/// For the possible metadata and the type of their thresholds see [`Metadata`](rules::Metadata).
+///```
+/// use elorapi::rules::{Condition, Metadata, MetadataCondition, RefValue};
+/// # use elorapi::devices::Device;
+/// # fn example(device: Device) {
+///
+/// // the best rssi of all gateways is lower than -115 dBm
+/// let condition_rssi = Condition::Metadata(MetadataCondition::new(device.clone(), Metadata::Rssi, "<".to_string(), RefValue::IntNumber(-115)));
+///
+/// // the message was sent on port 10
+/// let condition_port = Condition::Metadata(MetadataCondition::new(device.clone(), Metadata::FPort, "==".to_string(), RefValue::IntNumber(10)));
+///
+/// // more than 3 messages were lost since the last message
+/// let condition_gap = Condition::Metadata(MetadataCondition::new(device, Metadata::FCntGap, ">".to_string(), RefValue::IntNumber(3)));
+/// # }
+/// ```
+/// ## Creating conditions with arithmetic expressions
+/// An [`Expression`](rules::Expression) can be used as threshold to compare the data of a device
+/// with the scaled or shifted data of other devices.
+/// In expressions uplink data is written as `{device_index:uplink_index}`,
+/// where the device index is the index in the [`DeviceContainer`](devices::DeviceContainer).<br/>
+/// In this example the condition _temperature of device 0 - temperature of device 1 > 5_ is created,
+/// which is the same as _temperature of device 0 > temperature of device 1 + 5_.
+///```
+/// use elorapi::rules::{Condition, DeviceCondition, Expression, RefValue};
+/// # use elorapi::devices::DeviceContainer;
+/// # fn example(dev_container: DeviceContainer) {
+///
+/// // index of the temperature in the uplink payloads
+/// let index = 4;
+/// let expression = Expression::parse("{1:4} + 5", &dev_container).unwrap();
+/// let device = dev_container.get_device(0).unwrap();
+/// let condition = Condition::Device(DeviceCondition::new(device, index, ">".to_string(), RefValue::Expression(expression)));
+/// # }
+/// ```
+/// Possible are numbers, the operators `+`, `-`, `*`, `/`, parentheses and the functions
+/// `abs(x)`, `min(x, y)` and `max(x, y)`, e.g. `min({0:1} * 0.9, 80)`.
+/// ## Comparison operators
+/// Besides `<`, `<=`, `>`, `>=`, `==` and `!=` the following operators can be used in
+/// device and metadata conditions:
+///
+/// | Operator | Threshold | Fulfilled when the data is |
+/// |---|---|---|
+/// | `between` | [`RefValue::Range`](rules::RefValue::Range) | inside the range, including the bounds |
+/// | `outside` | [`RefValue::Range`](rules::RefValue::Range) | outside the range |
+/// | `in` | [`RefValue::List`](rules::RefValue::List) | equal to one of the values |
+/// | `not_in` | [`RefValue::List`](rules::RefValue::List) | equal to none of the values |
+/// | `~=` | [`RefValue::Tolerance`](rules::RefValue::Tolerance) | at most the tolerance away from the value |
+/// | `contains` | [`RefValue::String`](rules::RefValue::String) | a text containing the threshold |
+/// | `starts_with` | [`RefValue::String`](rules::RefValue::String) | a text starting with the threshold |
+/// | `ends_with` | [`RefValue::String`](rules::RefValue::String) | a text ending with the threshold |
+/// | `matches` | [`RefValue::Regex`](rules::RefValue::Regex) | a text matching the regular expression |
+///```
+/// use elorapi::rules::{Condition, DeviceCondition, RefValue};
+/// # use elorapi::devices::Device;
+/// # fn example(device: Device) {
+///
+/// // the temperature is between 18 and 22 degrees
+/// let condition_range = Condition::Device(DeviceCondition::new(device.clone(), 4, "between".to_string(), RefValue::Range((18.0, 22.0))));
+///
+/// // the state is either "open" or "half_open"
+/// let states = vec![RefValue::String("open".to_string()), RefValue::String("half_open".to_string())];
+/// let condition_list = Condition::Device(DeviceCondition::new(device.clone(), 3, "in".to_string(), RefValue::List(states)));
+///
+/// // the voltage is 3.3 V with a tolerance of 0.1 V
+/// let condition_voltage = Condition::Device(DeviceCondition::new(device.clone(), 5, "~=".to_string(), RefValue::Tolerance((3.3, 0.1))));
+///
+/// // the error code is an "E" followed by digits
+/// // the regular expression is compiled once and an invalid one is rejected
+/// let error_code = RefValue::regex("^E[0-9]+$").unwrap();
+/// let condition_error = Condition::Device(DeviceCondition::new(device, 6, "matches".to_string(), error_code));
+/// # }
+/// ```
+/// ## Creating scheduled rules
+/// Rules can also execute their actions at fixed times, independent of messages and conditions.
+/// The times are given by a cron expression (for the format see [`Schedule`](rules::Schedule)).<br/>
+/// In this example the heating is switched on every weekday at 6am in the timezone Europe/Berlin.
+///```
+/// use elorapi::rules::{Action, Rule, Schedule};
+/// # use elorapi::devices::Device;
+/// # use elorapi::rules::RuleContainer;
+/// # fn example(device: Device, rule_container: &mut RuleContainer) {
+///
+/// let mut schedule = Schedule::parse("0 6 * * MON-FRI").unwrap();
+/// schedule.set_timezone("Europe/Berlin").unwrap();
+///
+/// let action = Action::new(device, vec![0], "080100ff".to_string(), 55);
+/// let rule = Rule::new_scheduled(schedule, vec![action]);
+/// rule_container.add_rule(rule);
+/// # }
+/// ```
+/// Scheduled rules are started like all other rules (see [start of a rule](#start-of-a-rule)),
+/// or directly via [`RuleContainer::start_scheduled_execution`](rules::RuleContainer::start_scheduled_execution).
+/// ## Creating delayed actions
+/// Every action can wait for a delay before it is executed.
+/// The delay is counted from the previous action of the rule, for the first action from the moment the conditions are fulfilled.
+/// Therefore the actions of a rule form a sequence.
+/// While the actions are waiting, the rule keeps checking its conditions.
+/// When the rule is stopped, the waiting actions are dropped.<br/>
+/// In this example the light is switched on and switched off again after 10 minutes.
+///```
+/// use std::time::Duration;
+/// use elorapi::rules::{Action, Rule};
+/// # use elorapi::devices::Device;
+/// # use elorapi::rules::Condition;
+/// # fn example(device: Device, conditions: Vec<Condition>, bool_ops: Vec<String>) {
+///
+/// let light_on = Action::new(device.clone(), vec![0], "0801".to_string(), 55);
+/// let mut light_off = Action::new(device, vec![0], "0800".to_string(), 55);
+/// light_off.set_delay(Duration::from_secs(600));
+///
+/// let rule = Rule::new(conditions, bool_ops, vec![light_on, light_off]);
+/// # }
+/// ```
+/// ## Creating json object downlinks
+/// If the device profile of a device has a payload codec, e.g. a payload encoder script,
+/// the message can be given as JSON object, which is encoded by the Chirpstack Application Server.
+/// Then no [`Downlink`](devices::Downlink) specification is needed.
+///```
+/// use serde_json::json;
+/// use elorapi::rules::Action;
+/// # use elorapi::devices::{Device, DeviceProfile};
+/// # fn example(device: Device, device_profile: DeviceProfile) {
+///
+/// let message = json!({"setpoint": 21.5, "mode": "comfort"});
+/// let action = Action::new_json_object(device, message, 10, &device_profile).unwrap();
+/// # }
+/// ```
+/// ## Creating confirmed downlinks
+/// Downlinks to actuators can be confirmed by the device.
+/// The acknowledgements are published by the Chirpstack Application Server to the `ack` event topic,
+/// which is received by an [`AckTracker`](rules::AckTracker) and matched to the downlink by its frame counter.
+/// If the downlink is not acknowledged within the timeout, or the device does not acknowledge it,
+/// it is enqueued again as often as given by the retries.<br/>
+/// The result of every attempt is reported to the rule.
+///```
+/// use std::time::Duration;
+/// use elorapi::connections::Mqtt;
+/// use elorapi::rules::{AckTracker, Action, Rule};
+/// # use elorapi::devices::Device;
+/// # use elorapi::rules::Condition;
+/// # fn example(device: Device, conditions: Vec<Condition>, bool_ops: Vec<String>) {
+///
+/// let mqtt = Mqtt::new("uri", "username", "password");
+/// // the tracker can be cloned and used for all confirmed actions
+/// let tracker = AckTracker::start(mqtt.start_receiving_acks().unwrap());
+///
+/// // the valve is opened, if there is no acknowledgement within 10 minutes, it is tried 2 more times
+/// let action = Action::new_confirmed(device, vec![0], "0801".to_string(), 55, tracker, Duration::from_secs(600), 2);
+/// let rule = Rule::new(conditions, bool_ops, vec![action]);
+///
+/// // later on
+/// for report in rule.lock().unwrap().get_ack_reports() {
+///     println!("{} {:?}", report.get_f_cnt(), report.get_status());
+/// }
+/// # }
+/// ```
+/// ## Creating multicast actions
+/// To send the same message to many devices, e.g. to switch all lamps of a street,
+/// an action can enqueue the message into the queue of a multicast group of the Chirpstack Application Server.
+/// The message is built like the message for a single device,
+/// with the [`Downlink`](devices::Downlink) specification of the device profile of the devices in the group.
+///```
+/// use elorapi::rules::Action;
+///
+/// // the id of the multicast group in the Chirpstack Application Server
+/// let multicast_group_id = "9ee7b58b-0d2d-4b8a-8a13-6c0eb1a4e1f4";
+/// let action = Action::new_multicast(multicast_group_id, vec![0], "0801".to_string(), 55);
+/// ```
+/// ## Inspecting and flushing device queues
+/// The messages in the queue of a device, which are not sent yet, can be listed and removed.
+/// A downlink action can also replace these messages, so that only its newest message is sent.
+///```
+/// use elorapi::rules::{Action, QueuePolicy, RuleContainer};
+/// # use elorapi::connections::ChirpstackConnection;
+/// # use elorapi::devices::Device;
+/// # async fn example(device: Device, rule_container: &mut RuleContainer, chirpstack_connection: ChirpstackConnection) {
+///
+/// let client = rule_container.get_client().unwrap();
+/// let items = RuleContainer::list_queue(client.clone(), "0102030405060708", chirpstack_connection.clone()).await.unwrap();
+/// for item in items {
+///     println!("{} {:?}", item.f_port, item.data);
+/// }
+/// RuleContainer::flush_queue(client, "0102030405060708", chirpstack_connection.clone()).await.unwrap();
+///
+/// let mut action = Action::new(device, vec![0], "0815".to_string(), 55);
+/// action.set_queue_policy(QueuePolicy::ReplacePending).unwrap();
+/// # }
+/// ```
+/// ## Retrying failed downlinks
+/// If a downlink can not be enqueued due to a transient error, e.g. the Chirpstack Application Server is unavailable,
+/// it is tried again later, according to the [`RetryPolicy`](rules::RetryPolicy) of the rule.
+/// This applies to downlinks to devices and to multicast groups.
+/// Downlinks which ultimately failed can be added to a [`DeadLetterStore`](rules::DeadLetterStore),
+/// which is saved as JSON file. Later, they can be inspected and enqueued again.
+///```
+/// use std::time::Duration;
+/// use elorapi::rules::{DeadLetterStore, RetryPolicy};
+/// # use std::sync::{Arc, Mutex};
+/// # use elorapi::connections::ChirpstackConnection;
+/// # use elorapi::rules::{Rule, RuleContainer};
+/// # async fn example(rule: Arc<Mutex<Rule>>, rule_container: &mut RuleContainer, chirpstack_connection: ChirpstackConnection) {
+///
+/// let store = DeadLetterStore::open("dead_letters.json").unwrap();
+/// {
+///     let mut rule = rule.lock().unwrap();
+///     // 5 retries, waiting 2, 4, 8, 16 and 30 secs
+///     rule.set_retry_policy(RetryPolicy::new(5, Duration::from_secs(2), Duration::from_secs(30)));
+///     rule.set_dead_letter_store(store.clone());
+/// }
+///
+/// // later on
+/// for dead_letter in store.get_dead_letters() {
+///     println!("{} {}", dead_letter.get_dev_eui(), dead_letter.get_error());
+/// }
+/// let count = store.replay(rule_container.get_client().unwrap(), chirpstack_connection.clone()).await.unwrap();
+/// # }
+/// ```
+/// ## Limiting the airtime of downlinks
+/// Rules can enqueue downlinks as fast as their conditions are fulfilled,
+/// which quickly violates the duty cycle limits, e.g. in EU868.
+/// An [`AirtimeBudget`](rules::AirtimeBudget) estimates the airtime of every downlink
+/// and limits it per device and per gateway within a time window.
+/// Downlinks which exceed the budget are dropped or delayed, according to the [`BudgetPolicy`](rules::BudgetPolicy).
+/// The reason is printed in both cases.
+/// The gateway budget applies to the devices whose uplinks are observed,
+/// which are the devices in the conditions of the rules and the ones passed to [`AirtimeBudget::observe_uplink`](rules::AirtimeBudget::observe_uplink).<br/>
+/// In this example the duty cycle of every device is limited to 1 % and of every gateway to 10 %.
+///```
+/// use std::time::Duration;
+/// use elorapi::rules::{AirtimeBudget, BudgetPolicy};
+/// # use elorapi::devices::DeviceProfile;
+/// # use elorapi::rules::RuleContainer;
+/// # fn example(device_profile: DeviceProfile, rule_container: &mut RuleContainer) {
+///
+/// let budget = AirtimeBudget::new(Duration::from_secs(3600), Duration::from_secs(36), Duration::from_secs(360), BudgetPolicy::Coalesce);
+/// // the region and the data rate of the device profile are used to estimate the airtime
+/// budget.add_device_profile(&device_profile).unwrap();
+///
+/// // the budget is shared by all rules
+/// for rule in rule_container.get_rules() {
+///     rule.lock().unwrap().set_airtime_budget(budget.clone());
+/// }
+/// # }
+/// ```
+/// ## Creating mqtt publish actions
+/// Instead of enqueueing a downlink, an action can publish a JSON message to any topic of a mqtt broker,
+/// e.g. to trigger Node-RED or Home Assistant.
+/// The publisher is created from a [`Mqtt`](connections::Mqtt) connection and uses its own client.<br/>
+/// The message is a template, whose placeholders are replaced when the action is executed
+/// (for the placeholders see [`MqttPublishAction`](rules::MqttPublishAction)).
+///```no_run
+/// use elorapi::connections::Mqtt;
+/// use elorapi::rules::Action;
+///
+/// let mqtt = Mqtt::new("uri", "username", "password");
+/// let publisher = mqtt.create_publisher().unwrap();
+///
+/// let template = r#"{"rule": {{rule_id}}, "time": {{timestamp}}, "temperature": {{living-room.temperature}}}"#;
+/// let action = Action::new_mqtt_publish(publisher, "home/heating/alarm", template, 1).unwrap();
+/// ```
+/// ## Creating webhook actions
+/// An action can also post the rule id, the time and the field values of the checked devices as JSON to a http server
+/// (for the format see [`WebhookAction`](rules::WebhookAction)).
+/// If the connection fails, or the server answers with a server error or `429 Too Many Requests`, the message is sent again later.<br/>
+/// In this example a ticket is created in an alarm system, which is reached with a token.
+///```
+/// use std::time::Duration;
+/// use elorapi::rules::{Action, WebhookAction};
+///
+/// let mut webhook = WebhookAction::new("http://localhost:8080/tickets");
+/// webhook.add_header("Authorization", "Bearer token");
+/// webhook.set_timeout(Duration::from_secs(5));
+/// webhook.set_retries(5);
+///
+/// let action = Action::new_webhook(webhook);
+/// ```
 /// ## Start of a rule
 /// It is necessary to establish a [`Mqtt`](connections::Mqtt) connection and a [`ChirpstackConnection`](connections::ChirpstackConnection),
 /// when a rule should be executed.
@@ -426,7 +935,7 @@ pub mod devices;
 /// the established container connection
 /// and the established Chirpstack connection.<br/>
 ///
-///```
+///```ignore
 /// use elorapi::connections::{ChirpstackConnection, Mqtt};
 /// use elorapi::rules::RuleContainer;
 ///
@@ -1,20 +1,28 @@
 use std::borrow::{Borrow, BorrowMut};
-use chirpstack_api::as_pb::external::api::{Device as ChirpstackDevice, device_queue_service_client::DeviceQueueServiceClient, DeviceQueueItem, EnqueueDeviceQueueItemRequest};
-use crate::{connections::ChirpstackConnection, devices::{Device, DeviceContainer, DeviceProfile, DeviceProfileContainer}};
+use std::collections::HashMap;
+#[cfg(not(feature = "v4"))]
+use chirpstack_api::as_pb::external::api::{Device as ChirpstackDevice, device_queue_service_client::DeviceQueueServiceClient, DeviceQueueItem, EnqueueDeviceQueueItemRequest, ListDeviceQueueItemsRequest, FlushDeviceQueueRequest, multicast_group_service_client::MulticastGroupServiceClient, MulticastQueueItem, EnqueueMulticastQueueItemRequest};
+// the device queue is managed by the device service in Chirpstack version 4
+#[cfg(feature = "v4")]
+use chirpstack_api::api::{Device as ChirpstackDevice, device_service_client::DeviceServiceClient as DeviceQueueServiceClient, DeviceQueueItem, EnqueueDeviceQueueItemRequest, GetDeviceQueueItemsRequest, FlushDeviceQueueRequest, multicast_group_service_client::MulticastGroupServiceClient, MulticastGroupQueueItem, EnqueueMulticastGroupQueueItemRequest};
+use crate::{connections::{ChirpstackConnection, MqttPublisher}, events::{AckEvent, UplinkEvent}, devices::{Device, DeviceContainer, DeviceProfile, DeviceProfileContainer}};
 use std::{io, thread};
 use std::io::{BufRead, ErrorKind};
+use std::path::{Path, PathBuf};
 use std::ops::{BitAnd, BitOr, BitXor};
 use std::str::FromStr;
 use std::string::String;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, mpsc, atomic::{AtomicU64, Ordering}};
 use std::thread::{sleep};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use paho_mqtt::{Message};
 use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::{runtime::Handle, sync::watch::Receiver};
-use tonic::{metadata::MetadataValue, Request, transport::{Channel, Error}};
-use chrono::{Weekday, offset::Local, Datelike, NaiveTime};
+use tonic::{metadata::MetadataValue, Code, Request, Status, transport::{Channel, Error}};
+use chrono::{Weekday, offset::{Local, LocalResult, Utc}, Datelike, Timelike, DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use chrono_tz::Tz;
 
 
 /**
@@ -44,22 +52,25 @@ impl RuleGenerator {
         let mut stdin = io::stdin().lock();
         let mut buffer = String::with_capacity(2048);
         println!("\n...............................Selection of date and or time................................");
-        println!("Select the weekday, e.g. \"Mon\", \"Sunday\",...");
+        println!("Select the weekdays separated by commas, e.g. \"Mon\", \"Sunday\", \"Mon,Tue,Wed\",...");
         println!("\"All\" for everyday");
-        let mut weekday = None;
+        let mut weekdays = Vec::new();
         'date: loop {
             stdin.read_line(&mut buffer).expect("");
             buffer = buffer.replace("\n", "");
-            let res = Weekday::from_str(buffer.as_str());
             if buffer.as_str() != "All" {
-                weekday = match res {
-                    Ok(weekday) => Some(weekday),
-                    Err(_) => {
-                        buffer.clear();
-                        println!("Error while parsing date!\nPleas try again:");
-                        continue 'date
-                    }
-                };
+                for day in buffer.split(',') {
+                    let res = Weekday::from_str(day.trim());
+                    match res {
+                        Ok(weekday) => weekdays.push(weekday),
+                        Err(_) => {
+                            buffer.clear();
+                            weekdays.clear();
+                            println!("Error while parsing date!\nPleas try again:");
+                            continue 'date
+                        }
+                    };
+                }
             }
             break;
         }
@@ -72,12 +83,12 @@ impl RuleGenerator {
                 _ => {}
             }
 
-            println!("In format of hh:mm!");
+            println!("In format of hh:mm, or \"sunrise\" or \"sunset\" with an optional offset in minutes, e.g. \"sunset-30\"!");
 
             buffer.clear();
             stdin.read_line(&mut buffer).expect("");
             buffer = buffer.replace("\n", "");
-            let result = NaiveTime::parse_from_str(buffer.as_str(), "%H:%M");
+            let result = TimeBoundary::from_str(buffer.as_str());
             match result {
                 Ok(timespan) => {
                     timespans.push(timespan);
@@ -89,8 +100,89 @@ impl RuleGenerator {
                 }
             }
         }
+        let mut time_condition = TimeCondition::new(None, NaiveTime::from_hms(0, 0, 0), NaiveTime::from_hms(0, 0, 0));
+        time_condition.set_timespan(timespans[0], timespans[1]);
+        time_condition.set_weekdays(weekdays);
+
+        if time_condition.is_astronomical() {
+            println!("Select the location for sunrise and sunset!");
+            println!("In format of latitude longitude in degrees, e.g. \"52.52 13.40\":");
+            loop {
+                buffer.clear();
+                stdin.read_line(&mut buffer).expect("");
+                buffer = buffer.replace("\n", "");
+                let coordinates: Vec<Result<f64, _>> = buffer.split_whitespace().map(|c| c.parse::<f64>()).collect();
+                if let [Ok(latitude), Ok(longitude)] = coordinates.as_slice() {
+                    if time_condition.set_location(*latitude, *longitude).is_ok() {
+                        break;
+                    }
+                }
+                println!("Error while parsing location!\nPleas try again:");
+            }
+        }
+
+        println!("Select the range of dates in which the condition should be active!");
+        println!("In format of yyyy-mm-dd yyyy-mm-dd, e.g. \"2022-05-01 2022-09-30\"; empty for every date.");
+        'date_range: loop {
+            buffer.clear();
+            stdin.read_line(&mut buffer).expect("");
+            buffer = buffer.replace("\n", "");
+            if buffer.is_empty() {
+                break;
+            }
+            let dates: Vec<Result<NaiveDate, _>> = buffer.split_whitespace().map(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d")).collect();
+            if let [Ok(start), Ok(end)] = dates.as_slice() {
+                println!("Should the range be repeated every year? (y/n)");
+                buffer.clear();
+                stdin.read_line(&mut buffer).expect("");
+                time_condition.set_date_range(*start, *end, buffer.as_str() == "y\n");
+                break;
+            }
+            println!("Error while parsing date range!\nPleas try again:");
+            continue 'date_range
+        }
+
+        println!("Select the timezone in which the condition should be checked, e.g. \"Europe/Berlin\" or \"UTC\";");
+        println!("empty for the local time of the server.");
+        loop {
+            buffer.clear();
+            stdin.read_line(&mut buffer).expect("");
+            buffer = buffer.replace("\n", "");
+            if buffer.is_empty() {
+                break;
+            }
+            match time_condition.set_timezone(buffer.trim()) {
+                Ok(()) => break,
+                Err(_) => println!("Error while parsing timezone!\nPleas try again:"),
+            }
+        }
+
+        println!("Select the dates on which the condition should never be active, separated by commas!");
+        println!("In format of yyyy-mm-dd, e.g. \"2022-12-24,2022-12-25\"; empty for none.");
+        'exclusions: loop {
+            buffer.clear();
+            stdin.read_line(&mut buffer).expect("");
+            buffer = buffer.replace("\n", "");
+            if buffer.is_empty() {
+                break;
+            }
+            let mut exclusions = Vec::new();
+            for date in buffer.split(',') {
+                match NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d") {
+                    Ok(date) => exclusions.push(date),
+                    Err(_) => {
+                        println!("Error while parsing dates!\nPleas try again:");
+                        continue 'exclusions
+                    }
+                }
+            }
+            for date in exclusions {
+                time_condition.add_exclusion(date);
+            }
+            break;
+        }
         println!("............................................................................................");
-        return Ok(TimeCondition::new(weekday, timespans[0], timespans[1]))
+        return Ok(time_condition)
     }
 
     /// Selecting a [`Device`] via cmd.
@@ -201,6 +293,9 @@ impl RuleGenerator {
         println!("\n..............................Selection of comparison operators.............................");
         println!("Select Comparison Operator for payload with index {} and following name:", uplink);
         println!("{:?}:", device_profile.get_uplink().unwrap().get_payloads()[uplink]);
+        println!("Possible are <, >, <=, >=, ==, !=,");
+        println!("\"between\" and \"outside\" of a range, \"in\" and \"not_in\" a list of values, \"~=\" for approximately equal,");
+        println!("and for text \"contains\", \"starts_with\", \"ends_with\" and \"matches\" for a regular expression.");
         buffer.clear();
         stdin.read_line(&mut buffer).expect("");
         buffer = buffer.replace("\n", "");
@@ -212,6 +307,15 @@ impl RuleGenerator {
                 ">=" => ">=".to_string(),
                 "==" => "==".to_string(),
                 "!=" => "!=".to_string(),
+                "between" => "between".to_string(),
+                "outside" => "outside".to_string(),
+                "in" => "in".to_string(),
+                "not_in" => "not_in".to_string(),
+                "~=" => "~=".to_string(),
+                "contains" => "contains".to_string(),
+                "starts_with" => "starts_with".to_string(),
+                "ends_with" => "ends_with".to_string(),
+                "matches" => "matches".to_string(),
                 _ => {
                     println!("There was a Error while parsing!\nDo you want to try again? (y/n)");
                     buffer.clear();
@@ -232,11 +336,104 @@ impl RuleGenerator {
         }
     }
 
+    /// Selecting a floating point number via cmd.
+    fn select_float(name: &str) -> Result<f32, ()> {
+        let mut stdin = io::stdin().lock();
+        let mut buffer = String::with_capacity(2048);
+        loop {
+            println!("{}:", name);
+            buffer.clear();
+            stdin.read_line(&mut buffer).expect("");
+            buffer = buffer.replace("\n", "");
+            let res = buffer.parse::<f32>();
+            match res {
+                Ok(float) => return Ok(float),
+                Err(_) => {
+                    println!("There was a Error while parsing!\nDo you want to try again? (y/n)");
+                    buffer.clear();
+                    stdin.read_line(&mut buffer).expect("");
+                    match buffer.as_str() {
+                        "y\n" => continue,
+                        _ => return Err(()),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Parses a single threshold value, which is neither an uplink nor an expression.
+    fn parse_ref_value(text: &str) -> RefValue {
+        if let Ok(int) = text.parse::<i32>() {
+            return RefValue::IntNumber(int);
+        }
+        if let Ok(float) = text.parse::<f32>() {
+            return RefValue::FloatNumber(float);
+        }
+        if let Ok(bool) = text.parse::<bool>() {
+            return RefValue::Bool(bool);
+        }
+        return RefValue::String(text.to_string());
+    }
+
+    /// Selecting threshold for the operators `between`, `outside`, `in`, `not_in`, `~=` and `matches` via cmd.
+    fn select_operator_threshold(operator: &str) -> Result<RefValue, ()> {
+        match operator {
+            "between" | "outside" => {
+                println!("Range in which the data should be (for \"between\") or should not be (for \"outside\").");
+                let lower = RuleGenerator::select_float("Lower bound")?;
+                let upper = RuleGenerator::select_float("Upper bound")?;
+                Ok(RefValue::Range((lower, upper)))
+            },
+            "in" | "not_in" => {
+                let mut stdin = io::stdin().lock();
+                let mut buffer = String::with_capacity(2048);
+                println!("Values separated by commas, e.g. \"open,closed\" or \"1,2,3\":");
+                stdin.read_line(&mut buffer).expect("");
+                buffer = buffer.replace("\n", "");
+                let values = buffer.split(',').map(|value| RuleGenerator::parse_ref_value(value.trim())).collect();
+                Ok(RefValue::List(values))
+            },
+            "~=" => {
+                let value = RuleGenerator::select_float("Value")?;
+                let tolerance = RuleGenerator::select_float("Tolerance")?;
+                Ok(RefValue::Tolerance((value, tolerance)))
+            },
+            "matches" => {
+                let mut stdin = io::stdin().lock();
+                let mut buffer = String::with_capacity(2048);
+                loop {
+                    println!("Regular expression, e.g. \"^E[0-9]+$\":");
+                    buffer.clear();
+                    stdin.read_line(&mut buffer).expect("");
+                    buffer = buffer.replace("\n", "");
+                    match RefValue::regex(buffer.as_str()) {
+                        Ok(regex) => return Ok(regex),
+                        Err(e) => {
+                            println!("{}\nDo you want to try again? (y/n)", e.to_string());
+                            buffer.clear();
+                            stdin.read_line(&mut buffer).expect("");
+                            match buffer.as_str() {
+                                "y\n" => continue,
+                                _ => return Err(()),
+                            }
+                        }
+                    }
+                }
+            },
+            _ => Err(()),
+        }
+    }
+
     /// Selecting threshold via cmd.
-    fn select_threshold(uplink:usize, device_profile: &mut DeviceProfile, dev_container: &mut DeviceContainer) -> Result<RefValue, ()> {
+    fn select_threshold(uplink:usize, operator: &str, device_profile: &mut DeviceProfile, dev_container: &mut DeviceContainer) -> Result<RefValue, ()> {
         let stdin = io::stdin();
         let mut buffer = String::with_capacity(2048);
         println!("\n...................................Selection of threshold...................................");
+        if ["between", "outside", "in", "not_in", "~=", "matches"].contains(&operator) {
+            let res = RuleGenerator::select_operator_threshold(operator);
+            println!("............................................................................................");
+            return res;
+        }
         let re_i32 = Regex::new(r"\d+").unwrap();
         let re_f32 = Regex::new(r"\d+[.]\d+").unwrap();
         let re_bool= Regex::new("false|true").unwrap();
@@ -245,6 +442,7 @@ impl RuleGenerator {
         'threshold: loop {
             println!("Threshold:");
             println!("Type \"device\" if an uplink from another device should be use as threshold!");
+            println!("Type \"expression\" if an arithmetic expression should be used as threshold!");
             let par: RefValue;
             buffer.clear();
             stdin.read_line(&mut buffer).expect("");
@@ -263,6 +461,28 @@ impl RuleGenerator {
                 };
 
                 par = RefValue::Uplink((device, uplink_index));
+            } else if buffer_str.eq("expression") {
+                println!("Uplink data is written as {{device_index:uplink_index}}, e.g. \"{{0:2}} - {{1:2}} + 5\".");
+                println!("Possible are numbers, +, -, *, /, parentheses, abs(x), min(x, y) and max(x, y).");
+                println!("Expression:");
+                buffer.clear();
+                stdin.read_line(&mut buffer).expect("");
+                buffer = buffer.replace("\n", "");
+                let res = Expression::parse(buffer.as_str(), dev_container);
+                par = match res {
+                    Ok(expression) => RefValue::Expression(expression),
+                    Err(e) => {
+                        println!("{}\nDo you want to try again? (y/n)", e.to_string());
+                        buffer.clear();
+                        stdin.read_line(&mut buffer).expect("");
+                        match buffer.as_str() {
+                            "y\n" => {
+                                continue 'threshold
+                            },
+                            _ => return Err(()),
+                        }
+                    }
+                };
             } else if re_f32.is_match(buffer_str) {
                 let res = buffer.parse::<f32>();
                 par = match res {
@@ -431,6 +651,53 @@ impl RuleGenerator {
         }
     }
 
+    /// Selecting multicast group via cmd; `None` if the message is sent to the device only.
+    fn select_multicast_group() -> Option<String> {
+        let mut stdin = io::stdin().lock();
+        let mut buffer = String::with_capacity(2048);
+        println!("..............................Selection of multicast group.................................");
+        println!("Id of the multicast group, to which the message should be sent (empty for the device only):");
+        stdin.read_line(&mut buffer).expect("");
+        buffer = buffer.replace("\n", "");
+        println!("............................................................................................");
+        if buffer.is_empty() {
+            return None;
+        }
+        return Some(buffer);
+    }
+
+    /// Selecting delay of an action via cmd.
+    fn select_delay() -> Result<Duration, ()> {
+        let mut stdin = io::stdin().lock();
+        let mut buffer = String::with_capacity(2048);
+        println!("..................................Selection of delay........................................");
+        println!("Seconds to wait before the action is executed, after the previous action (empty for none):");
+        loop {
+            buffer.clear();
+            stdin.read_line(&mut buffer).expect("");
+            buffer = buffer.replace("\n", "");
+            if buffer.is_empty() {
+                println!("............................................................................................");
+                return Ok(Duration::from_secs(0));
+            }
+            let res = buffer.parse::<u64>();
+            let delay = match res {
+                Ok(secs) => Duration::from_secs(secs),
+                Err(_) => {
+                    println!("There was a Error while parsing!\nDo you want to try again? (y/n)");
+                    buffer.clear();
+                    stdin.read_line(&mut buffer).expect("");
+                    match buffer.as_str() {
+                        "y\n" => continue ,
+                        _ => return Err(()),
+                    }
+                }
+            };
+            println!("............................................................................................");
+            return Ok(delay);
+        }
+    }
+
     /// Changing [`Downlink`](crate::devices::Downlink) command to actual message.
     fn downlink_message(device_profile: &mut DeviceProfile, payload_indices: Vec<usize>) -> String {
         let mut downlink = device_profile.get_downlink().unwrap();
@@ -551,7 +818,7 @@ impl RuleGenerator {
                 };
 
                 // select threshold
-                let thresh_res = RuleGenerator::select_threshold(uplink_copy.clone(), &mut device_profile, dev_container);
+                let thresh_res = RuleGenerator::select_threshold(uplink_copy.clone(), &op, &mut device_profile, dev_container);
                 let thresh = match thresh_res {
                     Ok(threshold) => threshold,
                     Err(()) => {
@@ -643,7 +910,24 @@ impl RuleGenerator {
                 }
             };
 
-            let action = Action::new(act_dev, down, hex_message, f_port);
+            // select delay
+            let delay_res = RuleGenerator::select_delay();
+            let delay = match delay_res {
+                Ok(delay) => delay,
+                Err(()) => {
+                    RuleGenerator::end_rule_generator();
+                    return
+                }
+            };
+
+            // the message can be sent to a multicast group, which shares the device profile of the device
+            let multicast_group = RuleGenerator::select_multicast_group();
+
+            let mut action = match multicast_group {
+                Some(multicast_group_id) => Action::new_multicast(&multicast_group_id, down, hex_message, f_port),
+                None => Action::new(act_dev, down, hex_message, f_port),
+            };
+            action.set_delay(delay);
             actions.push(action);
 
             println!("Do you want to add another action? (y/n)");
@@ -676,6 +960,251 @@ pub enum RefValue {
     FloatNumber(f32),
     Bool(bool),
     Uplink((Device, usize)),
+    Expression(Expression),
+    /// Lower and upper bound of a range, including the bounds.
+    /// For the operators `between` and `outside`.
+    Range((f32, f32)),
+    /// List of values for the operators `in` and `not_in`.
+    List(Vec<RefValue>),
+    /// Value and tolerance for the operator `~=`.
+    Tolerance((f32, f32)),
+    /// Regular expression for the operator `matches`, created via [`RefValue::regex`].
+    Regex(Regex),
+}
+
+impl RefValue {
+    /// Creates the threshold for the operator `matches` out of a regular expression.<br/>
+    /// Returns an error of kind `InvalidInput`, if the regular expression is invalid.
+    pub fn regex(pattern: &str) -> Result<RefValue, io::Error> {
+        match Regex::new(pattern) {
+            Ok(regex) => Ok(RefValue::Regex(regex)),
+            Err(e) => Err(io::Error::new(ErrorKind::InvalidInput, e)),
+        }
+    }
+}
+
+/**
+    Arithmetic expression over constants and uplink data, which can be used as threshold.
+
+    Expressions can be created directly or parsed via [`Expression::parse`].
+    The uplink data is always read as floating point number.
+*/
+#[derive(Debug, Clone)]
+pub enum Expression {
+    /// A constant number.
+    Constant(f32),
+    /// The data of the [`Uplink`](crate::devices::Uplink) payload with the index of the device.
+    Uplink((Device, usize)),
+    /// Sum of two expressions.
+    Add(Box<Expression>, Box<Expression>),
+    /// Difference of two expressions.
+    Sub(Box<Expression>, Box<Expression>),
+    /// Product of two expressions.
+    Mul(Box<Expression>, Box<Expression>),
+    /// Quotient of two expressions.
+    Div(Box<Expression>, Box<Expression>),
+    /// Absolute value of an expression.
+    Abs(Box<Expression>),
+    /// Minimum of two expressions.
+    Min(Box<Expression>, Box<Expression>),
+    /// Maximum of two expressions.
+    Max(Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    /// Parses an expression out of a text.<br/>
+    /// Possible are numbers, the operators `+`, `-`, `*`, `/`, parentheses and the functions
+    /// `abs(x)`, `min(x, y)` and `max(x, y)`.
+    /// Uplink data is written as `{device_index:uplink_index}`, where the device index is the index
+    /// of the device in the `DeviceContainer` and the uplink index the index of the uplink payload,
+    /// e.g. `{0:2} - {1:2}` or `min({0:1} * 0.9, 80)`.<br/>
+    /// For an example see this [link](./index.html#creating-conditions-with-arithmetic-expressions).
+    pub fn parse(text: &str, dev_container: &DeviceContainer) -> Result<Expression, io::Error> {
+        let mut parser = ExpressionParser {
+            chars: text.chars().filter(|c| !c.is_whitespace()).collect(),
+            position: 0,
+            dev_container,
+        };
+        let expression = parser.parse_sum()?;
+        if parser.position < parser.chars.len() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("Unexpected character '{}' in expression!", parser.chars[parser.position])));
+        }
+        Ok(expression)
+    }
+
+    /// Evaluates the expression.<br/>
+    /// `value_of` gets the data of the uplink payload with the index of a device;
+    /// `None` if the data is not available.
+    /// Returns `None` if data is not available or if it is divided by zero.
+    pub fn evaluate<F: FnMut(&Device, usize) -> Option<f32>>(&self, value_of: &mut F) -> Option<f32> {
+        let value = match self {
+            Expression::Constant(value) => *value,
+            Expression::Uplink((device, index)) => value_of(device, *index)?,
+            Expression::Add(a, b) => a.evaluate(value_of)? + b.evaluate(value_of)?,
+            Expression::Sub(a, b) => a.evaluate(value_of)? - b.evaluate(value_of)?,
+            Expression::Mul(a, b) => a.evaluate(value_of)? * b.evaluate(value_of)?,
+            Expression::Div(a, b) => {
+                let divisor = b.evaluate(value_of)?;
+                if divisor == 0.0 {
+                    return None;
+                }
+                a.evaluate(value_of)? / divisor
+            },
+            Expression::Abs(a) => a.evaluate(value_of)?.abs(),
+            Expression::Min(a, b) => a.evaluate(value_of)?.min(b.evaluate(value_of)?),
+            Expression::Max(a, b) => a.evaluate(value_of)?.max(b.evaluate(value_of)?),
+        };
+        Some(value)
+    }
+
+    /// Gets all devices whose uplink data is used in the expression.
+    pub fn get_devices(&self) -> Vec<&Device> {
+        match self {
+            Expression::Constant(_) => Vec::new(),
+            Expression::Uplink((device, _)) => vec![device],
+            Expression::Abs(a) => a.get_devices(),
+            Expression::Add(a, b) | Expression::Sub(a, b) | Expression::Mul(a, b) | Expression::Div(a, b)
+            | Expression::Min(a, b) | Expression::Max(a, b) => {
+                let mut devices = a.get_devices();
+                devices.append(&mut b.get_devices());
+                devices
+            },
+        }
+    }
+}
+
+/// Recursive descent parser for [`Expression`]s.
+struct ExpressionParser<'a> {
+    /// The text of the expression without whitespaces.
+    chars: Vec<char>,
+    /// Position of the next character.
+    position: usize,
+    /// Container with the devices which can be referred to.
+    dev_container: &'a DeviceContainer,
+}
+
+impl<'a> ExpressionParser<'a> {
+
+    /// Gets the next character without consuming it.
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    /// Consumes the next character, if it is the expected one.
+    fn expect(&mut self, expected: char) -> Result<(), io::Error> {
+        if self.peek() != Some(expected) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("'{}' was expected in expression!", expected)));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    /// Parses a sum or difference of products.
+    fn parse_sum(&mut self) -> Result<Expression, io::Error> {
+        let mut expression = self.parse_product()?;
+        loop {
+            match self.peek() {
+                Some('+') => {
+                    self.position += 1;
+                    expression = Expression::Add(Box::new(expression), Box::new(self.parse_product()?));
+                },
+                Some('-') => {
+                    self.position += 1;
+                    expression = Expression::Sub(Box::new(expression), Box::new(self.parse_product()?));
+                },
+                _ => return Ok(expression),
+            }
+        }
+    }
+
+    /// Parses a product or quotient of factors.
+    fn parse_product(&mut self) -> Result<Expression, io::Error> {
+        let mut expression = self.parse_factor()?;
+        loop {
+            match self.peek() {
+                Some('*') => {
+                    self.position += 1;
+                    expression = Expression::Mul(Box::new(expression), Box::new(self.parse_factor()?));
+                },
+                Some('/') => {
+                    self.position += 1;
+                    expression = Expression::Div(Box::new(expression), Box::new(self.parse_factor()?));
+                },
+                _ => return Ok(expression),
+            }
+        }
+    }
+
+    /// Parses a number, uplink data, a function, a negation or an expression in parentheses.
+    fn parse_factor(&mut self) -> Result<Expression, io::Error> {
+        match self.peek() {
+            Some('-') => {
+                self.position += 1;
+                let factor = self.parse_factor()?;
+                Ok(Expression::Sub(Box::new(Expression::Constant(0.0)), Box::new(factor)))
+            },
+            Some('(') => {
+                self.position += 1;
+                let expression = self.parse_sum()?;
+                self.expect(')')?;
+                Ok(expression)
+            },
+            Some('{') => {
+                self.position += 1;
+                let device_index = self.parse_index()?;
+                self.expect(':')?;
+                let uplink_index = self.parse_index()?;
+                self.expect('}')?;
+                let device = self.dev_container.get_device(device_index)?;
+                Ok(Expression::Uplink((device, uplink_index)))
+            },
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let start = self.position;
+                while self.peek().map_or(false, |c| c.is_ascii_digit() || c == '.') {
+                    self.position += 1;
+                }
+                let number: String = self.chars[start..self.position].iter().collect();
+                let value = number.parse::<f32>().map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+                Ok(Expression::Constant(value))
+            },
+            Some(c) if c.is_ascii_alphabetic() => {
+                let start = self.position;
+                while self.peek().map_or(false, |c| c.is_ascii_alphabetic()) {
+                    self.position += 1;
+                }
+                let name: String = self.chars[start..self.position].iter().collect();
+                self.expect('(')?;
+                let first = self.parse_sum()?;
+                let expression = match name.as_str() {
+                    "abs" => Expression::Abs(Box::new(first)),
+                    "min" | "max" => {
+                        self.expect(',')?;
+                        let second = self.parse_sum()?;
+                        if name == "min" {
+                            Expression::Min(Box::new(first), Box::new(second))
+                        } else {
+                            Expression::Max(Box::new(first), Box::new(second))
+                        }
+                    },
+                    _ => return Err(io::Error::new(ErrorKind::InvalidInput, format!("Unknown function '{}' in expression!", name))),
+                };
+                self.expect(')')?;
+                Ok(expression)
+            },
+            Some(c) => Err(io::Error::new(ErrorKind::InvalidInput, format!("Unexpected character '{}' in expression!", c))),
+            None => Err(io::Error::new(ErrorKind::InvalidInput, "Unexpected end of expression!")),
+        }
+    }
+
+    /// Parses an index of a device or an uplink payload.
+    fn parse_index(&mut self) -> Result<usize, io::Error> {
+        let start = self.position;
+        while self.peek().map_or(false, |c| c.is_ascii_digit()) {
+            self.position += 1;
+        }
+        let index: String = self.chars[start..self.position].iter().collect();
+        index.parse::<usize>().map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Index in expression could not be parsed!"))
+    }
 }
 
 /**
@@ -720,45 +1249,54 @@ impl RuleContainer {
         println!("Start rule execution...");
         let safe = Arc::clone(&arc_rule);
         let mut rule = safe.lock().unwrap();
+        // scheduled rules do not depend on messages
+        if rule.schedule.is_some() {
+            drop(rule);
+            return RuleContainer::start_scheduled_execution(arc_rule, client, connection);
+        }
         rule.running = true;
         let mut topics = Vec::new();
         let mut qos = Vec::new();
 
+        // topics of devices, which are watched for silence
+        let mut silence_topics = Vec::new();
+
         for i in &rule.conditions {
-            if let Condition::Device(device_condition) = i {
-                // get device and the respective topic, then add topic to vector
-                let device: ChirpstackDevice = device_condition.device.get_chirpstack_device().device.unwrap();
-                let dev_eui = device.dev_eui;
-                let application_id = device.application_id;
-                let topic = "application/".to_owned() + &application_id.to_string() + "/device/" + &dev_eui + "/event/up";
+            // get the topics of the devices of the condition, then add them to the vector
+            for topic in RuleContainer::condition_topics(i) {
                 if !topics.contains(&topic) {
                     topics.push(topic);
                     qos.push(0);
                 }
-
-                // if threshold is a device, then add the topic of this device also to the vector
-                if let RefValue::Uplink(de) = &device_condition.threshold {
-                    let dev = de.0.get_chirpstack_device().device.unwrap();
-                    let de_eui = dev.dev_eui;
-                    let de_application_id = dev.application_id;
-                    let de_topic = "application/".to_owned() + &de_application_id.to_string() + "/device/" + &de_eui + "/event/up";
-                    if !topics.contains(&de_topic) {
-                        topics.push(de_topic);
-                        qos.push(0);
-                    }
+            }
+            if let Condition::Silence(silence_condition) = i {
+                let topic = RuleContainer::uplink_topic(&silence_condition.device);
+                if !silence_topics.contains(&topic) {
+                    silence_topics.push(topic);
                 }
             }
         }
 
         let mut bool_ops:Vec<fn(bool, bool)->bool> = Vec::new();
         let mut messages = Vec::new();
+        // time of the last uplink of every watched device, the start of the rule counts as first uplink
+        let mut last_uplinks: HashMap<String, Instant> = HashMap::new();
+        for topic in &silence_topics {
+            last_uplinks.insert(topic.clone(), Instant::now());
+        }
+        // frame counter of the last uplink of every topic, to detect gaps
+        let mut last_f_cnts: HashMap<String, i64> = HashMap::new();
         let dev_profile_container = Arc::clone(&dev_profile_container);
         let safe = Arc::clone(&arc_rule);
         let handle = Handle::current();
         let handler = thread::spawn(move || {
-            println!("Waiting for rule...");
-            let mut rule = safe.lock().unwrap();
-            'control: while rule.running {
+            // actions which are executed later and confirmed downlinks which wait for their acknowledgement
+            let mut queue = ActionQueue::new(handle, client, connection);
+            // the rule is only locked shortly while waiting, so that it can be stopped
+            'control: loop {
+                if !safe.lock().unwrap().running {
+                    break 'control
+                }
                 /*
                  *  The vector 'messages' should contain as less messages as possible,
                  *  therefore if two or more conditions share the same mqtt topic,
@@ -770,8 +1308,31 @@ impl RuleContainer {
                  *  the old one will be replaced with the new one.
                  */
                 println!("Waiting for messages to arrive...");
-                // there are as many messages required, as there are topics
-                while messages.len() < topics.len() {
+                let mut waited = false;
+                loop {
+                    {
+                        let mut rule = safe.lock().unwrap();
+                        if !rule.running {
+                            break 'control
+                        }
+                        queue.execute_due(&mut rule);
+
+                        // there are as many messages required, as there are topics
+                        if !topics.is_empty() && messages.len() >= topics.len() {
+                            break
+                        }
+                        // the silence of a device is detected by time and not by messages, so it is checked on every wake-up,
+                        // but the rule waits at least one round, so that it is not checked continuously
+                        if waited && !silence_topics.is_empty() && RuleContainer::is_any_silent(&rule.conditions, &last_uplinks) {
+                            break
+                        }
+                        // a rule without conditions on devices is checked every round
+                        if waited && topics.is_empty() && silence_topics.is_empty() {
+                            break
+                        }
+                    }
+                    waited = true;
+
                     // check if there are new messages in the channel
                     let res_seen = receiver.has_changed();
                     let seen = match res_seen {
@@ -781,23 +1342,26 @@ impl RuleContainer {
                             continue
                         },
                     };
-                    // if there are no new messages sleep for 5 secs
+                    // if there are no new messages sleep for 5 secs, or until the next action is due
                     if !seen {
-                        sleep(Duration::from_secs(5));
+                        sleep(queue.waiting_time());
                         continue
                     }
                     // copy the message out of the channel
                     let message = receiver.borrow_and_update().clone();
+                    // every uplink, even without data, shows that a watched device is not silent
+                    if let Some(last_uplink) = last_uplinks.get_mut(message.topic()) {
+                        *last_uplink = Instant::now();
+                    }
                     // if there is no data sleep for 5 secs
-                    let pat = "\"data\":null";
-                    let res = message.payload_str().find(pat);
-                    if let Some(_) = res {
-                        sleep(Duration::from_secs(5));
+                    let has_data = UplinkEvent::parse(&message.payload_str()).map_or(false, |event| event.has_data());
+                    if !has_data {
+                        sleep(queue.waiting_time());
                         continue
                     }
                     // if the message from the channel is not for one of the topics than sleep for 5 secs
                     if !topics.contains(&message.topic().to_string().clone()) {
-                        sleep(Duration::from_secs(5));
+                        sleep(queue.waiting_time());
                         continue
                     }
                     // if vector 'messages' is empty, then add message to vector
@@ -822,14 +1386,24 @@ impl RuleContainer {
                         }
                     }
                 }
+                // when the rule is woken by silence, the messages are kept until there is one of every topic
+                let complete = !topics.is_empty() && messages.len() >= topics.len();
 
+                let mut rule = safe.lock().unwrap();
                 let mut bool_vec: Vec<bool> = Vec::new();
+                // topics of watched devices, which are silent at the moment
+                let mut silent_topics: Vec<String> = Vec::new();
                 {
                     println!("Waiting for container...");
                     let mut dev_prof_container = dev_profile_container.lock().unwrap();
 
                     println!("Begin to check rule conditions...");
                     for i in &rule.conditions {
+                        // when the rule is woken by silence, a condition on a device without a new uplink is not fulfilled
+                        if !RuleContainer::has_messages(&messages, i) {
+                            bool_vec.push(false);
+                            continue
+                        }
                         if let Condition::Device(device_condition) = i {
                             // get the id of the device, the index of the device in chirpsatck
                             // and the name of the filed in the uplink message
@@ -941,6 +1515,64 @@ impl RuleContainer {
                                 };
                                 let bool_res = op(&measured_data, &threshold);
                                 bool_vec.push(bool_res);
+                            } else if let RefValue::Range(_) | RefValue::List(_) | RefValue::Tolerance(_) | RefValue::Regex(_) = &device_condition.threshold {
+                                let result = RuleContainer::compare_value(&payload[uplink_name], device_condition.operator.clone(), &device_condition.threshold);
+                                let bool_res = match result {
+                                    Ok(bool) => bool,
+                                    Err(e) if e.kind() == ErrorKind::InvalidInput => {
+                                        println!("Error occurred: {}\nStopping rule!", e.to_string());
+                                        rule.running = false;
+                                        continue 'control
+                                    },
+                                    Err(_) => {
+                                        println!("Error occurred while reading message.");
+                                        messages.clear();
+                                        bool_ops.clear();
+                                        continue 'control
+                                    },
+                                };
+                                bool_vec.push(bool_res);
+                            } else if let RefValue::Expression(expression) = &device_condition.threshold {
+                                let op_res = 0.0f32.get_operator(device_condition.operator.clone());
+                                let op = match op_res {
+                                    Ok(op) => op,
+                                    Err(()) => {
+                                        println!("Error occurred: Operator could not be parsed!\nStopping rule!");
+                                        rule.running = false;
+                                        continue 'control
+                                    }
+                                };
+
+                                let measured_data = match payload[uplink_name].as_f64() {
+                                    Some(val) => val as f32,
+                                    None => {
+                                        println!("Error occurred while reading message.");
+                                        messages.clear();
+                                        bool_ops.clear();
+                                        continue 'control
+                                    }
+                                };
+
+                                // reads the data of the uplink payloads used in the expression
+                                let mut value_of = |device: &Device, index: usize| -> Option<f32> {
+                                    let message = RuleContainer::message(&messages, device.clone());
+                                    let payload = RuleContainer::extract_data(message).ok()?;
+                                    let id = device.get_chirpstack_device().device?.device_profile_id;
+                                    let profile_index = dev_prof_container.get_device_profile_index_via_dev_prof_id(&id).ok()?;
+                                    let name = dev_prof_container.get_device_profiles()[profile_index].get_uplink()?.get_payloads().get(index)?.clone();
+                                    payload[name].as_f64().map(|val| val as f32)
+                                };
+                                let threshold = match expression.evaluate(&mut value_of) {
+                                    Some(val) => val,
+                                    None => {
+                                        println!("Error occurred while evaluating expression.");
+                                        messages.clear();
+                                        bool_ops.clear();
+                                        continue 'control
+                                    }
+                                };
+                                let bool_res = op(&measured_data, &threshold);
+                                bool_vec.push(bool_res);
                             } else if let RefValue::Uplink(threshold) = &device_condition.threshold {
                                 let val = payload[uplink_name.clone()].clone();
 
@@ -1059,37 +1691,48 @@ impl RuleContainer {
                             }
                         }
                         if let Condition::Time(time_condition) = i {
-                            let mut bool_weekday = true;
-                            let mut bool_time = false;
-                            let today = Local::now();
-                            if time_condition.weekday.is_some() {
-                                if time_condition.weekday.unwrap() != today.weekday() {
-                                    bool_weekday = false;
-                                }
-                            }
-
-                            let time = today.time();
-                            let start = time_condition.timespan[0];
-                            let end = time_condition.timespan[1];
-
-                            // check time
-                            // e.g. time: 15:00; range: 12:00-15:00
-                            // 12:00 < 15:00 < 16:00
-                            let bool_1 = (start < time) & (time < end) & (start < end);
-
-                            // e.g. time: 15:00; range: 23:00-16:00
-                            // 23:00 > 15:00 < 16:00
-                            let bool_2 = (start > time) & (time < end) & (start > end);
-
-                            // e.g. time: 15:00; range: 14:00-2:00
-                            // 14:00 < 15:00 > 2:00
-                            let bool_3 = (start < time) & (time > end) & (start > end);
-
-
-                            if bool_1 | bool_2 | bool_3 {
-                                bool_time = true;
+                            let bool_res = time_condition.is_fulfilled_now();
+                            bool_vec.push(bool_res);
+                        }
+                        if let Condition::Metadata(metadata_condition) = i {
+                            let message = RuleContainer::message(&messages, metadata_condition.device.clone());
+                            let topic = RuleContainer::uplink_topic(&metadata_condition.device);
+                            let result = RuleContainer::extract_metadata(message, &metadata_condition.metadata, last_f_cnts.get(&topic));
+                            let measured_data = match result {
+                                Ok(value) => value,
+                                Err(e) => {
+                                    println!("Metadata could not be extracted: {}", e.to_string());
+                                    messages.clear();
+                                    bool_ops.clear();
+                                    continue 'control;
+                                },
+                            };
+                            let result = RuleContainer::compare_value(&measured_data, metadata_condition.operator.clone(), &metadata_condition.threshold);
+                            let bool_res = match result {
+                                Ok(bool) => bool,
+                                Err(e) if e.kind() == ErrorKind::InvalidInput => {
+                                    println!("Error occurred: {}\nStopping rule!", e.to_string());
+                                    rule.running = false;
+                                    continue 'control
+                                },
+                                Err(e) => {
+                                    println!("Error occurred while reading message: {}", e.to_string());
+                                    messages.clear();
+                                    bool_ops.clear();
+                                    continue 'control
+                                },
+                            };
+                            bool_vec.push(bool_res);
+                        }
+                        if let Condition::Silence(silence_condition) = i {
+                            let topic = RuleContainer::uplink_topic(&silence_condition.device);
+                            let bool_res = match last_uplinks.get(&topic) {
+                                Some(last_uplink) => last_uplink.elapsed() >= silence_condition.interval,
+                                None => false,
+                            };
+                            if bool_res {
+                                silent_topics.push(topic);
                             }
-                            let bool_res = bool_weekday & bool_time;
                             bool_vec.push(bool_res);
                         }
                     }
@@ -1119,53 +1762,257 @@ impl RuleContainer {
 
                 println!("Conditions are {}", bool_result);
                 if bool_result {
-                    for l in &rule.actions {
-                        println!("Enqueueing message...");
-                        let dev_eui = l.device.get_chirpstack_device().device.unwrap().dev_eui;
-                        let clone_connection = connection.clone();
-                        let clone_client = client.clone();
-                        let res = handle.block_on(async {RuleContainer::enqueue_message(clone_client, dev_eui, l.f_port, l.message.clone(), clone_connection).await});
-                        if let Err(e) = res {
-                            println!("Message could not be enqueued: {}", e.to_string().as_str());
-                            messages.clear();
-                            bool_ops.clear();
-                            continue 'control
-                        }
+                    let trigger = Trigger::new(rule.id, &messages);
+                    queue.schedule(&rule.actions, trigger);
+                    queue.execute_due(&mut rule);
+                    // the silence is reported once per interval, afterwards the interval starts again
+                    for topic in silent_topics {
+                        last_uplinks.insert(topic, Instant::now());
+                    }
+                }
+
+                // remember the gateways of the devices, so that their downlinks are counted for these gateways
+                if let Some(airtime_budget) = &rule.airtime_budget {
+                    for m in &messages {
+                        airtime_budget.observe_uplink(m);
                     }
                 }
 
-                messages.clear();
                 bool_ops.clear();
+                if !complete {
+                    continue 'control
+                }
+
+                // remember the frame counters, so that gaps can be detected with the next messages
+                for m in &messages {
+                    let result = UplinkEvent::parse(&m.payload_str());
+                    if let Some(f_cnt) = result.ok().and_then(|event| event.get_f_cnt()) {
+                        last_f_cnts.insert(m.topic().to_string(), f_cnt as i64);
+                    }
+                }
+
+                messages.clear();
             }
             ()
         });
         return handler;
     }
 
+    /// Starts execution of a given `Rule` with a [`Schedule`].<br/>
+    /// The actions are executed at the times of the schedule, independent of messages.
+    /// This is also done by [`RuleContainer::start_rule_execution`], when the rule has a schedule.<br/>
+    /// For an example see this [link](./index.html#creating-scheduled-rules).
+    pub fn start_scheduled_execution(arc_rule: &Arc<Mutex<Rule>>, client: DeviceQueueServiceClient<Channel>, connection: ChirpstackConnection) -> thread::JoinHandle<()> {
+        println!("Start scheduled rule execution...");
+        let safe = Arc::clone(&arc_rule);
+        safe.lock().unwrap().running = true;
+        let handle = Handle::current();
+        let handler = thread::spawn(move || {
+            // actions which are executed later and confirmed downlinks which wait for their acknowledgement
+            let mut queue = ActionQueue::new(handle, client, connection);
+            // the rule is only locked shortly, so that it can be stopped while waiting
+            'control: loop {
+                let next = {
+                    let rule = safe.lock().unwrap();
+                    if !rule.running {
+                        break 'control
+                    }
+                    match rule.schedule.as_ref().and_then(|schedule| schedule.next_after(Utc::now())) {
+                        Some(next) => next,
+                        None => {
+                            println!("Error occurred: Schedule has no next execution!\nStopping rule!");
+                            break 'control
+                        }
+                    }
+                };
+                println!("Waiting for {}...", next);
+                while Utc::now() < next {
+                    {
+                        let mut rule = safe.lock().unwrap();
+                        if !rule.running {
+                            break 'control
+                        }
+                        queue.execute_due(&mut rule);
+                    }
+                    let remaining = (next - Utc::now()).to_std().unwrap_or(Duration::from_secs(0));
+                    sleep(remaining.min(Duration::from_secs(1)));
+                }
+
+                let mut rule = safe.lock().unwrap();
+                if !rule.running {
+                    break 'control
+                }
+                let trigger = Trigger::new(rule.id, &[]);
+                queue.schedule(&rule.actions, trigger);
+                queue.execute_due(&mut rule);
+            }
+            let mut rule = safe.lock().unwrap();
+            rule.running = false;
+        });
+        return handler;
+    }
+
     /// Extracts data out of given Mqtt message.
     fn extract_data(message: String) -> Result<Value, io::Error> {
-        let pat = "\"objectJSON\":\"";
-        let res = message.find(pat);
-        let begin = match res {
-            Some(e) => e,
-            // change that to false ?
-            None => return Err(io::Error::new(ErrorKind::NotFound, "Selected uplink was not found!"))
+        let event = match UplinkEvent::parse(&message) {
+            Ok(event) => event,
+            Err(_) => return Err(io::Error::new(ErrorKind::NotFound, "Selected uplink was not found!")),
         };
-        let end = message.find("\",\"tags\":").unwrap();
-        let result = message.get(begin+pat.len()..end);
-        let payload_text = match result {
-            Some(e) => e,
-            // change that to false ?
-            None => return Err(io::Error::new(ErrorKind::NotFound, "Selected uplink was not found!"))
-        };
-        let new_payload_text = payload_text.replace("\\", "");
-        let result = serde_json::from_str::<Value>(&new_payload_text.as_str());
-        let payload = match result {
+        return event.get_object();
+    }
+
+    /// Extracts the radio [`Metadata`] out of given Mqtt message.<br/>
+    /// `last_f_cnt` is the frame counter of the previous uplink message of the same device, if there was one.
+    fn extract_metadata(message: String, metadata: &Metadata, last_f_cnt: Option<&i64>) -> Result<Value, io::Error> {
+        let result = UplinkEvent::parse(&message);
+        let event = match result {
             Ok(e) => e,
-            // change that to false ?
             Err(e) => return Err(io::Error::new(ErrorKind::NotFound, e.to_string())),
         };
-        Ok(payload)
+        let rx_info = event.get_rx_info();
+        // the gateway with the best rssi
+        let best_gateway = event.get_best_gateway();
+
+        let value = match metadata {
+            Metadata::Rssi => best_gateway.and_then(|info| info.get_rssi()).map(Value::from),
+            Metadata::Snr => rx_info.iter().map(|info| info.get_lora_snr()).reduce(f64::max).map(Value::from),
+            Metadata::GatewayId => best_gateway.map(|info| Value::from(info.get_gateway_id())),
+            Metadata::DataRate => Some(Value::from(event.get_dr())),
+            Metadata::SpreadingFactor => event.get_tx_info()
+                .and_then(|tx_info| tx_info.get_lora_modulation_info())
+                .map(|info| Value::from(info.get_spreading_factor())),
+            Metadata::FPort => Some(Value::from(event.get_f_port())),
+            Metadata::FCnt => event.get_f_cnt().map(Value::from),
+            Metadata::FCntGap => {
+                // the frame counter is reset, when the device joins again
+                event.get_f_cnt().map(|f_cnt| match last_f_cnt {
+                    Some(last) if f_cnt as i64 > *last => Value::from(f_cnt as i64 - last - 1),
+                    _ => Value::from(0),
+                })
+            },
+        };
+        match value {
+            Some(value) if !value.is_null() => Ok(value),
+            _ => Err(io::Error::new(ErrorKind::NotFound, "Selected metadata was not found!")),
+        }
+    }
+
+    /// Compares a value of a message with a threshold, which is not an uplink of another device.<br/>
+    /// Returns an error of kind `InvalidInput`, when the operator could not be parsed
+    /// and of kind `InvalidData`, when the value has not the type of the threshold.
+    fn compare_value(value: &Value, operator: String, threshold: &RefValue) -> Result<bool, io::Error> {
+        let operator_error = io::Error::new(ErrorKind::InvalidInput, "Operator could not be parsed!");
+        let data_error = io::Error::new(ErrorKind::InvalidData, "Value has not the type of the threshold!");
+        match threshold {
+            RefValue::IntNumber(threshold) => {
+                let op = threshold.get_operator(operator).map_err(|_| operator_error)?;
+                let measured_data = value.as_i64().ok_or(data_error)? as i32;
+                Ok(op(&measured_data, threshold))
+            },
+            RefValue::FloatNumber(threshold) => {
+                let op = threshold.get_operator(operator).map_err(|_| operator_error)?;
+                let measured_data = value.as_f64().ok_or(data_error)? as f32;
+                Ok(op(&measured_data, threshold))
+            },
+            RefValue::Bool(threshold) => {
+                let op = threshold.get_operator(operator).map_err(|_| operator_error)?;
+                let measured_data = value.as_bool().ok_or(data_error)?;
+                Ok(op(&measured_data, threshold))
+            },
+            RefValue::String(threshold) => {
+                let op = threshold.get_operator(operator).map_err(|_| operator_error)?;
+                let measured_data = value.as_str().ok_or(data_error)?.to_string();
+                Ok(op(&measured_data, threshold))
+            },
+            RefValue::Range((lower, upper)) => {
+                let measured_data = value.as_f64().ok_or(data_error)? as f32;
+                let inside = (*lower <= measured_data) & (measured_data <= *upper);
+                match operator.as_str() {
+                    "between" => Ok(inside),
+                    "outside" => Ok(!inside),
+                    _ => Err(operator_error),
+                }
+            },
+            RefValue::List(values) => {
+                let mut contained = false;
+                for i in values {
+                    // values of another type are never equal
+                    contained |= match RuleContainer::compare_value(value, "==".to_string(), i) {
+                        Ok(bool) => bool,
+                        Err(e) if e.kind() == ErrorKind::InvalidData => false,
+                        Err(e) => return Err(e),
+                    };
+                }
+                match operator.as_str() {
+                    "in" => Ok(contained),
+                    "not_in" => Ok(!contained),
+                    _ => Err(operator_error),
+                }
+            },
+            RefValue::Tolerance((threshold, tolerance)) => {
+                let measured_data = value.as_f64().ok_or(data_error)? as f32;
+                match operator.as_str() {
+                    "~=" => Ok((measured_data - threshold).abs() <= *tolerance),
+                    _ => Err(operator_error),
+                }
+            },
+            RefValue::Regex(regex) => {
+                let measured_data = value.as_str().ok_or(data_error)?;
+                match operator.as_str() {
+                    "matches" => Ok(regex.is_match(measured_data)),
+                    _ => Err(operator_error),
+                }
+            },
+            RefValue::Uplink(_) | RefValue::Expression(_) => Err(io::Error::new(ErrorKind::InvalidInput, "An uplink can not be used as threshold here!")),
+        }
+    }
+
+    /// Gets the topic of the uplink messages of a specific `Device`.
+    fn uplink_topic(device: &Device) -> String {
+        let device: ChirpstackDevice = device.get_chirpstack_device().device.unwrap();
+        return "application/".to_owned() + &device.application_id.to_string() + "/device/" + &device.dev_eui + "/event/up";
+    }
+
+    /// Gets the topics of the uplink messages, which are needed to check a [`DeviceCondition`] or a [`MetadataCondition`].
+    fn condition_topics(condition: &Condition) -> Vec<String> {
+        let mut topics = Vec::new();
+        if let Condition::Device(device_condition) = condition {
+            topics.push(RuleContainer::uplink_topic(&device_condition.device));
+            // if threshold is a device, then the topic of this device is needed as well
+            if let RefValue::Uplink(de) = &device_condition.threshold {
+                topics.push(RuleContainer::uplink_topic(&de.0));
+            }
+            // the same applies to all devices of an expression
+            if let RefValue::Expression(expression) = &device_condition.threshold {
+                for de in expression.get_devices() {
+                    topics.push(RuleContainer::uplink_topic(de));
+                }
+            }
+        }
+        if let Condition::Metadata(metadata_condition) = condition {
+            topics.push(RuleContainer::uplink_topic(&metadata_condition.device));
+        }
+        return topics;
+    }
+
+    /// Checks if there are messages for all topics of a condition.
+    fn has_messages(messages: &[Message], condition: &Condition) -> bool {
+        return RuleContainer::condition_topics(condition).iter().all(|topic| messages.iter().any(|m| m.topic() == topic));
+    }
+
+    /// Checks if at least one device of the [`SilenceCondition`]s has been silent for its interval.
+    fn is_any_silent(conditions: &[Condition], last_uplinks: &HashMap<String, Instant>) -> bool {
+        for i in conditions {
+            if let Condition::Silence(silence_condition) = i {
+                let topic = RuleContainer::uplink_topic(&silence_condition.device);
+                if let Some(last_uplink) = last_uplinks.get(&topic) {
+                    if last_uplink.elapsed() >= silence_condition.interval {
+                        return true;
+                    }
+                }
+            }
+        }
+        return false;
     }
 
     /// Gets message for a specific `Device`.
@@ -1181,26 +2028,48 @@ impl RuleContainer {
     /// Establishes a new connection to Chirpstack Server to manage a device queue
     /// and adds resulting client to the container.
     pub async fn establish_connection(&mut self, connection: ChirpstackConnection) -> Result<(), Error> {
-        let my_client = DeviceQueueServiceClient::connect(connection.get_uri()).await?;
+        let my_client = DeviceQueueServiceClient::new(connection.get_channel()?);
         self.client = Option::from(my_client);
         Ok(())
     }
 
-    /// Enqueues a message for a specific device, on a specific port.
-    async fn enqueue_message(mut client: DeviceQueueServiceClient<Channel>, dev_eui: String, f_port: u32, data: String, connection: ChirpstackConnection) -> Result<(), io::Error>{
-        let mes = hex::decode(data).unwrap();
-        let data_b64 = base64::encode(mes).as_bytes().to_vec();
+    /// Enqueues a message for a specific device, on a specific port, which is `confirmed` by the device or not.
+    /// The message is either the `data` encoded in hex or a `json_object`, which is encoded by the Chirpstack Application Server;
+    /// the other one is empty.<br/>
+    /// Returns the id of the downlink, which is its frame counter.
+    #[cfg(not(feature = "v4"))]
+    async fn enqueue_message(mut client: DeviceQueueServiceClient<Channel>, dev_eui: String, f_port: u32, data: String, json_object: String, confirmed: bool, connection: ChirpstackConnection) -> Result<String, io::Error>{
+        let device_queue_item = RuleContainer::device_queue_item(dev_eui, f_port, &data, json_object, confirmed)?;
+        let enqueue_device_queue_item_request = EnqueueDeviceQueueItemRequest {
+            device_queue_item: Some(device_queue_item),
+        };
 
-        let device_queue_item = DeviceQueueItem {
-            dev_eui,
-            confirmed: false,
-            f_cnt: 0,
-            f_port,
-            data: data_b64,
-            json_object: "".to_string(),
+        let mut request = Request::new(enqueue_device_queue_item_request);
+        let token = connection.get_api_token().parse::<MetadataValue<_>>();
+        let token:MetadataValue<_> = match token {
+            Ok(t) => t,
+            Err(e) => {return Err(io::Error::new(ErrorKind::InvalidData, e))}
+        };
+        request.metadata_mut().insert("authorization", token.clone());
+
+        let response= client.enqueue(request).await;
+        let response = match response {
+            Ok(e) => e,
+            Err(status) => return Err(RetryPolicy::status_to_error(status))
         };
+
+        Ok(response.get_ref().f_cnt.to_string())
+    }
+
+    /// Enqueues a message for a specific device, on a specific port, which is `confirmed` by the device or not.
+    /// The message is either the `data` encoded in hex or a `json_object`, which is encoded by the Chirpstack Application Server;
+    /// the other one is empty.<br/>
+    /// Returns the id of the downlink, which is the id of the queue item.
+    #[cfg(feature = "v4")]
+    async fn enqueue_message(mut client: DeviceQueueServiceClient<Channel>, dev_eui: String, f_port: u32, data: String, json_object: String, confirmed: bool, connection: ChirpstackConnection) -> Result<String, io::Error>{
+        let device_queue_item = RuleContainer::device_queue_item(dev_eui, f_port, &data, json_object, confirmed)?;
         let enqueue_device_queue_item_request = EnqueueDeviceQueueItemRequest {
-            device_queue_item: Some(device_queue_item),
+            queue_item: Some(device_queue_item),
         };
 
         let mut request = Request::new(enqueue_device_queue_item_request);
@@ -1212,15 +2081,206 @@ impl RuleContainer {
         request.metadata_mut().insert("authorization", token.clone());
 
         let response= client.enqueue(request).await;
-        let _response = match response {
+        let response = match response {
+            Ok(e) => e,
+            Err(status) => return Err(RetryPolicy::status_to_error(status))
+        };
+
+        Ok(response.get_ref().id.clone())
+    }
+
+    /// Creates the queue item of a message, whose `data` encoded in hex is sent as raw bytes.<br/>
+    /// The data used to be encoded in base64 once more with Chirpstack version 3,
+    /// although the bytes of the gRPC API are sent as they are,
+    /// so that the devices received the base64 text instead of the message.
+    #[cfg(not(feature = "v4"))]
+    fn device_queue_item(dev_eui: String, f_port: u32, data: &str, json_object: String, confirmed: bool) -> Result<DeviceQueueItem, io::Error> {
+        let mes = match hex::decode(data) {
+            Ok(mes) => mes,
+            Err(e) => return Err(io::Error::new(ErrorKind::InvalidData, e)),
+        };
+        return Ok(DeviceQueueItem {
+            dev_eui,
+            confirmed,
+            f_cnt: 0,
+            f_port,
+            data: mes,
+            json_object,
+        });
+    }
+
+    /// Creates the queue item of a message, whose `data` encoded in hex is sent as raw bytes
+    /// and whose `json_object` is converted to a protobuf struct.
+    #[cfg(feature = "v4")]
+    fn device_queue_item(dev_eui: String, f_port: u32, data: &str, json_object: String, confirmed: bool) -> Result<DeviceQueueItem, io::Error> {
+        let mes = match hex::decode(data) {
+            Ok(mes) => mes,
+            Err(e) => return Err(io::Error::new(ErrorKind::InvalidData, e)),
+        };
+        let object = match json_object.as_str() {
+            "" => None,
+            text => match serde_json::from_str::<Value>(text) {
+                Ok(Value::Object(object)) => Some(RuleContainer::to_struct(object)),
+                _ => return Err(io::Error::new(ErrorKind::InvalidData, "The message has to be a JSON object")),
+            },
+        };
+        return Ok(DeviceQueueItem {
+            dev_eui,
+            confirmed,
+            f_port,
+            data: mes,
+            object,
+            ..Default::default()
+        });
+    }
+
+    /// Converts a JSON `object` to a protobuf struct, which is used for the objects of queue items in Chirpstack version 4.
+    #[cfg(feature = "v4")]
+    fn to_struct(object: serde_json::Map<String, Value>) -> prost_types::Struct {
+        fn to_value(value: Value) -> prost_types::Value {
+            let kind = match value {
+                Value::Null => prost_types::value::Kind::NullValue(0),
+                Value::Bool(bool) => prost_types::value::Kind::BoolValue(bool),
+                Value::Number(number) => prost_types::value::Kind::NumberValue(number.as_f64().unwrap_or_default()),
+                Value::String(string) => prost_types::value::Kind::StringValue(string),
+                Value::Array(array) => prost_types::value::Kind::ListValue(prost_types::ListValue {
+                    values: array.into_iter().map(to_value).collect(),
+                }),
+                Value::Object(object) => prost_types::value::Kind::StructValue(RuleContainer::to_struct(object)),
+            };
+            return prost_types::Value { kind: Some(kind) };
+        }
+        return prost_types::Struct {
+            fields: object.into_iter().map(|(key, value)| (key, to_value(value))).collect(),
+        }
+    }
+
+    /// Lists the messages in the queue of the device with `dev_eui`, which are not sent yet.<br/>
+    /// For an example see this [link](./index.html#inspecting-and-flushing-device-queues).
+    #[cfg(not(feature = "v4"))]
+    pub async fn list_queue(mut client: DeviceQueueServiceClient<Channel>, dev_eui: &str, connection: ChirpstackConnection) -> Result<Vec<DeviceQueueItem>, io::Error> {
+        let list_device_queue_items_request = ListDeviceQueueItemsRequest {
+            dev_eui: dev_eui.to_string(),
+            count_only: false,
+        };
+
+        let mut request = Request::new(list_device_queue_items_request);
+        let token = connection.get_api_token().parse::<MetadataValue<_>>();
+        let token:MetadataValue<_> = match token {
+            Ok(t) => t,
+            Err(e) => {return Err(io::Error::new(ErrorKind::InvalidData, e))}
+        };
+        request.metadata_mut().insert("authorization", token.clone());
+
+        let response= client.list(request).await;
+        let response = match response {
+            Ok(e) => e,
+            Err(status) => return Err(RetryPolicy::status_to_error(status))
+        };
+
+        Ok(response.get_ref().device_queue_items.clone())
+    }
+
+    /// Lists the messages in the queue of the device with `dev_eui`, which are not sent yet.<br/>
+    /// For an example see this [link](./index.html#inspecting-and-flushing-device-queues).
+    #[cfg(feature = "v4")]
+    pub async fn list_queue(mut client: DeviceQueueServiceClient<Channel>, dev_eui: &str, connection: ChirpstackConnection) -> Result<Vec<DeviceQueueItem>, io::Error> {
+        let get_device_queue_items_request = GetDeviceQueueItemsRequest {
+            dev_eui: dev_eui.to_string(),
+            count_only: false,
+        };
+
+        let mut request = Request::new(get_device_queue_items_request);
+        let token = connection.get_api_token().parse::<MetadataValue<_>>();
+        let token:MetadataValue<_> = match token {
+            Ok(t) => t,
+            Err(e) => {return Err(io::Error::new(ErrorKind::InvalidData, e))}
+        };
+        request.metadata_mut().insert("authorization", token.clone());
+
+        let response= client.get_queue(request).await;
+        let response = match response {
             Ok(e) => e,
-            Err(status) => return Err(io::Error::new(ErrorKind::Other, status.message().to_string() + &status.code().to_string()))
+            Err(status) => return Err(RetryPolicy::status_to_error(status))
+        };
+
+        Ok(response.get_ref().result.clone())
+    }
+
+    /// Removes all messages from the queue of the device with `dev_eui`, which are not sent yet.<br/>
+    /// For an example see this [link](./index.html#inspecting-and-flushing-device-queues).
+    pub async fn flush_queue(mut client: DeviceQueueServiceClient<Channel>, dev_eui: &str, connection: ChirpstackConnection) -> Result<(), io::Error> {
+        let flush_device_queue_request = FlushDeviceQueueRequest {
+            dev_eui: dev_eui.to_string(),
+        };
+
+        let mut request = Request::new(flush_device_queue_request);
+        let token = connection.get_api_token().parse::<MetadataValue<_>>();
+        let token:MetadataValue<_> = match token {
+            Ok(t) => t,
+            Err(e) => {return Err(io::Error::new(ErrorKind::InvalidData, e))}
+        };
+        request.metadata_mut().insert("authorization", token.clone());
+
+        #[cfg(not(feature = "v4"))]
+        let response= client.flush(request).await;
+        #[cfg(feature = "v4")]
+        let response= client.flush_queue(request).await;
+        if let Err(status) = response {
+            return Err(RetryPolicy::status_to_error(status))
+        }
+
+        Ok(())
+    }
+
+    /// Enqueues a message for all devices of a multicast group, on a specific port.
+    async fn enqueue_multicast_message(multicast_group_id: String, f_port: u32, data: String, connection: ChirpstackConnection) -> Result<(), io::Error>{
+        let mes = match hex::decode(data) {
+            Ok(mes) => mes,
+            Err(e) => return Err(io::Error::new(ErrorKind::InvalidData, e)),
+        };
+        let mut client = match connection.get_channel() {
+            Ok(channel) => MulticastGroupServiceClient::new(channel),
+            Err(e) => return Err(io::Error::new(ErrorKind::NotConnected, e)),
+        };
+
+        #[cfg(not(feature = "v4"))]
+        let enqueue_multicast_queue_item_request = EnqueueMulticastQueueItemRequest {
+            multicast_queue_item: Some(MulticastQueueItem {
+                multicast_group_id,
+                f_cnt: 0,
+                f_port,
+                data: mes,
+            }),
+        };
+        #[cfg(feature = "v4")]
+        let enqueue_multicast_queue_item_request = EnqueueMulticastGroupQueueItemRequest {
+            queue_item: Some(MulticastGroupQueueItem {
+                multicast_group_id,
+                f_cnt: 0,
+                f_port,
+                data: mes,
+            }),
+        };
+
+        let mut request = Request::new(enqueue_multicast_queue_item_request);
+        let token = connection.get_api_token().parse::<MetadataValue<_>>();
+        let token:MetadataValue<_> = match token {
+            Ok(t) => t,
+            Err(e) => {return Err(io::Error::new(ErrorKind::InvalidData, e))}
         };
+        request.metadata_mut().insert("authorization", token.clone());
+
+        let response= client.enqueue(request).await;
+        if let Err(status) = response {
+            return Err(RetryPolicy::status_to_error(status))
+        }
 
         Ok(())
     }
 
-    /// Stops the execution of a rule
+    /// Stops the execution of a rule.
+    /// Actions of the rule which are waiting for their delay are not executed anymore.
     pub fn stop_rule_execution(arc_rule: &Arc<Mutex<Rule>>) {
         println!("Stop rule execution...");
         let safe = Arc::clone(arc_rule);
@@ -1235,7 +2295,9 @@ impl RuleContainer {
 */
 pub enum Condition {
     Device(DeviceCondition),
-    Time(TimeCondition)
+    Time(TimeCondition),
+    Silence(SilenceCondition),
+    Metadata(MetadataCondition),
 }
 
 /**
@@ -1288,14 +2350,31 @@ impl DeviceCondition {
 
 /**
     Condition for a [`Rule`] depending on time.
+
+    The condition is fulfilled on the selected weekdays inside the timespan,
+    if the date is inside the date range and is not excluded.
+    The start and the end of the timespan are included to the minute.
+    If the start is after the end, the timespan lasts over midnight;
+    the weekdays and dates are always the ones of the moment which is checked.
 */
 pub struct TimeCondition {
-    /// [`Weekday`](https://docs.rs/chrono/0.4.20/chrono/enum.Weekday.html) which should be used for the condition;
-    /// `None` if the condition should be executed everyday.
-    weekday: Option<Weekday>,
+    /// [`Weekday`](https://docs.rs/chrono/0.4.20/chrono/enum.Weekday.html)s which should be used for the condition;
+    /// empty if the condition should be executed everyday.
+    weekdays: Vec<Weekday>,
     /// Timespan in which the [`Rule`] should be executed.
-    /// Format of timespan: h:m
-    timespan: [NaiveTime; 2],
+    timespan: [TimeBoundary; 2],
+    /// Latitude and longitude in degrees, which are used for sunrise and sunset.
+    location: Option<(f64, f64)>,
+    /// First and last date, on which the [`Rule`] should be executed;
+    /// `None` if it should be executed on every date.
+    date_range: Option<[NaiveDate; 2]>,
+    /// If the year of the date range is ignored, so that the range is repeated every year.
+    yearly: bool,
+    /// Dates on which the [`Rule`] should never be executed.
+    exclusions: Vec<NaiveDate>,
+    /// [Timezone](https://docs.rs/chrono-tz/0.6.1/chrono_tz/enum.Tz.html) in which the condition is checked;
+    /// `None` if the local time of the server should be used.
+    timezone: Option<Tz>,
 }
 
 impl TimeCondition {
@@ -1303,120 +2382,2377 @@ impl TimeCondition {
     /// For an example see this [link](./index.html#creating-conditions-depending-on-time).
     pub fn new(weekday: Option<Weekday>, timespan_start: NaiveTime, timespan_end: NaiveTime) -> Self {
         return TimeCondition {
-            weekday,
-            timespan: [timespan_start, timespan_end]
+            weekdays: weekday.into_iter().collect(),
+            timespan: [TimeBoundary::Time(timespan_start), TimeBoundary::Time(timespan_end)],
+            location: None,
+            date_range: None,
+            yearly: false,
+            exclusions: Vec::new(),
+            timezone: None,
         }
     }
 
-    /// Gets the weekday.
-    pub fn get_weekday(&self) -> Option<Weekday>{
-        self.weekday
+    /// Gets the weekday; `None` if the condition is fulfilled everyday.
+    /// If there are several weekdays, only the first one is returned.
+    #[deprecated(note = "there can be several weekdays, use `get_weekdays` instead")]
+    pub fn get_weekday(&self) -> Option<Weekday> {
+        self.get_weekdays().first().copied()
+    }
+
+    /// Gets the weekdays; empty if the condition is fulfilled everyday.
+    pub fn get_weekdays(&self) -> &[Weekday] {
+        self.weekdays.borrow()
     }
 
-    /// Gets the timespan.
+    /// Sets the weekdays; empty if the condition should be fulfilled everyday.
+    pub fn set_weekdays(&mut self, weekdays: Vec<Weekday>) {
+        self.weekdays = weekdays;
+    }
+
+    /// Gets the timespan.<br/>
+    /// Sunrise and sunset are given as their time on the current date in the timezone of the condition;
+    /// midnight if there is no location or the sun does not rise or set on this date.
+    /// To get them as sunrise and sunset use [`TimeCondition::get_timespan_boundaries`].
     pub fn get_timespan(&self) -> [NaiveTime; 2] {
+        let time_of = |boundary: &TimeBoundary| {
+            let time = match self.timezone {
+                Some(tz) => boundary.time_on(Utc::now().with_timezone(&tz).naive_local().date(), self.location, &tz),
+                None => boundary.time_on(Local::now().naive_local().date(), self.location, &Local),
+            };
+            time.unwrap_or_else(|| NaiveTime::from_hms(0, 0, 0))
+        };
+        [time_of(&self.timespan[0]), time_of(&self.timespan[1])]
+    }
+
+    /// Gets the start and the end of the timespan, which can also depend on sunrise and sunset.
+    pub fn get_timespan_boundaries(&self) -> [TimeBoundary; 2] {
         self.timespan
     }
-}
 
-/**
-    Action which should be executed, when the [`Condition`]s of a [`Rule`] are true.
- */
-pub struct Action {
-    /// The device on which the action is executed on.
-    device: Device,
-    /// The indices of the [`DownlinkPayload`](crate::devices::DownlinkPayload)s which should be executed.
-    payload_indices: Vec<usize>,
-    /// The actual message that is sent encoded in hex.
-    message: String,
-    /// The port to which the message is sent to.
-    f_port: u32,
-}
+    /// Sets the start and the end of the timespan, which can also depend on sunrise and sunset.<br/>
+    /// For sunrise and sunset a location must be set.
+    /// For an example see this [link](./index.html#creating-conditions-depending-on-sunrise-and-sunset).
+    pub fn set_timespan(&mut self, timespan_start: TimeBoundary, timespan_end: TimeBoundary) {
+        self.timespan = [timespan_start, timespan_end];
+    }
 
-impl Action {
-    /// Creates a new action with the `device` on which the action should be executed on,
-    /// the `indices` of the [`DownlinkPayload`](crate::devices::DownlinkPayload)s, which should be executed,
-    /// the actual `message` that will be sent, encoded in hex,
-    /// and the `port` to which the message will be sent to.<br/>
-    /// For an example see this [link](./index.html#creating-a-rule-depending-on-a-device).
-    pub fn new(device: Device, payload_indices: Vec<usize>, message: String, f_port: u32) -> Self {
-        return Action {
-            device,
-            payload_indices,
-            message,
-            f_port
+    /// Checks if the timespan depends on sunrise or sunset.
+    pub fn is_astronomical(&self) -> bool {
+        self.timespan.iter().any(|boundary| !matches!(boundary, TimeBoundary::Time(_)))
+    }
+
+    /// Gets latitude and longitude of the location used for sunrise and sunset.
+    pub fn get_location(&self) -> Option<(f64, f64)> {
+        self.location
+    }
+
+    /// Sets the location used for sunrise and sunset via `latitude` and `longitude` in degrees.
+    pub fn set_location(&mut self, latitude: f64, longitude: f64) -> Result<(), io::Error> {
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "Latitude or longitude is out of range!"));
         }
+        self.location = Some((latitude, longitude));
+        Ok(())
     }
 
-    /// Gets the device to which the data should be sent.
-    pub fn get_device(&self) -> &Device {
-        self.device.borrow()
+    /// Sets the location used for sunrise and sunset to the location of a [`Device`] in Chirpstack.<br/>
+    /// Only available with Chirpstack version 3, because version 4 does not provide the location of a device.
+    #[cfg(not(feature = "v4"))]
+    pub fn set_location_from_device(&mut self, device: &Device) -> Result<(), io::Error> {
+        let location = device.get_chirpstack_device().location;
+        match location {
+            Some(location) => self.set_location(location.latitude, location.longitude),
+            None => Err(io::Error::new(ErrorKind::NotFound, "The device has no location!")),
+        }
     }
 
-    /// Gets the payload indices of the possible downlinks, that should be used.
-    pub fn get_payload_indices(&self) -> &[usize] {
-        self.payload_indices.borrow()
+    /// Gets the date range and if it is repeated every year.
+    pub fn get_date_range(&self) -> Option<([NaiveDate; 2], bool)> {
+        self.date_range.map(|range| (range, self.yearly))
     }
 
-    /// Gets the message to sent.
-    pub fn get_message(&self) -> String{
-        self.message.clone()
+    /// Sets the first and the last date on which the condition can be fulfilled.<br/>
+    /// If `yearly` is true, the year is ignored, so that the range is repeated every year,
+    /// e.g. for seasons. Then the range can also last over the new year.
+    pub fn set_date_range(&mut self, start: NaiveDate, end: NaiveDate, yearly: bool) {
+        self.date_range = Some([start, end]);
+        self.yearly = yearly;
     }
 
-    /// Gets the port to which data should be sent.
-    pub fn get_f_port(&self) -> u32 {
-        self.f_port
+    /// Removes the date range, so that the condition can be fulfilled on every date.
+    pub fn remove_date_range(&mut self) {
+        self.date_range = None;
+        self.yearly = false;
     }
-}
 
-/**
-    Representation of a rule.
- */
-pub struct Rule {
-    /// Condition that should be satisfied.
-    conditions: Vec<Condition>,
-    /// Boolean operators which should be used for two conditions.
-    bool_ops: Vec<String>,
-    /// Actions that should be executed.
-    actions: Vec<Action>,
-    /// Indicator if the rule is momentarily executed.
-    running: bool
-}
+    /// Gets the dates on which the condition is never fulfilled.
+    pub fn get_exclusions(&self) -> &[NaiveDate] {
+        self.exclusions.borrow()
+    }
 
-impl Rule {
-    /// Creates a new rule with `conditions` that should be satisfied,
-    /// `boolean operators` which should be used for two conditions and
-    /// `actions` that should be executed.<br/>
-    /// For an example see this [link](./index.html#creating-a-rule-depending-on-a-device).
+    /// Adds a date on which the condition is never fulfilled, e.g. a holiday.
+    pub fn add_exclusion(&mut self, date: NaiveDate) {
+        self.exclusions.push(date);
+    }
+
+    /// Gets the timezone; `None` if the local time of the server is used.
+    pub fn get_timezone(&self) -> Option<Tz> {
+        self.timezone
+    }
+
+    /// Sets the timezone in which the condition is checked via its IANA name, e.g. `Europe/Berlin` or `UTC`.<br/>
+    /// The weekdays, dates and the timespan are then always compared with the wall clock time
+    /// of the timezone, also after a change of the daylight saving time.
+    /// Therefore on the day the clocks are put forward, times in the skipped hour never occur,
+    /// and on the day they are put back, times in the repeated hour occur twice.
+    pub fn set_timezone(&mut self, timezone: &str) -> Result<(), io::Error> {
+        let tz = timezone.parse::<Tz>().map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+        self.timezone = Some(tz);
+        Ok(())
+    }
+
+    /// Removes the timezone, so that the local time of the server is used.
+    pub fn remove_timezone(&mut self) {
+        self.timezone = None;
+    }
+
+    /// Checks if the condition is fulfilled at the moment, in its timezone.
+    pub fn is_fulfilled_now(&self) -> bool {
+        match self.timezone {
+            Some(tz) => self.matches(&Utc::now().with_timezone(&tz)),
+            None => self.matches(&Local::now()),
+        }
+    }
+
+    /// Checks if the condition is fulfilled at a specific date and time.<br/>
+    /// The wall clock time of the given date and time is used, regardless of the timezone of the condition.
+    pub fn matches<Z: TimeZone>(&self, date_time: &DateTime<Z>) -> bool {
+        let local = date_time.naive_local();
+        let date = local.date();
+
+        if !self.weekdays.is_empty() && !self.weekdays.contains(&date.weekday()) {
+            return false;
+        }
+        if self.exclusions.contains(&date) {
+            return false;
+        }
+        if let Some([first, last]) = self.date_range {
+            let inside = if self.yearly {
+                let day = (date.month(), date.day());
+                let first = (first.month(), first.day());
+                let last = (last.month(), last.day());
+                if first <= last {
+                    (first <= day) & (day <= last)
+                } else {
+                    // e.g. the winter from the first of November to the last of March
+                    (first <= day) | (day <= last)
+                }
+            } else {
+                (first <= date) & (date <= last)
+            };
+            if !inside {
+                return false;
+            }
+        }
+
+        // the time is compared to the minute, so that the start and end minute are included
+        let time = NaiveTime::from_hms(local.hour(), local.minute(), 0);
+        let start = match self.timespan[0].time_on(date, self.location, &date_time.timezone()) {
+            Some(start) => start,
+            None => return false,
+        };
+        let end = match self.timespan[1].time_on(date, self.location, &date_time.timezone()) {
+            Some(end) => end,
+            None => return false,
+        };
+        if start <= end {
+            // e.g. time: 15:00; range: 12:00-15:00
+            (start <= time) & (time <= end)
+        } else {
+            // e.g. time: 15:00; range: 23:00-16:00 or range: 14:00-2:00
+            (start <= time) | (time <= end)
+        }
+    }
+}
+
+/**
+    Start or end of the timespan of a [`TimeCondition`].
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeBoundary {
+    /// A fixed time.
+    Time(NaiveTime),
+    /// Sunrise with an offset in minutes, e.g. `-30` for 30 minutes before sunrise.
+    Sunrise(i64),
+    /// Sunset with an offset in minutes, e.g. `-30` for 30 minutes before sunset.
+    Sunset(i64),
+}
+
+impl TimeBoundary {
+    /// Gets the wall clock time of the boundary on a specific date in a timezone.<br/>
+    /// Sunrise and sunset are computed for the location, they are accurate to a few minutes.
+    /// `None` if there is no location or if the sun does not rise or set on this date.
+    fn time_on<Z: TimeZone>(&self, date: NaiveDate, location: Option<(f64, f64)>, timezone: &Z) -> Option<NaiveTime> {
+        let (sunrise, offset) = match self {
+            TimeBoundary::Time(time) => return Some(*time),
+            TimeBoundary::Sunrise(offset) => (true, *offset),
+            TimeBoundary::Sunset(offset) => (false, *offset),
+        };
+        let (latitude, longitude) = location?;
+        let utc = sun_event(date, latitude, longitude, sunrise)?;
+        let local = timezone.from_utc_datetime(&utc).naive_local() + chrono::Duration::minutes(offset);
+        Some(NaiveTime::from_hms(local.hour(), local.minute(), 0))
+    }
+}
+
+impl FromStr for TimeBoundary {
+    type Err = io::Error;
+
+    /// Parses a boundary out of a time in format `hh:mm`
+    /// or `sunrise` and `sunset` with an optional offset in minutes, e.g. `sunset-30` or `sunrise+15`.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        let error = || io::Error::new(ErrorKind::InvalidInput, "Time could not be parsed!");
+        for (name, sunrise) in [("sunrise", true), ("sunset", false)] {
+            if let Some(offset) = text.strip_prefix(name) {
+                let offset = offset.replace(" ", "");
+                let minutes = if offset.is_empty() {
+                    0
+                } else {
+                    offset.trim_start_matches('+').parse::<i64>().map_err(|_| error())?
+                };
+                return Ok(if sunrise { TimeBoundary::Sunrise(minutes) } else { TimeBoundary::Sunset(minutes) });
+            }
+        }
+        let time = NaiveTime::parse_from_str(text, "%H:%M").map_err(|_| error())?;
+        Ok(TimeBoundary::Time(time))
+    }
+}
+
+/// Computes sunrise or sunset in UTC on a specific date for a location in degrees,
+/// with the algorithm of the _Almanac for Computers_ (1990).
+/// `None` if the sun does not rise or set on this date.
+fn sun_event(date: NaiveDate, latitude: f64, longitude: f64, sunrise: bool) -> Option<NaiveDateTime> {
+    // the official zenith, which includes refraction and the radius of the sun
+    let zenith = 90.833_f64.to_radians();
+    let normalize = |value: f64, max: f64| ((value % max) + max) % max;
+
+    // approximate time of the event in days
+    let longitude_hour = longitude / 15.0;
+    let t = date.ordinal() as f64 + ((if sunrise { 6.0 } else { 18.0 }) - longitude_hour) / 24.0;
+
+    // mean anomaly and true longitude of the sun
+    let mean_anomaly = 0.9856 * t - 3.289;
+    let true_longitude = normalize(mean_anomaly + 1.916 * mean_anomaly.to_radians().sin()
+        + 0.020 * (2.0 * mean_anomaly).to_radians().sin() + 282.634, 360.0);
+
+    // right ascension in the same quadrant as the true longitude, in hours
+    let mut right_ascension = normalize((0.91764 * true_longitude.to_radians().tan()).atan().to_degrees(), 360.0);
+    right_ascension += (true_longitude / 90.0).floor() * 90.0 - (right_ascension / 90.0).floor() * 90.0;
+    right_ascension /= 15.0;
+
+    // declination and local hour angle of the sun
+    let sin_declination = 0.39782 * true_longitude.to_radians().sin();
+    let cos_declination = sin_declination.asin().cos();
+    let cos_hour_angle = (zenith.cos() - sin_declination * latitude.to_radians().sin()) / (cos_declination * latitude.to_radians().cos());
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let hour_angle = if sunrise {
+        (360.0 - cos_hour_angle.acos().to_degrees()) / 15.0
+    } else {
+        cos_hour_angle.acos().to_degrees() / 15.0
+    };
+
+    // local mean time and universal time of the event
+    let local_mean_time = hour_angle + right_ascension - 0.06571 * t - 6.622;
+    let universal_time = normalize(local_mean_time - longitude_hour, 24.0);
+    let seconds = (universal_time * 3600.0).round() as i64;
+    Some(date.and_hms(0, 0, 0) + chrono::Duration::seconds(seconds))
+}
+
+/**
+    Radio metadata of an uplink message, which can be used in a [`MetadataCondition`].
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metadata {
+    /// Best RSSI of all gateways which received the message, compared as [`RefValue::IntNumber`].
+    Rssi,
+    /// Best SNR of all gateways which received the message, compared as [`RefValue::FloatNumber`].
+    Snr,
+    /// Id of the gateway with the best RSSI encoded in hex, compared as [`RefValue::String`].
+    GatewayId,
+    /// Data rate of the message, compared as [`RefValue::IntNumber`].
+    DataRate,
+    /// Spreading factor of the message, compared as [`RefValue::IntNumber`].
+    SpreadingFactor,
+    /// Port on which the message was sent, compared as [`RefValue::IntNumber`].
+    FPort,
+    /// Frame counter of the message, compared as [`RefValue::IntNumber`].
+    FCnt,
+    /// Number of messages lost since the previous message of the device, compared as [`RefValue::IntNumber`].
+    /// It is 0 for the first message after the start of the rule and after the device joined again.
+    FCntGap,
+}
+
+/**
+    Condition for a [`Rule`] depending on the radio [`Metadata`] of the uplink messages of a [`Device`].
+*/
+pub struct MetadataCondition {
+    /// Device which should be used for the condition.
+    device: Device,
+    /// Metadata of the uplink message which should be used for the condition.
+    metadata: Metadata,
+    /// The comparison operator used in the condition.
+    operator: String,
+    /// The threshold to which the metadata should be compared.
+    threshold: RefValue,
+}
+
+impl MetadataCondition {
+    /// Creates a new condition with the [`Device`], the [`Metadata`] that should be read from the message,
+    /// a comparison `operator` and the `threshold` to which the metadata should be compared to.
+    /// The threshold can not be an uplink of another device.<br/>
+    /// See this [link](./index.html#creating-conditions-depending-on-radio-metadata) for an example.
+    pub fn new(device: Device, metadata: Metadata, operator: String, threshold: RefValue) -> Self {
+        return MetadataCondition {
+            device,
+            metadata,
+            operator,
+            threshold
+        }
+    }
+
+    /// Gets the device from which the metadata should be used.
+    pub fn get_device(&self) -> &Device {
+        self.device.borrow()
+    }
+
+    /// Gets the metadata that should be read out of the uplink message.
+    pub fn get_metadata(&self) -> Metadata {
+        self.metadata
+    }
+
+    /// Gets the comparison operator.
+    pub fn get_operator(&self) -> String{
+        self.operator.clone()
+    }
+
+    /// Gets the threshold.
+    pub fn get_threshold(&self) -> &RefValue{
+        self.threshold.borrow()
+    }
+}
+
+/**
+    Condition for a [`Rule`] depending on the absence of uplink messages of a [`Device`].
+
+    The condition is true, when the device has not sent an uplink message within the interval.
+    The interval starts with the start of the rule and again after every uplink message.
+    When the rule was executed because of the silence, the interval starts again,
+    so that the silence is reported once per interval.<br/>
+    The silence is checked every few seconds and not only when a message arrives.
+    If the rule is checked because of the silence, the other conditions on devices,
+    which have not sent an uplink message since the last check, are not fulfilled.
+*/
+pub struct SilenceCondition {
+    /// Device which should be watched.
+    device: Device,
+    /// Interval in which the device has to send an uplink message.
+    interval: Duration,
+}
+
+impl SilenceCondition {
+    /// Creates a new condition with the [`Device`] which should be watched and the `interval`
+    /// in which the device has to send an uplink message.<br/>
+    /// For an example see this [link](./index.html#creating-conditions-depending-on-the-silence-of-a-device).
+    pub fn new(device: Device, interval: Duration) -> Self {
+        return SilenceCondition {
+            device,
+            interval,
+        }
+    }
+
+    /// Gets the device which is watched.
+    pub fn get_device(&self) -> &Device {
+        self.device.borrow()
+    }
+
+    /// Gets the interval.
+    pub fn get_interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+/**
+    Action which should be executed, when the [`Condition`]s of a [`Rule`] are true.
+ */
+pub struct Action {
+    /// What is done when the action is executed.
+    kind: ActionKind,
+    /// The time to wait before the action is executed, after the previous action of the rule.
+    delay: Duration,
+}
+
+impl Action {
+    /// Creates a new action with the `device` on which the action should be executed on,
+    /// the `indices` of the [`DownlinkPayload`](crate::devices::DownlinkPayload)s, which should be executed,
+    /// the actual `message` that will be sent, encoded in hex,
+    /// and the `port` to which the message will be sent to.<br/>
+    /// For an example see this [link](./index.html#creating-a-rule-depending-on-a-device).
+    pub fn new(device: Device, payload_indices: Vec<usize>, message: String, f_port: u32) -> Self {
+        return Action {
+            kind: ActionKind::Downlink(DownlinkAction {
+                device,
+                payload_indices,
+                message,
+                f_port,
+                json_object: None,
+                queue_policy: QueuePolicy::Append,
+                confirmed: None,
+            }),
+            delay: Duration::from_secs(0),
+        }
+    }
+
+    /// Creates a new action with the `device` on which the action should be executed on,
+    /// the message as `json_object` and the `port` to which the message will be sent to.<br/>
+    /// The message is encoded by the Chirpstack Application Server,
+    /// with the payload codec of the `device_profile` of the device,
+    /// therefore an error is returned if the device profile has no codec or the message is no JSON object.<br/>
+    /// For an example see this [link](./index.html#creating-json-object-downlinks).
+    pub fn new_json_object(device: Device, json_object: Value, f_port: u32, device_profile: &DeviceProfile) -> Result<Self, io::Error> {
+        if !json_object.is_object() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "The message has to be a JSON object"));
+        }
+        if device_profile.has_payload_encoder() == Some(false) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "The device profile has no payload encoder"));
+        }
+        return Ok(Action {
+            kind: ActionKind::Downlink(DownlinkAction {
+                device,
+                payload_indices: Vec::new(),
+                message: String::new(),
+                f_port,
+                json_object: Some(json_object),
+                queue_policy: QueuePolicy::Append,
+                confirmed: None,
+            }),
+            delay: Duration::from_secs(0),
+        })
+    }
+
+    /// Creates a new action like [`Action::new`], but the message has to be confirmed by the device.
+    /// The acknowledgement is received by the `tracker`.
+    /// If it is not acknowledged within the `ack_timeout`, or the device does not acknowledge it,
+    /// the message is enqueued again up to `retries` times.
+    /// The result of every attempt is reported to the rule (see [`Rule::get_ack_reports`]).<br/>
+    /// For an example see this [link](./index.html#creating-confirmed-downlinks).
+    pub fn new_confirmed(device: Device, payload_indices: Vec<usize>, message: String, f_port: u32, tracker: AckTracker, ack_timeout: Duration, retries: u32) -> Self {
+        return Action {
+            kind: ActionKind::Downlink(DownlinkAction {
+                device,
+                payload_indices,
+                message,
+                f_port,
+                json_object: None,
+                queue_policy: QueuePolicy::Append,
+                confirmed: Some(Confirmation {
+                    tracker,
+                    timeout: ack_timeout,
+                    retries,
+                }),
+            }),
+            delay: Duration::from_secs(0),
+        }
+    }
+
+    /// Creates a new action, which enqueues a message for all devices of the multicast group
+    /// with the `multicast_group_id`.
+    /// The `indices`, the `message` and the `port` are the same as for a single device (see [`Action::new`]),
+    /// therefore the message is built with the [`Downlink`](crate::devices::Downlink) specification of the device profile,
+    /// which is shared by the devices of the group.<br/>
+    /// For an example see this [link](./index.html#creating-multicast-actions).
+    pub fn new_multicast(multicast_group_id: &str, payload_indices: Vec<usize>, message: String, f_port: u32) -> Self {
+        return Action {
+            kind: ActionKind::Multicast(MulticastAction {
+                multicast_group_id: multicast_group_id.to_string(),
+                payload_indices,
+                message,
+                f_port,
+            }),
+            delay: Duration::from_secs(0),
+        }
+    }
+
+    /// Creates a new action, which publishes a JSON message to the `topic` of a mqtt broker
+    /// with the quality of service `qos`.<br/>
+    /// The `template` is the JSON message, which can contain placeholders (see [`MqttPublishAction`]).<br/>
+    /// Returns an error of kind `InvalidInput`, if the quality of service is not 0, 1 or 2.<br/>
+    /// For an example see this [link](./index.html#creating-mqtt-publish-actions).
+    pub fn new_mqtt_publish(publisher: MqttPublisher, topic: &str, template: &str, qos: i32) -> Result<Self, io::Error> {
+        if !(0..=2).contains(&qos) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "The quality of service has to be 0, 1 or 2"));
+        }
+        return Ok(Action {
+            kind: ActionKind::MqttPublish(MqttPublishAction {
+                publisher,
+                topic: topic.to_string(),
+                template: template.to_string(),
+                qos,
+                placeholder: Regex::new(r"\{\{\s*([^{}]+?)\s*\}\}").unwrap(),
+            }),
+            delay: Duration::from_secs(0),
+        });
+    }
+
+    /// Creates a new action, which posts the trigger of the rule as JSON to a `webhook`.<br/>
+    /// For an example see this [link](./index.html#creating-webhook-actions).
+    pub fn new_webhook(webhook: WebhookAction) -> Self {
+        return Action {
+            kind: ActionKind::Webhook(webhook),
+            delay: Duration::from_secs(0),
+        }
+    }
+
+    /// Gets what is done when the action is executed.
+    pub fn get_kind(&self) -> &ActionKind {
+        self.kind.borrow()
+    }
+
+    /// Gets the device to which the data should be sent;
+    /// `None` if the action is no downlink to a device.
+    pub fn get_device(&self) -> Option<&Device> {
+        match &self.kind {
+            ActionKind::Downlink(downlink) => Some(downlink.get_device()),
+            _ => None,
+        }
+    }
+
+    /// Gets the payload indices of the possible downlinks, that should be used;
+    /// `None` if the action is no downlink to a device or multicast group.
+    pub fn get_payload_indices(&self) -> Option<&[usize]> {
+        match &self.kind {
+            ActionKind::Downlink(downlink) => Some(downlink.get_payload_indices()),
+            ActionKind::Multicast(multicast) => Some(multicast.get_payload_indices()),
+            _ => None,
+        }
+    }
+
+    /// Gets the message to sent;
+    /// `None` if the action is no downlink to a device or multicast group.
+    pub fn get_message(&self) -> Option<String> {
+        match &self.kind {
+            ActionKind::Downlink(downlink) => Some(downlink.get_message()),
+            ActionKind::Multicast(multicast) => Some(multicast.get_message()),
+            _ => None,
+        }
+    }
+
+    /// Gets the port to which data should be sent;
+    /// `None` if the action is no downlink to a device or multicast group.
+    pub fn get_f_port(&self) -> Option<u32> {
+        match &self.kind {
+            ActionKind::Downlink(downlink) => Some(downlink.get_f_port()),
+            ActionKind::Multicast(multicast) => Some(multicast.get_f_port()),
+            _ => None,
+        }
+    }
+
+    /// Sets how the message of a downlink is added to the queue of the device.<br/>
+    /// Returns an error if the action is no downlink to a device.
+    /// For an example see this [link](./index.html#inspecting-and-flushing-device-queues).
+    pub fn set_queue_policy(&mut self, queue_policy: QueuePolicy) -> Result<(), io::Error> {
+        match &mut self.kind {
+            ActionKind::Downlink(downlink) => {
+                downlink.queue_policy = queue_policy;
+                Ok(())
+            }
+            _ => Err(io::Error::new(ErrorKind::InvalidInput, "Only downlinks to a device have a queue policy")),
+        }
+    }
+
+    /// Gets the time to wait before the action is executed.
+    pub fn get_delay(&self) -> Duration {
+        self.delay
+    }
+
+    /// Sets the time to wait before the action is executed, after the previous action of the rule
+    /// or, for the first action, after the conditions were fulfilled.
+    /// Actions which are waiting are dropped, when the rule is stopped.<br/>
+    /// For an example see this [link](./index.html#creating-delayed-actions).
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.delay = delay;
+    }
+}
+
+/**
+    Enum of the different kinds of [`Action`]s.
+ */
+pub enum ActionKind {
+    /// Enqueues a message into the queue of a device.
+    Downlink(DownlinkAction),
+    /// Enqueues a message into the queue of a multicast group.
+    Multicast(MulticastAction),
+    /// Publishes a message to a mqtt broker.
+    MqttPublish(MqttPublishAction),
+    /// Posts a message to a http server.
+    Webhook(WebhookAction),
+}
+
+/**
+    Action which enqueues a downlink message into the queue of a device.
+ */
+pub struct DownlinkAction {
+    /// The device on which the action is executed on.
+    device: Device,
+    /// The indices of the [`DownlinkPayload`](crate::devices::DownlinkPayload)s which should be executed.
+    payload_indices: Vec<usize>,
+    /// The actual message that is sent encoded in hex.
+    message: String,
+    /// The port to which the message is sent to.
+    f_port: u32,
+    /// The message as JSON object, which is encoded by the Chirpstack Application Server;
+    /// `None` if the message is sent encoded in hex.
+    json_object: Option<Value>,
+    /// How the message is added to the queue of the device.
+    queue_policy: QueuePolicy,
+    /// How the acknowledgement is tracked; `None` if the message is unconfirmed.
+    confirmed: Option<Confirmation>,
+}
+
+impl DownlinkAction {
+    /// Gets the device to which the data should be sent.
+    pub fn get_device(&self) -> &Device {
+        self.device.borrow()
+    }
+
+    /// Gets the payload indices of the possible downlinks, that should be used.
+    pub fn get_payload_indices(&self) -> &[usize] {
+        self.payload_indices.borrow()
+    }
+
+    /// Gets the message to sent.
+    pub fn get_message(&self) -> String{
+        self.message.clone()
+    }
+
+    /// Gets the port to which data should be sent.
+    pub fn get_f_port(&self) -> u32 {
+        self.f_port
+    }
+
+    /// Gets the message as JSON object, which is encoded by the Chirpstack Application Server;
+    /// `None` if the message is sent encoded in hex.
+    pub fn get_json_object(&self) -> Option<&Value> {
+        self.json_object.as_ref()
+    }
+
+    /// Gets how the message is added to the queue of the device.
+    pub fn get_queue_policy(&self) -> QueuePolicy {
+        self.queue_policy
+    }
+
+    /// Checks if the message has to be confirmed by the device.
+    pub fn is_confirmed(&self) -> bool {
+        self.confirmed.is_some()
+    }
+
+    /// Gets the time to wait for the acknowledgement; `None` if the message is unconfirmed.
+    pub fn get_ack_timeout(&self) -> Option<Duration> {
+        self.confirmed.as_ref().map(|confirmation| confirmation.timeout)
+    }
+
+    /// Gets the number of times the message is enqueued again, if it is not acknowledged;
+    /// `None` if the message is unconfirmed.
+    pub fn get_retries(&self) -> Option<u32> {
+        self.confirmed.as_ref().map(|confirmation| confirmation.retries)
+    }
+}
+
+/**
+    Enum of the ways a message is added to the queue of a device.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueuePolicy {
+    /// The message is added after the messages, which are not sent yet.
+    Append,
+    /// All messages, which are not sent yet, are removed before the message is added,
+    /// e.g. so that only the newest setpoint is sent.
+    /// This also removes the messages of other rules.
+    ReplacePending,
+}
+
+/// How the acknowledgement of a confirmed downlink is tracked.
+struct Confirmation {
+    /// The tracker which receives the acknowledgements.
+    tracker: AckTracker,
+    /// The time to wait for the acknowledgement.
+    timeout: Duration,
+    /// The number of times the message is enqueued again, if it is not acknowledged.
+    retries: u32,
+}
+
+/**
+    Collects the acknowledgements of confirmed downlinks, which are published by the Chirpstack Application Server
+    to the `ack` event topic.
+    It is created with the receiver of [`Mqtt::start_receiving_acks`](crate::connections::Mqtt::start_receiving_acks)
+    and can be cloned, so that it is shared by all confirmed [`Action`]s.
+ */
+#[derive(Clone)]
+pub struct AckTracker {
+    /// Acknowledgements by dev_eui in hex and id of the downlink, with the frame counter and the time at which they were received.
+    acks: Arc<Mutex<HashMap<(String, String), (bool, u32, Instant)>>>,
+}
+
+impl AckTracker {
+    /// Starts collecting the acknowledgements of the ack event messages of the `receiver`.<br/>
+    /// For an example see this [link](./index.html#creating-confirmed-downlinks).
+    pub fn start(receiver: mpsc::Receiver<Message>) -> Self {
+        let tracker = AckTracker {
+            acks: Arc::new(Mutex::new(HashMap::new())),
+        };
+        let acks = Arc::clone(&tracker.acks);
+        thread::spawn(move || {
+            for message in receiver {
+                let event = match serde_json::from_str::<AckEvent>(&message.payload_str()) {
+                    Ok(event) => event,
+                    Err(_) => continue,
+                };
+                // an acknowledgement without device or downlink can not be assigned to a downlink
+                let (dev_eui, downlink_id) = match (event.get_device().get_dev_eui(), event.get_downlink_id()) {
+                    (Some(dev_eui), Some(downlink_id)) => (dev_eui, downlink_id),
+                    _ => continue,
+                };
+                // the frame counter is only reported, with Chirpstack version 3 it is the id of the downlink
+                let f_cnt = event.get_f_cnt().unwrap_or_default();
+                let acknowledged = event.is_acknowledged();
+                let mut acks = acks.lock().unwrap();
+                // acknowledgements of downlinks, which are not tracked, are removed after an hour
+                acks.retain(|_, (_, _, received)| received.elapsed() < Duration::from_secs(3600));
+                acks.insert((dev_eui, downlink_id), (acknowledged, f_cnt, Instant::now()));
+            }
+        });
+        return tracker;
+    }
+
+    /// Removes and returns the acknowledgement and the frame counter of the downlink with `downlink_id`
+    /// of the device with `dev_eui` in hex; `None` if there is no acknowledgement yet.
+    fn take(&self, dev_eui: &str, downlink_id: &str) -> Option<(bool, u32)> {
+        let mut acks = self.acks.lock().unwrap();
+        return acks.remove(&(dev_eui.to_string(), downlink_id.to_string())).map(|(acknowledged, f_cnt, _)| (acknowledged, f_cnt));
+    }
+}
+
+/**
+    Status of a confirmed downlink.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AckStatus {
+    /// The device acknowledged the downlink.
+    Acknowledged,
+    /// The device did not acknowledge the downlink.
+    NotAcknowledged,
+    /// There was no acknowledgement within the timeout.
+    Timeout,
+}
+
+/**
+    Report of a confirmed downlink of an [`Action`].
+ */
+#[derive(Debug, Clone)]
+pub struct AckReport {
+    /// The index of the action in the rule.
+    action_index: usize,
+    /// The dev_eui of the device in hex.
+    dev_eui: String,
+    /// The frame counter of the downlink.
+    f_cnt: u32,
+    /// The number of the attempt, starting with 1.
+    attempt: u32,
+    /// The status of the downlink.
+    status: AckStatus,
+    /// The time at which the status was known.
+    time: DateTime<Utc>,
+}
+
+impl AckReport {
+    /// Gets the index of the action in the rule.
+    pub fn get_action_index(&self) -> usize {
+        self.action_index
+    }
+
+    /// Gets the dev_eui of the device in hex.
+    pub fn get_dev_eui(&self) -> String {
+        self.dev_eui.clone()
+    }
+
+    /// Gets the frame counter of the downlink.
+    pub fn get_f_cnt(&self) -> u32 {
+        self.f_cnt
+    }
+
+    /// Gets the number of the attempt, starting with 1.
+    pub fn get_attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Gets the status of the downlink.
+    pub fn get_status(&self) -> AckStatus {
+        self.status
+    }
+
+    /// Gets the time at which the status was known.
+    pub fn get_time(&self) -> DateTime<Utc> {
+        self.time
+    }
+}
+
+/**
+    Action which enqueues a downlink message into the queue of a multicast group,
+    so that all devices of the group receive it.
+ */
+pub struct MulticastAction {
+    /// The id of the multicast group.
+    multicast_group_id: String,
+    /// The indices of the [`DownlinkPayload`](crate::devices::DownlinkPayload)s which should be executed.
+    payload_indices: Vec<usize>,
+    /// The actual message that is sent encoded in hex.
+    message: String,
+    /// The port to which the message is sent to.
+    f_port: u32,
+}
+
+impl MulticastAction {
+    /// Gets the id of the multicast group.
+    pub fn get_multicast_group_id(&self) -> String {
+        self.multicast_group_id.clone()
+    }
+
+    /// Gets the payload indices of the possible downlinks, that should be used.
+    pub fn get_payload_indices(&self) -> &[usize] {
+        self.payload_indices.borrow()
+    }
+
+    /// Gets the message to sent.
+    pub fn get_message(&self) -> String{
+        self.message.clone()
+    }
+
+    /// Gets the port to which data should be sent.
+    pub fn get_f_port(&self) -> u32 {
+        self.f_port
+    }
+}
+
+/**
+    Action which publishes a JSON message to a topic of a mqtt broker.
+
+    The template of the message can contain the following placeholders,
+    which are replaced by JSON values, when the action is executed:
+    - `{{rule_id}}`: the id of the rule (see [`Rule::get_id`]),
+    - `{{timestamp}}`: the time at which the conditions were fulfilled, as RFC 3339 string,
+    - `{{<device>.<field>}}`: the value of a field of the decoded uplink of a device,
+      which caused the execution. The device is given by its name or its dev_eui in hex.
+
+    Placeholders without value are replaced by `null`.
+ */
+pub struct MqttPublishAction {
+    /// The publisher which is connected to the broker.
+    publisher: MqttPublisher,
+    /// The topic to which the message is published.
+    topic: String,
+    /// The JSON message with placeholders.
+    template: String,
+    /// The quality of service of the message.
+    qos: i32,
+    /// The regular expression of the placeholders, which is compiled once.
+    placeholder: Regex,
+}
+
+impl MqttPublishAction {
+    /// Gets the topic to which the message is published.
+    pub fn get_topic(&self) -> String {
+        return self.topic.clone();
+    }
+
+    /// Gets the JSON message with placeholders.
+    pub fn get_template(&self) -> String {
+        return self.template.clone();
+    }
+
+    /// Gets the quality of service of the message.
+    pub fn get_qos(&self) -> i32 {
+        return self.qos;
+    }
+
+    /// Replaces the placeholders of the template with the `values`.<br/>
+    /// Returns an error if the result is not valid JSON.
+    pub fn render(&self, values: &HashMap<String, String>) -> Result<String, io::Error> {
+        let payload = self.placeholder.replace_all(&self.template, |captures: &regex::Captures| {
+            match values.get(&captures[1]) {
+                Some(value) => value.clone(),
+                None => "null".to_string(),
+            }
+        }).to_string();
+        if let Err(e) = serde_json::from_str::<Value>(&payload) {
+            return Err(io::Error::new(ErrorKind::InvalidData, "The message is no valid JSON: ".to_owned() + &e.to_string()));
+        }
+        return Ok(payload);
+    }
+}
+
+/**
+    Action which posts a JSON message to a http server, e.g. to create a ticket in an alarm system.
+
+    The message contains the id of the rule, the time at which the conditions were fulfilled
+    and the devices whose uplinks were checked, with the fields of their decoded uplinks:
+    ```json
+    {"rule_id": 1, "timestamp": "2022-06-21T12:00:00+00:00", "devices": [{"name": "sensor", "dev_eui": "0102030405060708", "object": {"temperature": 30.5}}]}
+    ```
+    The message is sent in the background, so that the rule is not blocked while waiting for the server.
+    If the connection fails, or the server answers with a server error or `429 Too Many Requests`,
+    the message is sent again later. Other statuses, e.g. `404 Not Found`, are not tried again.
+ */
+pub struct WebhookAction {
+    /// The client which sends the requests, which is built once.
+    client: reqwest::Client,
+    /// The url to which the message is posted.
+    url: String,
+    /// Additional headers of the request.
+    headers: Vec<(String, String)>,
+    /// The time to wait for an answer of the server.
+    timeout: Duration,
+    /// The number of times the message is sent again, after the first try failed.
+    retries: u32,
+}
+
+impl WebhookAction {
+    /// Creates a new webhook, which posts to the `url`
+    /// with a timeout of 10 secs and 3 retries.
+    pub fn new(url: &str) -> Self {
+        return WebhookAction {
+            client: reqwest::Client::new(),
+            url: url.to_string(),
+            headers: Vec::new(),
+            timeout: Duration::from_secs(10),
+            retries: 3,
+        }
+    }
+
+    /// Gets the url to which the message is posted.
+    pub fn get_url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// Gets the additional headers of the request.
+    pub fn get_headers(&self) -> &[(String, String)] {
+        self.headers.borrow()
+    }
+
+    /// Adds a header to the request, e.g. for authorization.
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    /// Gets the time to wait for an answer of the server.
+    pub fn get_timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Sets the time to wait for an answer of the server.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Gets the number of times the message is sent again, after the first try failed.
+    pub fn get_retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// Sets the number of times the message is sent again, after the first try failed.
+    pub fn set_retries(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+
+    /// Builds the request, which posts the `payload` to the url.
+    fn request(&self, payload: &Value) -> reqwest::RequestBuilder {
+        let mut request = self.client.post(self.url.clone())
+            .timeout(self.timeout)
+            .header("Content-Type", "application/json")
+            .body(payload.to_string());
+        for (name, value) in &self.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        return request;
+    }
+
+    /// Sends the `request` once.<br/>
+    /// Errors of the connection, server errors and `429 Too Many Requests` are transient (see [`RetryPolicy::is_transient`]),
+    /// other statuses which are no success are not.
+    async fn send(request: reqwest::RequestBuilder) -> Result<(), io::Error> {
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(e) if e.is_timeout() => return Err(io::Error::new(ErrorKind::TimedOut, e.to_string())),
+            Err(e) => return Err(io::Error::new(ErrorKind::NotConnected, e.to_string())),
+        };
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let kind = if status.is_server_error() {
+            ErrorKind::NotConnected
+        } else if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            ErrorKind::Interrupted
+        } else {
+            ErrorKind::Other
+        };
+        return Err(io::Error::new(kind, "The server answered with status ".to_owned() + &status.to_string()));
+    }
+
+    /// Gets the time to wait before the next try, after `retry` retries:
+    /// the time is doubled each time, starting with 1 sec.
+    fn backoff(retry: u32) -> Duration {
+        return Duration::from_secs(1).saturating_mul(2u32.saturating_pow(retry));
+    }
+}
+
+/// The actions of a rule which wait for their execution and
+/// the confirmed downlinks which wait for their acknowledgement.
+struct ActionQueue {
+    /// Actions which are executed later, ordered by the time at which they are due.
+    pending: Vec<PendingAction>,
+    /// Confirmed downlinks which are not acknowledged yet.
+    awaiting: Vec<AwaitingAck>,
+    /// The handle of the runtime, on which the requests are executed.
+    handle: Handle,
+    /// The client to manage device queues.
+    client: DeviceQueueServiceClient<Channel>,
+    /// The connection to the Chirpstack Application Server.
+    connection: ChirpstackConnection,
+    /// Sender of the results of webhooks, which are sent in the background.
+    webhook_sender: mpsc::Sender<(PendingAction, Result<(), io::Error>)>,
+    /// Receiver of the results of webhooks, which are sent in the background.
+    webhook_results: mpsc::Receiver<(PendingAction, Result<(), io::Error>)>,
+    /// The number of webhooks which are sent in the background.
+    webhooks_in_flight: usize,
+}
+
+impl ActionQueue {
+    /// Creates a new empty queue, which executes the actions with the `client` and the `connection` on the runtime of the `handle`.
+    fn new(handle: Handle, client: DeviceQueueServiceClient<Channel>, connection: ChirpstackConnection) -> Self {
+        let (webhook_sender, webhook_results) = mpsc::channel();
+        return ActionQueue {
+            pending: Vec::new(),
+            awaiting: Vec::new(),
+            handle,
+            client,
+            connection,
+            webhook_sender,
+            webhook_results,
+            webhooks_in_flight: 0,
+        }
+    }
+
+    /// Adds all actions of a rule to the pending actions, together with the `trigger` of their execution.
+    /// Each action is due after its delay, counted from the previous action.
+    fn schedule(&mut self, actions: &[Action], trigger: Trigger) {
+        let trigger = Arc::new(trigger);
+        let mut due = Instant::now();
+        for (index, l) in actions.iter().enumerate() {
+            due += l.delay;
+            self.pending.push(PendingAction {
+                due,
+                index,
+                trigger: Arc::clone(&trigger),
+                ack_attempt: 1,
+                retry: 0,
+                deferred: false,
+            });
+        }
+        // actions which are due at the same time keep their order
+        self.pending.sort_by_key(|pending_action| pending_action.due);
+    }
+
+    /// Executes all pending actions which are due, one after another, and removes them.
+    /// Afterwards the acknowledgements of confirmed downlinks are checked and reported to the `rule`.
+    fn execute_due(&mut self, rule: &mut Rule) {
+        self.check_webhooks(rule);
+        let now = Instant::now();
+        while !self.pending.is_empty() && self.pending[0].due <= now {
+            let pending_action = self.pending.remove(0);
+            let l = match rule.actions.get(pending_action.index) {
+                Some(l) => l,
+                None => continue,
+            };
+            match &l.kind {
+                ActionKind::Downlink(_) => {
+                    self.enqueue(rule, pending_action);
+                }
+                ActionKind::Multicast(_) => {
+                    self.enqueue_multicast(rule, pending_action);
+                }
+                ActionKind::Webhook(webhook) => {
+                    println!("Sending webhook...");
+                    // the rule is locked, therefore the request is sent in the background
+                    let request = webhook.request(&pending_action.trigger.to_json());
+                    let sender = self.webhook_sender.clone();
+                    self.webhooks_in_flight += 1;
+                    self.handle.spawn(async move {
+                        let res = WebhookAction::send(request).await;
+                        let _ = sender.send((pending_action, res));
+                    });
+                }
+                ActionKind::MqttPublish(mqtt_publish) => {
+                    println!("Publishing message...");
+                    let res = mqtt_publish.render(&pending_action.trigger.template_values())
+                        .and_then(|payload| mqtt_publish.publisher.publish(&mqtt_publish.topic, &payload, mqtt_publish.qos));
+                    if let Err(e) = res {
+                        println!("Message could not be published: {}", e.to_string().as_str());
+                    }
+                }
+            }
+        }
+        self.check_acks(rule);
+    }
+
+    /// Checks the results of the webhooks, which were sent in the background.
+    /// A webhook with a transient error is sent again later, if there are retries left.
+    fn check_webhooks(&mut self, rule: &Rule) {
+        while let Ok((pending_action, res)) = self.webhook_results.try_recv() {
+            self.webhooks_in_flight -= 1;
+            let e = match res {
+                Ok(()) => continue,
+                Err(e) => e,
+            };
+            let webhook = match rule.actions.get(pending_action.index).map(|l| &l.kind) {
+                Some(ActionKind::Webhook(webhook)) => webhook,
+                _ => continue,
+            };
+            if RetryPolicy::is_transient(&e) && pending_action.retry < webhook.retries {
+                let backoff = WebhookAction::backoff(pending_action.retry);
+                println!("Webhook failed: {}\nTrying again in {} secs...", e.to_string().as_str(), backoff.as_secs_f32());
+                let retry = PendingAction {
+                    due: Instant::now() + backoff,
+                    retry: pending_action.retry + 1,
+                    ..pending_action
+                };
+                let position = self.pending.partition_point(|p| p.due <= retry.due);
+                self.pending.insert(position, retry);
+            } else {
+                println!("Webhook could not be sent: {}", e.to_string().as_str());
+            }
+        }
+    }
+
+    /// Enqueues the message of the downlink of the `pending_action`.
+    /// A confirmed downlink waits for its acknowledgement afterwards.<br/>
+    /// If there is a transient error, the downlink is tried again later, according to the retry policy of the `rule`.
+    /// If it ultimately fails, it is added to the dead letter store of the rule.
+    fn enqueue(&mut self, rule: &Rule, pending_action: PendingAction) {
+        let downlink = match rule.actions.get(pending_action.index).map(|l| &l.kind) {
+            Some(ActionKind::Downlink(downlink)) => downlink,
+            _ => return,
+        };
+        println!("Enqueueing message...");
+        let dev_eui = downlink.device.get_chirpstack_device().device.unwrap().dev_eui;
+        let json_object = match &downlink.json_object {
+            Some(json_object) => json_object.to_string(),
+            None => String::new(),
+        };
+        // the size of JSON objects is only known after encoding, therefore the maximum payload at the lowest data rates is assumed
+        let payload_len = match &downlink.json_object {
+            Some(_) => 51,
+            None => downlink.message.len() / 2,
+        };
+        if let Some(airtime_budget) = &rule.airtime_budget {
+            let device = downlink.device.get_chirpstack_device().device.unwrap();
+            // the airtime is only counted, when the message is enqueued
+            let res = airtime_budget.check(&dev_eui, &device.device_profile_id, payload_len);
+            if let Err((reason, available)) = res {
+                match airtime_budget.defer_until(available) {
+                    None => {
+                        println!("Message is dropped: {}", reason);
+                    }
+                    Some(available) => {
+                        println!("Message is delayed: {}", reason);
+                        // an older delayed message of the action is replaced by the newer one
+                        let index = pending_action.index;
+                        let count = self.pending.len();
+                        self.pending.retain(|p| !(p.deferred && p.index == index));
+                        if self.pending.len() < count {
+                            println!("Older delayed message is dropped: it is replaced by the newer one");
+                        }
+                        let deferred = PendingAction {
+                            due: available,
+                            deferred: true,
+                            ..pending_action
+                        };
+                        let position = self.pending.partition_point(|p| p.due <= deferred.due);
+                        self.pending.insert(position, deferred);
+                    }
+                }
+                return
+            }
+        }
+        let clone_connection = self.connection.clone();
+        let clone_client = self.client.clone();
+        let res = self.handle.block_on(async {
+            if downlink.queue_policy == QueuePolicy::ReplacePending {
+                if let Err(e) = RuleContainer::flush_queue(clone_client.clone(), &dev_eui, clone_connection.clone()).await {
+                    return Err(e);
+                }
+            }
+            RuleContainer::enqueue_message(clone_client, dev_eui.clone(), downlink.f_port, downlink.message.clone(), json_object.clone(), downlink.confirmed.is_some(), clone_connection).await
+        });
+        let downlink_id = match res {
+            Ok(downlink_id) => {
+                if let Some(airtime_budget) = &rule.airtime_budget {
+                    let device = downlink.device.get_chirpstack_device().device.unwrap();
+                    airtime_budget.record(&dev_eui, &device.device_profile_id, payload_len);
+                }
+                downlink_id
+            },
+            Err(e) => {
+                println!("Message could not be enqueued: {}", e.to_string().as_str());
+                if RetryPolicy::is_transient(&e) && pending_action.retry < rule.retry_policy.retries {
+                    let backoff = rule.retry_policy.backoff(pending_action.retry);
+                    println!("Trying again in {} secs...", backoff.as_secs_f32());
+                    let retry = PendingAction {
+                        due: Instant::now() + backoff,
+                        retry: pending_action.retry + 1,
+                        ..pending_action
+                    };
+                    let position = self.pending.partition_point(|p| p.due <= retry.due);
+                    self.pending.insert(position, retry);
+                } else if let Some(dead_letter_store) = &rule.dead_letter_store {
+                    let dead_letter = DeadLetter {
+                        rule_id: rule.id,
+                        dev_eui,
+                        multicast_group_id: String::new(),
+                        f_port: downlink.f_port,
+                        message: downlink.message.clone(),
+                        json_object,
+                        confirmed: downlink.confirmed.is_some(),
+                        error: e.to_string(),
+                        attempts: pending_action.retry + 1,
+                        time: Utc::now().to_rfc3339(),
+                    };
+                    if let Err(e) = dead_letter_store.add(dead_letter) {
+                        println!("Dead letter could not be stored: {}", e.to_string().as_str());
+                    }
+                }
+                return
+            }
+        };
+        if let Some(confirmation) = &downlink.confirmed {
+            self.awaiting.push(AwaitingAck {
+                index: pending_action.index,
+                dev_eui: dev_eui.to_lowercase(),
+                downlink_id,
+                deadline: Instant::now() + confirmation.timeout,
+                attempt: pending_action.ack_attempt,
+                trigger: pending_action.trigger,
+            });
+        }
+    }
+
+    /// Enqueues the message of the multicast downlink of the `pending_action`.<br/>
+    /// If there is a transient error, the downlink is tried again later, according to the retry policy of the `rule`.
+    /// If it ultimately fails, it is added to the dead letter store of the rule.
+    fn enqueue_multicast(&mut self, rule: &Rule, pending_action: PendingAction) {
+        let multicast = match rule.actions.get(pending_action.index).map(|l| &l.kind) {
+            Some(ActionKind::Multicast(multicast)) => multicast,
+            _ => return,
+        };
+        println!("Enqueueing multicast message...");
+        let clone_connection = self.connection.clone();
+        let res = self.handle.block_on(async {RuleContainer::enqueue_multicast_message(multicast.multicast_group_id.clone(), multicast.f_port, multicast.message.clone(), clone_connection).await});
+        if let Err(e) = res {
+            println!("Multicast message could not be enqueued: {}", e.to_string().as_str());
+            if RetryPolicy::is_transient(&e) && pending_action.retry < rule.retry_policy.retries {
+                let backoff = rule.retry_policy.backoff(pending_action.retry);
+                println!("Trying again in {} secs...", backoff.as_secs_f32());
+                let retry = PendingAction {
+                    due: Instant::now() + backoff,
+                    retry: pending_action.retry + 1,
+                    ..pending_action
+                };
+                let position = self.pending.partition_point(|p| p.due <= retry.due);
+                self.pending.insert(position, retry);
+            } else if let Some(dead_letter_store) = &rule.dead_letter_store {
+                let dead_letter = DeadLetter {
+                    rule_id: rule.id,
+                    dev_eui: String::new(),
+                    multicast_group_id: multicast.multicast_group_id.clone(),
+                    f_port: multicast.f_port,
+                    message: multicast.message.clone(),
+                    json_object: String::new(),
+                    confirmed: false,
+                    error: e.to_string(),
+                    attempts: pending_action.retry + 1,
+                    time: Utc::now().to_rfc3339(),
+                };
+                if let Err(e) = dead_letter_store.add(dead_letter) {
+                    println!("Dead letter could not be stored: {}", e.to_string().as_str());
+                }
+            }
+        }
+    }
+
+    /// Checks the confirmed downlinks, which wait for their acknowledgement,
+    /// reports the acknowledged, not acknowledged and timed out ones to the `rule`
+    /// and enqueues them again, if there are retries left.
+    fn check_acks(&mut self, rule: &mut Rule) {
+        let awaiting = std::mem::take(&mut self.awaiting);
+        for awaiting_ack in awaiting {
+            let confirmation = match rule.actions.get(awaiting_ack.index).map(|l| &l.kind) {
+                Some(ActionKind::Downlink(DownlinkAction { confirmed: Some(confirmation), .. })) => confirmation,
+                _ => continue,
+            };
+            let (status, f_cnt) = match confirmation.tracker.take(&awaiting_ack.dev_eui, &awaiting_ack.downlink_id) {
+                Some((true, f_cnt)) => (AckStatus::Acknowledged, f_cnt),
+                Some((false, f_cnt)) => (AckStatus::NotAcknowledged, f_cnt),
+                // the id of a downlink is only its frame counter with Chirpstack version 3
+                None if Instant::now() >= awaiting_ack.deadline => (AckStatus::Timeout, awaiting_ack.downlink_id.parse().unwrap_or(0)),
+                None => {
+                    self.awaiting.push(awaiting_ack);
+                    continue
+                }
+            };
+            println!("Downlink {} of device {} is {:?}", awaiting_ack.downlink_id, awaiting_ack.dev_eui, status);
+            let report = AckReport {
+                action_index: awaiting_ack.index,
+                dev_eui: awaiting_ack.dev_eui.clone(),
+                f_cnt,
+                attempt: awaiting_ack.attempt,
+                status,
+                time: Utc::now(),
+            };
+            if status != AckStatus::Acknowledged && awaiting_ack.attempt <= confirmation.retries {
+                let retry = PendingAction {
+                    due: Instant::now(),
+                    index: awaiting_ack.index,
+                    trigger: awaiting_ack.trigger,
+                    ack_attempt: awaiting_ack.attempt + 1,
+                    retry: 0,
+                    deferred: false,
+                };
+                self.enqueue(rule, retry);
+            }
+            rule.add_ack_report(report);
+        }
+    }
+
+    /// Gets the time to wait for new messages: 5 secs, 1 sec while webhooks are sent,
+    /// or less if a pending action is due earlier.
+    fn waiting_time(&self) -> Duration {
+        // the results of webhooks, which are sent in the background, are checked every second
+        let waiting_time = if self.webhooks_in_flight > 0 { Duration::from_secs(1) } else { Duration::from_secs(5) };
+        match self.pending.first() {
+            Some(pending_action) => waiting_time.min(pending_action.due.saturating_duration_since(Instant::now())),
+            None => waiting_time,
+        }
+    }
+}
+
+/// A confirmed downlink, which waits for its acknowledgement.
+struct AwaitingAck {
+    /// The index of the action in the rule.
+    index: usize,
+    /// The dev_eui of the device in hex.
+    dev_eui: String,
+    /// The id of the downlink, which is its frame counter with Chirpstack version 3 and the id of the queue item with version 4.
+    downlink_id: String,
+    /// The time after which the downlink is timed out.
+    deadline: Instant,
+    /// The number of the attempt, starting with 1.
+    attempt: u32,
+    /// What caused the execution of the downlink.
+    trigger: Arc<Trigger>,
+}
+
+/// An action of a rule, which waits for its delay.
+struct PendingAction {
+    /// The time at which the action is executed.
+    due: Instant,
+    /// The index of the action in the rule.
+    index: usize,
+    /// What caused the execution, which is shared by the actions of one execution.
+    trigger: Arc<Trigger>,
+    /// The number of the attempt of a confirmed downlink, starting with 1.
+    ack_attempt: u32,
+    /// The number of retries after transient errors so far.
+    retry: u32,
+    /// Indicator if the downlink was delayed, because the airtime budget was exceeded.
+    deferred: bool,
+}
+
+/// What caused the execution of the actions of a rule.
+struct Trigger {
+    /// The id of the rule.
+    rule_id: u64,
+    /// The time at which the conditions were fulfilled.
+    timestamp: DateTime<Utc>,
+    /// The name, the dev_eui in hex and the decoded object of every uplink which was checked.
+    uplinks: Vec<(String, String, serde_json::Map<String, Value>)>,
+}
+
+impl Trigger {
+    /// Creates a new trigger of the rule with `rule_id` from the uplink `messages` which were checked.
+    fn new(rule_id: u64, messages: &[Message]) -> Self {
+        let mut uplinks = Vec::new();
+        for m in messages {
+            let event = match UplinkEvent::parse(&m.payload_str()) {
+                Ok(event) => event,
+                Err(_) => continue,
+            };
+            let object = match event.get_object() {
+                Ok(Value::Object(object)) => object,
+                _ => serde_json::Map::new(),
+            };
+            let name = event.get_device().get_device_name();
+            let dev_eui = event.get_device().get_dev_eui().unwrap_or_default();
+            uplinks.push((name, dev_eui, object));
+        }
+        return Trigger {
+            rule_id,
+            timestamp: Utc::now(),
+            uplinks,
+        }
+    }
+
+    /// Gets the values for the placeholders of templates, encoded as JSON.
+    /// The fields of the uplinks are added once with the device name and once with the dev_eui as prefix.
+    fn template_values(&self) -> HashMap<String, String> {
+        let mut values = HashMap::new();
+        values.insert("rule_id".to_string(), self.rule_id.to_string());
+        values.insert("timestamp".to_string(), Value::String(self.timestamp.to_rfc3339()).to_string());
+        for (name, dev_eui, object) in &self.uplinks {
+            for (field, value) in object {
+                values.insert(name.clone() + "." + field, value.to_string());
+                values.insert(dev_eui.clone() + "." + field, value.to_string());
+            }
+        }
+        return values;
+    }
+
+    /// Gets the trigger as JSON object with the rule id, the timestamp and the devices with their field values.
+    fn to_json(&self) -> Value {
+        let devices: Vec<Value> = self.uplinks.iter().map(|(name, dev_eui, object)| {
+            serde_json::json!({
+                "name": name,
+                "dev_eui": dev_eui,
+                "object": object,
+            })
+        }).collect();
+        return serde_json::json!({
+            "rule_id": self.rule_id,
+            "timestamp": self.timestamp.to_rfc3339(),
+            "devices": devices,
+        });
+    }
+}
+
+/// The identifier of the next rule which is created.
+static NEXT_RULE_ID: AtomicU64 = AtomicU64::new(1);
+
+/**
+    Representation of a rule.
+ */
+pub struct Rule {
+    /// Condition that should be satisfied.
+    conditions: Vec<Condition>,
+    /// Boolean operators which should be used for two conditions.
+    bool_ops: Vec<String>,
+    /// Actions that should be executed.
+    actions: Vec<Action>,
+    /// Schedule at which the actions should be executed, independent of conditions;
+    /// `None` if the rule depends on its conditions.
+    schedule: Option<Schedule>,
+    /// Identifier of the rule, which is unique within the process.
+    id: u64,
+    /// Reports of the confirmed downlinks, the newest last.
+    ack_reports: Vec<AckReport>,
+    /// How downlinks are retried after transient errors.
+    retry_policy: RetryPolicy,
+    /// Store of the downlinks, which ultimately failed; `None` if they are dropped.
+    dead_letter_store: Option<DeadLetterStore>,
+    /// Budget of the airtime of downlinks; `None` if the airtime is not limited.
+    airtime_budget: Option<AirtimeBudget>,
+    /// Indicator if the rule is momentarily executed.
+    running: bool
+}
+
+impl Rule {
+    /// Creates a new rule with `conditions` that should be satisfied,
+    /// `boolean operators` which should be used for two conditions and
+    /// `actions` that should be executed.<br/>
+    /// For an example see this [link](./index.html#creating-a-rule-depending-on-a-device).
     pub fn new(conditions: Vec<Condition>, bool_ops: Vec<String>, actions: Vec<Action>,) -> Arc<Mutex<Self>> {
         return Arc::new(Mutex::new(Rule {
             conditions,
             bool_ops,
             actions,
+            schedule: None,
+            id: NEXT_RULE_ID.fetch_add(1, Ordering::Relaxed),
+            ack_reports: Vec::new(),
+            retry_policy: RetryPolicy::new(3, Duration::from_secs(1), Duration::from_secs(60)),
+            dead_letter_store: None,
+            airtime_budget: None,
+            running: false,
+        }))
+    }
+
+    /// Creates a new rule, which executes its `actions` at the times of the `schedule`,
+    /// independent of messages.<br/>
+    /// For an example see this [link](./index.html#creating-scheduled-rules).
+    pub fn new_scheduled(schedule: Schedule, actions: Vec<Action>) -> Arc<Mutex<Self>> {
+        return Arc::new(Mutex::new(Rule {
+            conditions: Vec::new(),
+            bool_ops: Vec::new(),
+            actions,
+            schedule: Some(schedule),
+            id: NEXT_RULE_ID.fetch_add(1, Ordering::Relaxed),
+            ack_reports: Vec::new(),
+            retry_policy: RetryPolicy::new(3, Duration::from_secs(1), Duration::from_secs(60)),
+            dead_letter_store: None,
+            airtime_budget: None,
             running: false,
         }))
     }
 
-    /// Checks if rule is executed.
-    pub fn is_running(&self) -> bool{
-        return self.running;
+    /// Gets the identifier of the rule, which is unique within the process.
+    pub fn get_id(&self) -> u64 {
+        self.id
+    }
+
+    /// Checks if rule is executed.
+    pub fn is_running(&self) -> bool{
+        return self.running;
+    }
+
+    /// Gets condition.
+    pub fn get_conditions(&self) -> &[Condition] {
+        self.conditions.borrow()
+    }
+
+    /// Get bool_ops.
+    pub fn get_bool_ops(&self) -> &[String] {
+        self.bool_ops.borrow()
+    }
+
+    /// Get actions.
+    pub fn get_action(&self) -> &[Action] {
+        self.actions.borrow()
+    }
+
+    /// Gets the schedule; `None` if the rule depends on its conditions.
+    pub fn get_schedule(&self) -> Option<&Schedule> {
+        self.schedule.as_ref()
+    }
+
+    /// Gets the reports of the confirmed downlinks of the actions, the newest last.
+    /// Only the last 100 reports are kept.
+    pub fn get_ack_reports(&self) -> &[AckReport] {
+        self.ack_reports.borrow()
+    }
+
+    /// Removes all reports of confirmed downlinks.
+    pub fn clear_ack_reports(&mut self) {
+        self.ack_reports.clear();
+    }
+
+    /// Gets how downlinks are retried after transient errors.
+    pub fn get_retry_policy(&self) -> &RetryPolicy {
+        self.retry_policy.borrow()
+    }
+
+    /// Sets how downlinks are retried after transient errors.
+    /// By default they are retried 3 times, starting after 1 sec.<br/>
+    /// For an example see this [link](./index.html#retrying-failed-downlinks).
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    /// Gets the store of the downlinks, which ultimately failed; `None` if they are dropped.
+    pub fn get_dead_letter_store(&self) -> Option<&DeadLetterStore> {
+        self.dead_letter_store.as_ref()
+    }
+
+    /// Sets the store of the downlinks, which ultimately failed.
+    /// The store can be shared by several rules.<br/>
+    /// For an example see this [link](./index.html#retrying-failed-downlinks).
+    pub fn set_dead_letter_store(&mut self, dead_letter_store: DeadLetterStore) {
+        self.dead_letter_store = Some(dead_letter_store);
+    }
+
+    /// Gets the budget of the airtime of downlinks; `None` if the airtime is not limited.
+    pub fn get_airtime_budget(&self) -> Option<&AirtimeBudget> {
+        self.airtime_budget.as_ref()
+    }
+
+    /// Sets the budget of the airtime of downlinks.
+    /// The budget should be shared by all rules, so that the airtime of all downlinks is counted.<br/>
+    /// For an example see this [link](./index.html#limiting-the-airtime-of-downlinks).
+    pub fn set_airtime_budget(&mut self, airtime_budget: AirtimeBudget) {
+        self.airtime_budget = Some(airtime_budget);
+    }
+
+    /// Adds a report of a confirmed downlink, the oldest reports are removed.
+    fn add_ack_report(&mut self, report: AckReport) {
+        if self.ack_reports.len() >= 100 {
+            self.ack_reports.remove(0);
+        }
+        self.ack_reports.push(report);
+    }
+
+}
+
+/**
+    Policy how downlinks are retried after transient errors of the Chirpstack Application Server,
+    e.g. if it is unavailable for a short time.
+    The time between the tries is doubled each time, up to a maximum.
+ */
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// The number of retries after the first try.
+    retries: u32,
+    /// The time to wait before the first retry.
+    initial_backoff: Duration,
+    /// The maximum time to wait before a retry.
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Creates a new policy with the number of `retries` after the first try,
+    /// the time to wait before the first retry `initial_backoff`
+    /// and the maximum time to wait `max_backoff`.
+    pub fn new(retries: u32, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        return RetryPolicy {
+            retries,
+            initial_backoff,
+            max_backoff,
+        }
+    }
+
+    /// Gets the number of retries after the first try.
+    pub fn get_retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// Gets the time to wait before the first retry.
+    pub fn get_initial_backoff(&self) -> Duration {
+        self.initial_backoff
+    }
+
+    /// Gets the maximum time to wait before a retry.
+    pub fn get_max_backoff(&self) -> Duration {
+        self.max_backoff
+    }
+
+    /// Gets the time to wait before the retry with the number `retry`, starting with 0.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry);
+        return self.initial_backoff.saturating_mul(factor).min(self.max_backoff);
+    }
+
+    /// Checks if an error of a request is transient, so that the request can be tried again.
+    pub fn is_transient(error: &io::Error) -> bool {
+        return matches!(error.kind(), ErrorKind::NotConnected | ErrorKind::TimedOut | ErrorKind::Interrupted);
+    }
+
+    /// Changes the status of a failed request into an error,
+    /// whose kind shows if the error is transient (see [`RetryPolicy::is_transient`]).
+    fn status_to_error(status: Status) -> io::Error {
+        let kind = match status.code() {
+            Code::Unavailable => ErrorKind::NotConnected,
+            Code::DeadlineExceeded => ErrorKind::TimedOut,
+            Code::ResourceExhausted | Code::Aborted => ErrorKind::Interrupted,
+            // the connection errors of the channel of Chirpstack version 3 have no code
+            Code::Unknown if status.message() == "transport error" => ErrorKind::NotConnected,
+            _ => ErrorKind::Other,
+        };
+        return io::Error::new(kind, format!("{} ({})", status.message(), status.code()));
+    }
+}
+
+/**
+    Enum of the ways downlinks are handled, which exceed an [`AirtimeBudget`].
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BudgetPolicy {
+    /// The downlink is dropped.
+    Drop,
+    /// The downlink is delayed until there is enough airtime.
+    /// If there is already a delayed downlink of the same action, it is replaced by the newer one.
+    /// A downlink which alone exceeds the limit is dropped, because there is never enough airtime.
+    Coalesce,
+}
+
+/**
+    Budget of the airtime of downlinks within a time window, per device and per gateway,
+    e.g. to comply with the duty cycle limits in EU868.
+
+    The airtime is estimated from the size of the payload and the data rate of the RX2 window,
+    which is given by the region and the data rate of the device profile
+    (see [`AirtimeBudget::add_device_profile`]); for unknown device profiles SF12 with 125 kHz is assumed.
+    The gateway of a device is the one which received its last uplink with the best RSSI.
+    Only the uplinks of the devices in the conditions of a rule are observed,
+    the uplinks of other devices have to be passed to [`AirtimeBudget::observe_uplink`].<br/>
+    The airtime of a downlink is only counted, when it is enqueued, so that failed downlinks and their retries use no airtime.
+    The budget can be cloned, so that it is shared by all rules.
+ */
+#[derive(Clone)]
+pub struct AirtimeBudget {
+    /// The time window in which the airtime is counted.
+    window: Duration,
+    /// The maximum airtime per device within the window.
+    device_limit: Duration,
+    /// The maximum airtime per gateway within the window.
+    gateway_limit: Duration,
+    /// How downlinks are handled, which exceed the budget.
+    policy: BudgetPolicy,
+    /// The airtime used so far and the gateways of the devices.
+    usage: Arc<Mutex<AirtimeUsage>>,
+}
+
+/// The airtime used by devices and gateways.
+struct AirtimeUsage {
+    /// Spreading factor and bandwidth in kHz of the downlinks by device profile id.
+    modulations: HashMap<String, (u32, u32)>,
+    /// The gateway in hex by dev_eui in hex.
+    gateways: HashMap<String, String>,
+    /// The start and the airtime of the downlinks by dev_eui in hex.
+    devices: HashMap<String, Vec<(Instant, Duration)>>,
+    /// The start and the airtime of the downlinks by gateway in hex.
+    gateway_usage: HashMap<String, Vec<(Instant, Duration)>>,
+}
+
+impl AirtimeBudget {
+    /// Creates a new budget with the maximum airtime `device_limit` per device
+    /// and `gateway_limit` per gateway within the time `window`.
+    /// The `policy` is how downlinks are handled, which exceed the budget.<br/>
+    /// E.g. for the duty cycle of 1 % in EU868 the limit is 36 secs within an hour.
+    pub fn new(window: Duration, device_limit: Duration, gateway_limit: Duration, policy: BudgetPolicy) -> Self {
+        return AirtimeBudget {
+            window,
+            device_limit,
+            gateway_limit,
+            policy,
+            usage: Arc::new(Mutex::new(AirtimeUsage {
+                modulations: HashMap::new(),
+                gateways: HashMap::new(),
+                devices: HashMap::new(),
+                gateway_usage: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Gets the time window in which the airtime is counted.
+    pub fn get_window(&self) -> Duration {
+        self.window
+    }
+
+    /// Gets the maximum airtime per device within the window.
+    pub fn get_device_limit(&self) -> Duration {
+        self.device_limit
+    }
+
+    /// Gets the maximum airtime per gateway within the window.
+    pub fn get_gateway_limit(&self) -> Duration {
+        self.gateway_limit
+    }
+
+    /// Gets how downlinks are handled, which exceed the budget.
+    pub fn get_policy(&self) -> BudgetPolicy {
+        self.policy
+    }
+
+    /// Adds the region and the RX2 data rate of a loaded `device_profile`,
+    /// which are used to estimate the airtime of the downlinks of its devices.<br/>
+    /// Returns an error if the device profile is not loaded from the Chirpstack Application Server
+    /// or the data rate is unknown in the region.
+    pub fn add_device_profile(&self, device_profile: &DeviceProfile) -> Result<(), io::Error> {
+        let (region, data_rate) = match (device_profile.get_region(), device_profile.get_rx2_data_rate()) {
+            (Some(region), Some(data_rate)) => (region, data_rate),
+            _ => return Err(io::Error::new(ErrorKind::InvalidInput, "The device profile is not loaded")),
+        };
+        let modulation = AirtimeBudget::modulation(&region, data_rate);
+        let modulation = match modulation {
+            Some(modulation) => modulation,
+            None => return Err(io::Error::new(ErrorKind::InvalidInput, "The data rate ".to_owned() + &data_rate.to_string() + " is unknown in region " + &region)),
+        };
+        let mut usage = self.usage.lock().unwrap();
+        usage.modulations.insert(device_profile.get_id(), modulation);
+        Ok(())
+    }
+
+    /// Gets the airtime used by the device with `dev_eui` in hex within the window.
+    pub fn get_device_usage(&self, dev_eui: &str) -> Duration {
+        let mut usage = self.usage.lock().unwrap();
+        let window = self.window;
+        return usage.devices.get_mut(&dev_eui.to_lowercase()).map(|used| AirtimeBudget::used(used, window)).unwrap_or_default();
+    }
+
+    /// Gets the airtime used by the gateway with `gateway_id` in hex within the window.
+    pub fn get_gateway_usage(&self, gateway_id: &str) -> Duration {
+        let mut usage = self.usage.lock().unwrap();
+        let window = self.window;
+        return usage.gateway_usage.get_mut(&gateway_id.to_lowercase()).map(|used| AirtimeBudget::used(used, window)).unwrap_or_default();
+    }
+
+    /// Estimates the airtime of a downlink with a payload of `payload_len` bytes,
+    /// with the spreading factor `spreading_factor` and the bandwidth `bandwidth` in kHz.
+    /// The LoRaWAN frame adds 13 bytes to the payload,
+    /// the coding rate is 4/5 and the preamble has 8 symbols.
+    pub fn airtime(payload_len: usize, spreading_factor: u32, bandwidth: u32) -> Duration {
+        let sf = spreading_factor as f64;
+        let symbol_time = 2f64.powf(sf) / (bandwidth as f64 * 1000.0);
+        let preamble_time = (8.0 + 4.25) * symbol_time;
+        // low data rate optimization
+        let de = if spreading_factor >= 11 && bandwidth == 125 { 1.0 } else { 0.0 };
+        // explicit header and no CRC, as for downlinks
+        let bits = 8.0 * (payload_len + 13) as f64 - 4.0 * sf + 28.0;
+        let payload_symbols = 8.0 + ((bits / (4.0 * (sf - 2.0 * de))).ceil() * 5.0).max(0.0);
+        return Duration::from_secs_f64(preamble_time + payload_symbols * symbol_time);
+    }
+
+    /// Gets the spreading factor and the bandwidth in kHz of the data rate `dr` in the `region`;
+    /// `None` if it is no LoRa data rate of the region.
+    fn modulation(region: &str, dr: u32) -> Option<(u32, u32)> {
+        match region {
+            "US915" => match dr {
+                0..=3 => Some((10 - dr, 125)),
+                4 => Some((8, 500)),
+                8..=13 => Some((20 - dr, 500)),
+                _ => None,
+            },
+            "AU915" => match dr {
+                0..=5 => Some((12 - dr, 125)),
+                6 => Some((8, 500)),
+                8..=13 => Some((20 - dr, 500)),
+                _ => None,
+            },
+            _ => match dr {
+                0..=5 => Some((12 - dr, 125)),
+                6 => Some((7, 250)),
+                _ => None,
+            },
+        }
+    }
+
+    /// Remembers the gateway, which received the uplink `message` of a device with the best RSSI.<br/>
+    /// The rules observe the uplinks of the devices in their conditions.
+    /// The uplinks of other devices, e.g. of actuators which only receive downlinks, have to be passed here,
+    /// otherwise the gateway budget does not apply to their downlinks.
+    pub fn observe_uplink(&self, message: &Message) {
+        let event = match UplinkEvent::parse(&message.payload_str()) {
+            Ok(event) => event,
+            Err(_) => return,
+        };
+        if let (Some(dev_eui), Some(gateway)) = (event.get_device().get_dev_eui(), event.get_best_gateway()) {
+            let mut usage = self.usage.lock().unwrap();
+            usage.gateways.insert(dev_eui, gateway.get_gateway_id());
+        }
+    }
+
+    /// Estimates the airtime of a downlink of `payload_len` bytes for the device profile with `device_profile_id`.
+    fn estimate(&self, device_profile_id: &str, payload_len: usize) -> Duration {
+        let usage = self.usage.lock().unwrap();
+        let (spreading_factor, bandwidth) = usage.modulations.get(device_profile_id).cloned().unwrap_or((12, 125));
+        return AirtimeBudget::airtime(payload_len, spreading_factor, bandwidth);
+    }
+
+    /// Checks if a downlink of `payload_len` bytes
+    /// for the device with `dev_eui` and the device profile with `device_profile_id` is within the budget.
+    /// The airtime is not counted, until it is recorded (see [`AirtimeBudget::record`]).<br/>
+    /// Otherwise returns the reason and the time at which there is enough airtime again;
+    /// `None` if the downlink alone exceeds the limit.
+    fn check(&self, dev_eui: &str, device_profile_id: &str, payload_len: usize) -> Result<(), (String, Option<Instant>)> {
+        let airtime = self.estimate(device_profile_id, payload_len);
+        let mut usage = self.usage.lock().unwrap();
+        let window = self.window;
+        let dev_eui = dev_eui.to_lowercase();
+        let gateway = usage.gateways.get(&dev_eui).cloned();
+
+        let device_used = usage.devices.entry(dev_eui.clone()).or_default();
+        if AirtimeBudget::used(device_used, window) + airtime > self.device_limit {
+            let available = AirtimeBudget::available(device_used, window, self.device_limit, airtime);
+            return Err((AirtimeBudget::reason("device", &dev_eui, available), available));
+        }
+        if let Some(gateway) = &gateway {
+            let gateway_used = usage.gateway_usage.entry(gateway.clone()).or_default();
+            if AirtimeBudget::used(gateway_used, window) + airtime > self.gateway_limit {
+                let available = AirtimeBudget::available(gateway_used, window, self.gateway_limit, airtime);
+                return Err((AirtimeBudget::reason("gateway", gateway, available), available));
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts the airtime of a downlink of `payload_len` bytes, which was enqueued,
+    /// for the device with `dev_eui` and the device profile with `device_profile_id`.
+    fn record(&self, dev_eui: &str, device_profile_id: &str, payload_len: usize) {
+        let airtime = self.estimate(device_profile_id, payload_len);
+        let mut usage = self.usage.lock().unwrap();
+        let dev_eui = dev_eui.to_lowercase();
+        if let Some(gateway) = usage.gateways.get(&dev_eui).cloned() {
+            usage.gateway_usage.entry(gateway).or_default().push((Instant::now(), airtime));
+        }
+        usage.devices.entry(dev_eui).or_default().push((Instant::now(), airtime));
     }
 
-    /// Gets condition.
-    pub fn get_conditions(&self) -> &[Condition] {
-        self.conditions.borrow()
+    /// Gets the time until which a downlink, which exceeds the budget, is delayed,
+    /// if there is enough airtime again at the time `available`;
+    /// `None` if it is dropped, because of the policy or because it alone exceeds the limit.
+    fn defer_until(&self, available: Option<Instant>) -> Option<Instant> {
+        match self.policy {
+            BudgetPolicy::Drop => None,
+            BudgetPolicy::Coalesce => available,
+        }
     }
 
-    /// Get bool_ops.
-    pub fn get_bool_ops(&self) -> &[String] {
-        self.bool_ops.borrow()
+    /// Gets the reason why a downlink exceeds the budget of the device or gateway with `id`.
+    fn reason(kind: &str, id: &str, available: Option<Instant>) -> String {
+        match available {
+            Some(_) => "airtime budget of ".to_owned() + kind + " " + id + " is exceeded",
+            None => "the downlink alone exceeds the airtime budget of ".to_owned() + kind + " " + id,
+        }
     }
 
-    /// Get actions.
-    pub fn get_action(&self) -> &[Action] {
-        self.actions.borrow()
+    /// Removes the downlinks which are older than the `window` from `used` and sums up the airtime of the others.
+    fn used(used: &mut Vec<(Instant, Duration)>, window: Duration) -> Duration {
+        used.retain(|(start, _)| start.elapsed() < window);
+        return used.iter().map(|(_, airtime)| *airtime).sum();
+    }
+
+    /// Gets the time at which the downlinks in `used` leave the `window`,
+    /// so that there is enough airtime for a downlink with `airtime` within the `limit`;
+    /// `None` if the downlink alone exceeds the limit, so that there is never enough airtime.
+    fn available(used: &[(Instant, Duration)], window: Duration, limit: Duration, airtime: Duration) -> Option<Instant> {
+        if airtime > limit {
+            return None;
+        }
+        let mut total: Duration = used.iter().map(|(_, airtime)| *airtime).sum();
+        for (start, used_airtime) in used {
+            total = total.saturating_sub(*used_airtime);
+            if total + airtime <= limit {
+                return Some(*start + window);
+            }
+        }
+        return Some(Instant::now());
+    }
+}
+
+/**
+    Downlink of a [`Rule`], which could not be enqueued, even after all retries.
+    It is either sent to a device or to a multicast group.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// The id of the rule.
+    rule_id: u64,
+    /// The dev_eui of the device in hex; empty if the message is sent to a multicast group.
+    dev_eui: String,
+    /// The id of the multicast group; empty if the message is sent to a device.
+    #[serde(default)]
+    multicast_group_id: String,
+    /// The port to which the message is sent to.
+    f_port: u32,
+    /// The message encoded in hex; empty if the message is a JSON object.
+    message: String,
+    /// The message as JSON object; empty if the message is encoded in hex.
+    json_object: String,
+    /// Indicator if the message has to be confirmed by the device.
+    confirmed: bool,
+    /// The last error.
+    error: String,
+    /// The number of tries.
+    attempts: u32,
+    /// The time of the last try as RFC 3339 string.
+    time: String,
+}
+
+impl DeadLetter {
+    /// Gets the id of the rule.
+    pub fn get_rule_id(&self) -> u64 {
+        self.rule_id
+    }
+
+    /// Gets the dev_eui of the device in hex; empty if the message is sent to a multicast group.
+    pub fn get_dev_eui(&self) -> String {
+        self.dev_eui.clone()
+    }
+
+    /// Gets the id of the multicast group; empty if the message is sent to a device.
+    pub fn get_multicast_group_id(&self) -> String {
+        self.multicast_group_id.clone()
+    }
+
+    /// Gets the port to which the message is sent to.
+    pub fn get_f_port(&self) -> u32 {
+        self.f_port
+    }
+
+    /// Gets the message encoded in hex; empty if the message is a JSON object.
+    pub fn get_message(&self) -> String {
+        self.message.clone()
+    }
+
+    /// Gets the message as JSON object; empty if the message is encoded in hex.
+    pub fn get_json_object(&self) -> String {
+        self.json_object.clone()
+    }
+
+    /// Checks if the message has to be confirmed by the device.
+    pub fn is_confirmed(&self) -> bool {
+        self.confirmed
+    }
+
+    /// Gets the last error.
+    pub fn get_error(&self) -> String {
+        self.error.clone()
+    }
+
+    /// Gets the number of tries.
+    pub fn get_attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Gets the time of the last try as RFC 3339 string.
+    pub fn get_time(&self) -> String {
+        self.time.clone()
+    }
+}
+
+/**
+    Persistent store of [`DeadLetter`]s, which is saved as JSON file.
+    It can be cloned, so that it is shared by several rules.
+ */
+#[derive(Clone)]
+pub struct DeadLetterStore {
+    /// The path of the JSON file.
+    path: PathBuf,
+    /// The dead letters, the oldest first.
+    dead_letters: Arc<Mutex<Vec<DeadLetter>>>,
+}
+
+impl DeadLetterStore {
+    /// Opens the store saved in the file at `path`, or creates a new empty store, if there is no file.<br/>
+    /// For an example see this [link](./index.html#retrying-failed-downlinks).
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        let path = path.as_ref().to_path_buf();
+        let dead_letters = if path.exists() {
+            let file_text = std::fs::read_to_string(&path)?;
+            serde_json::from_str::<Vec<DeadLetter>>(&file_text)?
+        } else {
+            Vec::new()
+        };
+        return Ok(DeadLetterStore {
+            path,
+            dead_letters: Arc::new(Mutex::new(dead_letters)),
+        })
+    }
+
+    /// Gets a copy of all dead letters, the oldest first.
+    pub fn get_dead_letters(&self) -> Vec<DeadLetter> {
+        return self.dead_letters.lock().unwrap().clone();
+    }
+
+    /// Removes all dead letters.
+    pub fn clear(&self) -> Result<(), io::Error> {
+        let mut dead_letters = self.dead_letters.lock().unwrap();
+        dead_letters.clear();
+        return self.save(&dead_letters);
+    }
+
+    /// Enqueues all dead letters again, with the `client` and the `connection`.
+    /// The dead letters which are enqueued are removed, the others are kept with the new error.<br/>
+    /// Returns the number of dead letters, which are enqueued.
+    /// The acknowledgements of confirmed messages are not tracked.
+    pub async fn replay(&self, client: DeviceQueueServiceClient<Channel>, connection: ChirpstackConnection) -> Result<usize, io::Error> {
+        let dead_letters = std::mem::take(&mut *self.dead_letters.lock().unwrap());
+        let mut failed = Vec::new();
+        let mut count = 0;
+        for mut dead_letter in dead_letters {
+            let res = if dead_letter.multicast_group_id.is_empty() {
+                RuleContainer::enqueue_message(client.clone(), dead_letter.dev_eui.clone(), dead_letter.f_port, dead_letter.message.clone(), dead_letter.json_object.clone(), dead_letter.confirmed, connection.clone()).await.map(|_| ())
+            } else {
+                RuleContainer::enqueue_multicast_message(dead_letter.multicast_group_id.clone(), dead_letter.f_port, dead_letter.message.clone(), connection.clone()).await
+            };
+            match res {
+                Ok(()) => count += 1,
+                Err(e) => {
+                    dead_letter.error = e.to_string();
+                    dead_letter.attempts += 1;
+                    dead_letter.time = Utc::now().to_rfc3339();
+                    failed.push(dead_letter);
+                }
+            }
+        }
+        let mut dead_letters = self.dead_letters.lock().unwrap();
+        // dead letters added while replaying are kept after the failed ones
+        failed.append(&mut dead_letters);
+        *dead_letters = failed;
+        self.save(&dead_letters)?;
+        return Ok(count);
+    }
+
+    /// Adds a dead letter and saves the store.
+    fn add(&self, dead_letter: DeadLetter) -> Result<(), io::Error> {
+        let mut dead_letters = self.dead_letters.lock().unwrap();
+        dead_letters.push(dead_letter);
+        return self.save(&dead_letters);
+    }
+
+    /// Saves the `dead_letters` to the file.
+    fn save(&self, dead_letters: &[DeadLetter]) -> Result<(), io::Error> {
+        let file_text = serde_json::to_string_pretty(dead_letters)?;
+        std::fs::write(&self.path, file_text)
+    }
+}
+
+/**
+    Schedule of a [`Rule`] given by a cron expression.
+
+    The expression consists of five fields separated by spaces:
+    minute (0-59), hour (0-23), day of month (1-31), month (1-12 or `JAN`-`DEC`)
+    and day of week (0-7 or `SUN`-`SAT`, where 0 and 7 are Sunday).
+    Each field is either `*`, a value, a range `a-b`, a list `a,b,c` or a step `a-b/n`, where the range may also be `*`.
+    If both day of month and day of week are restricted, one of them has to match.
+*/
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    /// The cron expression.
+    expression: String,
+    /// Minutes at which the schedule matches.
+    minutes: Vec<u32>,
+    /// Hours at which the schedule matches.
+    hours: Vec<u32>,
+    /// Days of the month at which the schedule matches.
+    days_of_month: Vec<u32>,
+    /// Months at which the schedule matches.
+    months: Vec<u32>,
+    /// Days of the week at which the schedule matches, from 0 for Sunday to 6 for Saturday.
+    days_of_week: Vec<u32>,
+    /// If the day of month field is not `*`.
+    day_of_month_restricted: bool,
+    /// If the day of week field is not `*`.
+    day_of_week_restricted: bool,
+    /// [Timezone](https://docs.rs/chrono-tz/0.6.1/chrono_tz/enum.Tz.html) of the schedule;
+    /// `None` if the local time of the server should be used.
+    timezone: Option<Tz>,
+}
+
+impl Schedule {
+    /// Parses a schedule out of a cron expression, e.g. `0 6 * * MON-FRI` for every weekday at 6am.<br/>
+    /// An expression whose days of the month never occur in its months, e.g. `0 0 31 2 *`, is rejected with `InvalidInput`.
+    pub fn parse(expression: &str) -> Result<Schedule, io::Error> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "A cron expression must consist of five fields!"));
+        }
+        let months = ["JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC"];
+        let weekdays = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+        let mut days_of_week = Schedule::parse_field(fields[4], 0, 7, &weekdays)?;
+        // 7 is also Sunday
+        if days_of_week.contains(&7) {
+            days_of_week.retain(|day| *day != 7);
+            if !days_of_week.contains(&0) {
+                days_of_week.push(0);
+                days_of_week.sort();
+            }
+        }
+        let schedule = Schedule {
+            expression: expression.to_string(),
+            minutes: Schedule::parse_field(fields[0], 0, 59, &[])?,
+            hours: Schedule::parse_field(fields[1], 0, 23, &[])?,
+            days_of_month: Schedule::parse_field(fields[2], 1, 31, &[])?,
+            months: Schedule::parse_field(fields[3], 1, 12, &months)?,
+            days_of_week,
+            day_of_month_restricted: fields[2] != "*",
+            day_of_week_restricted: fields[4] != "*",
+            timezone: None,
+        };
+        // e.g. the 31st of February never occurs, unless one of the days of the week is enough
+        if !schedule.day_of_week_restricted {
+            let days_in_month = [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+            let possible = schedule.months.iter()
+                .any(|month| schedule.days_of_month.iter().any(|day| *day <= days_in_month[*month as usize - 1]));
+            if !possible {
+                return Err(io::Error::new(ErrorKind::InvalidInput, "The days of the month of the cron expression never occur in its months!"));
+            }
+        }
+        Ok(schedule)
+    }
+
+    /// Parses a field of a cron expression with values from `min` to `max`.
+    /// `names` are the names of the values beginning with `min`.
+    fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<Vec<u32>, io::Error> {
+        let error = || io::Error::new(ErrorKind::InvalidInput, "Field ".to_owned() + field + " of the cron expression could not be parsed!");
+        let value = |text: &str| -> Result<u32, io::Error> {
+            let upper = text.to_uppercase();
+            let value = match names.iter().position(|name| *name == upper) {
+                Some(position) => position as u32 + min,
+                None => text.parse::<u32>().map_err(|_| error())?,
+            };
+            if value < min || value > max {
+                return Err(error());
+            }
+            Ok(value)
+        };
+
+        let mut values = Vec::new();
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u32>().map_err(|_| error())?),
+                None => (part, 1),
+            };
+            if step == 0 {
+                return Err(error());
+            }
+            let (first, last) = if range == "*" {
+                (min, max)
+            } else if let Some((first, last)) = range.split_once('-') {
+                (value(first)?, value(last)?)
+            } else if part.contains('/') {
+                // e.g. 5/15 is every 15 minutes beginning with minute 5
+                (value(range)?, max)
+            } else {
+                let single = value(range)?;
+                (single, single)
+            };
+            if first > last {
+                return Err(error());
+            }
+            for i in (first..=last).step_by(step as usize) {
+                if !values.contains(&i) {
+                    values.push(i);
+                }
+            }
+        }
+        values.sort();
+        Ok(values)
+    }
+
+    /// Gets the cron expression.
+    pub fn get_expression(&self) -> String {
+        self.expression.clone()
+    }
+
+    /// Gets the timezone; `None` if the local time of the server is used.
+    pub fn get_timezone(&self) -> Option<Tz> {
+        self.timezone
+    }
+
+    /// Sets the timezone of the schedule via its IANA name, e.g. `Europe/Berlin` or `UTC`.<br/>
+    /// The schedule follows the wall clock time of the timezone: on the day the clocks are put forward,
+    /// times in the skipped hour are left out, and on the day they are put back,
+    /// times in the repeated hour are executed twice.
+    pub fn set_timezone(&mut self, timezone: &str) -> Result<(), io::Error> {
+        let tz = timezone.parse::<Tz>().map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+        self.timezone = Some(tz);
+        Ok(())
+    }
+
+    /// Checks if the schedule matches a specific date and time, to the minute.<br/>
+    /// The wall clock time of the given date and time is used, regardless of the timezone of the schedule.
+    pub fn matches<Z: TimeZone>(&self, date_time: &DateTime<Z>) -> bool {
+        let local = date_time.naive_local();
+        self.matches_day(local.date()) & self.minutes.contains(&local.minute()) & self.hours.contains(&local.hour()) & self.months.contains(&local.month())
+    }
+
+    /// Checks if the days of the month and the days of the week match a specific date.
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day_of_month = self.days_of_month.contains(&date.day());
+        let day_of_week = self.days_of_week.contains(&date.weekday().num_days_from_sunday());
+        if self.day_of_month_restricted && self.day_of_week_restricted {
+            day_of_month | day_of_week
+        } else {
+            day_of_month & day_of_week
+        }
+    }
+
+    /// Gets the next time after the given one, at which the schedule matches in its timezone.
+    /// `None` if there is no such time within the next four years.
+    pub fn next_after(&self, date_time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self.timezone {
+            Some(tz) => self.next_in(date_time, &tz),
+            None => self.next_in(date_time, &Local),
+        }
+    }
+
+    /// Gets the next time after the given one, at which the schedule matches in a timezone.
+    fn next_in<Z: TimeZone>(&self, date_time: DateTime<Utc>, timezone: &Z) -> Option<DateTime<Utc>> {
+        // beginning of the next minute
+        let start = date_time - chrono::Duration::seconds(date_time.second() as i64)
+            - chrono::Duration::nanoseconds(date_time.nanosecond() as i64) + chrono::Duration::minutes(1);
+        // around a change of the daylight saving time a later time can have an earlier wall clock time,
+        // therefore the wall clock times are searched from a few hours before until a few hours after the first match
+        let margin = chrono::Duration::hours(3);
+        let mut local = timezone.from_utc_datetime(&start.naive_utc()).naive_local() - margin;
+        // four years, so that also the 29th of February is found
+        let end = local + chrono::Duration::days(4 * 366) + margin;
+        let mut next: Option<(DateTime<Utc>, NaiveDateTime)> = None;
+        while let Some(candidate) = self.next_local(local, end) {
+            if let Some((_, first)) = next {
+                if candidate > first + margin {
+                    break
+                }
+            }
+            // wall clock times in the skipped hour do not exist, those in the repeated hour exist twice
+            let utcs = match timezone.from_local_datetime(&candidate) {
+                LocalResult::None => vec![],
+                LocalResult::Single(utc) => vec![utc],
+                LocalResult::Ambiguous(earliest, latest) => vec![earliest, latest],
+            };
+            for utc in utcs {
+                let utc = utc.with_timezone(&Utc);
+                if utc >= start && next.map_or(true, |(earliest, _)| utc < earliest) {
+                    next = Some((utc, candidate));
+                }
+            }
+            local = candidate + chrono::Duration::minutes(1);
+        }
+        return next.map(|(utc, _)| utc);
     }
 
+    /// Gets the first wall clock time from the given one on, at which the schedule matches;
+    /// `None` if there is none before `end`.<br/>
+    /// Instead of checking every minute, it skips to the next month, day or hour if the current one does not match.
+    fn next_local(&self, from: NaiveDateTime, end: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut next = from.date().and_hms(from.hour(), from.minute(), 0);
+        while next < end {
+            let date = next.date();
+            if !self.months.contains(&date.month()) {
+                // first day of the next month
+                let (year, month) = if date.month() == 12 { (date.year() + 1, 1) } else { (date.year(), date.month() + 1) };
+                next = NaiveDate::from_ymd(year, month, 1).and_hms(0, 0, 0);
+                continue
+            }
+            if !self.matches_day(date) {
+                next = date.succ().and_hms(0, 0, 0);
+                continue
+            }
+            let hour = match self.hours.iter().find(|hour| **hour >= next.hour()) {
+                Some(hour) => *hour,
+                None => {
+                    next = date.succ().and_hms(0, 0, 0);
+                    continue
+                },
+            };
+            if hour > next.hour() {
+                return Some(date.and_hms(hour, self.minutes[0], 0));
+            }
+            match self.minutes.iter().find(|minute| **minute >= next.minute()) {
+                Some(minute) => return Some(date.and_hms(hour, *minute, 0)),
+                // beginning of the next hour
+                None => next = date.and_hms(hour, 0, 0) + chrono::Duration::hours(1),
+            }
+        }
+        return None;
+    }
 }
 
 /// Trait for the selection of an comparison operator for a specific type.
@@ -1473,15 +4809,594 @@ impl Operator<bool> for bool {
 
 impl Operator<String> for String {
     fn get_operator(&self, operator: String) -> Result<fn(&String, &String) -> bool ,()> {
-        let op = match operator.as_str() {
+        let op: fn(&String, &String) -> bool = match operator.as_str() {
             "<" => <String as PartialOrd<String>>::lt,
             "<=" => <String as PartialOrd<String>>::le,
             ">" => <String as PartialOrd<String>>::gt,
             ">=" => <String as PartialOrd<String>>::ge,
             "==" => <String as PartialEq<String>>::eq,
             "!=" => <String as PartialEq<String>>::ne,
+            "contains" => |data, threshold| data.contains(threshold.as_str()),
+            "starts_with" => |data, threshold| data.starts_with(threshold.as_str()),
+            "ends_with" => |data, threshold| data.ends_with(threshold.as_str()),
             _ => return Err(())
         };
         return Ok(op);
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    #[cfg(not(feature = "v4"))]
+    use chirpstack_api::as_pb::external::api::GetDeviceResponse;
+    #[cfg(feature = "v4")]
+    use chirpstack_api::api::GetDeviceResponse;
+
+    /// Creates a container with devices, whose dev_eui is their index.
+    fn device_container(count: usize) -> DeviceContainer {
+        let mut dev_container = DeviceContainer::new();
+        for i in 0..count {
+            let chirpstack_device = ChirpstackDevice {
+                dev_eui: i.to_string(),
+                ..Default::default()
+            };
+            dev_container.add_device(Device::new(GetDeviceResponse {
+                device: Some(chirpstack_device),
+                ..Default::default()
+            }));
+        }
+        return dev_container;
+    }
+
+    /// Parses and evaluates an expression, the uplink data is 10 times the device index plus the uplink index.
+    fn evaluate(text: &str) -> Option<f32> {
+        let expression = Expression::parse(text, &device_container(2)).unwrap();
+        return expression.evaluate(&mut |device: &Device, index: usize| {
+            let dev_eui = device.get_chirpstack_device().device?.dev_eui;
+            Some(dev_eui.parse::<f32>().ok()? * 10.0 + index as f32)
+        });
+    }
+
+    #[test]
+    fn expression_precedence() {
+        assert_eq!(evaluate("1 + 2 * 3"), Some(7.0));
+        assert_eq!(evaluate("(1 + 2) * 3"), Some(9.0));
+        assert_eq!(evaluate("8 - 4 - 2"), Some(2.0));
+        assert_eq!(evaluate("8 / 4 / 2"), Some(1.0));
+        assert_eq!(evaluate("2 * 3 + 4 / 2"), Some(8.0));
+    }
+
+    #[test]
+    fn expression_unary_minus() {
+        assert_eq!(evaluate("-3 + 5"), Some(2.0));
+        assert_eq!(evaluate("2 * -3"), Some(-6.0));
+        assert_eq!(evaluate("-(1 + 2)"), Some(-3.0));
+        assert_eq!(evaluate("--2"), Some(2.0));
+    }
+
+    #[test]
+    fn expression_functions() {
+        assert_eq!(evaluate("abs(-2.5)"), Some(2.5));
+        assert_eq!(evaluate("min(3, 4 * 2)"), Some(3.0));
+        assert_eq!(evaluate("max(1, min(5, 2))"), Some(2.0));
+        assert_eq!(evaluate("abs(1 - max(4, 3))"), Some(3.0));
+    }
+
+    #[test]
+    fn expression_uplink_references() {
+        assert_eq!(evaluate("{1:2} - {0:1}"), Some(11.0));
+        assert_eq!(evaluate("max({0:3}, {1:0}) / 2"), Some(5.0));
+
+        let dev_container = device_container(2);
+        let expression = Expression::parse("{0:1} + {1:1} * {0:2}", &dev_container).unwrap();
+        assert_eq!(expression.get_devices().len(), 3);
+        // data which is not available makes the whole expression unavailable
+        assert_eq!(expression.evaluate(&mut |_: &Device, _: usize| None), None);
+        // there are only two devices
+        assert!(Expression::parse("{2:0}", &dev_container).is_err());
+    }
+
+    #[test]
+    fn expression_division_by_zero() {
+        assert_eq!(evaluate("1 / 0"), None);
+        assert_eq!(evaluate("1 / (2 - 2)"), None);
+        assert_eq!(evaluate("0 / 2"), Some(0.0));
+    }
+
+    #[test]
+    fn expression_malformed() {
+        let dev_container = device_container(1);
+        for text in ["", "1 +", "(1 + 2", "3)", "1 + * 2", "1.2.3", "foo(1)", "min(1)", "abs(1, 2)", "{0}", "{a:1}", "{0:1", "2 # 3"] {
+            let result = Expression::parse(text, &dev_container);
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput, "{}", text);
+        }
+    }
+
+    /// Creates a date and time in UTC.
+    fn date_time(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        return Utc.from_utc_datetime(&NaiveDate::from_ymd(year, month, day).and_hms(hour, minute, 0));
+    }
+
+    /// Creates a condition which is fulfilled the whole day.
+    fn whole_day() -> TimeCondition {
+        return TimeCondition::new(None, NaiveTime::from_hms(0, 0, 0), NaiveTime::from_hms(23, 59, 0));
+    }
+
+    #[test]
+    fn time_condition_weekdays() {
+        let mut condition = whole_day();
+        // 2022-10-17 is a Monday
+        for day in 17..24 {
+            assert!(condition.matches(&date_time(2022, 10, day, 12, 0)));
+        }
+        condition.set_weekdays(vec![Weekday::Mon, Weekday::Wed, Weekday::Sun]);
+        let fulfilled: Vec<u32> = (17..24).filter(|day| condition.matches(&date_time(2022, 10, *day, 12, 0))).collect();
+        assert_eq!(fulfilled, vec![17, 19, 23]);
+        #[allow(deprecated)]
+        let weekday = condition.get_weekday();
+        assert_eq!(weekday, Some(Weekday::Mon));
+    }
+
+    #[test]
+    fn time_condition_timespan() {
+        let condition = TimeCondition::new(None, NaiveTime::from_hms(22, 0, 0), NaiveTime::from_hms(2, 0, 0));
+        assert!(condition.matches(&date_time(2022, 10, 17, 22, 0)));
+        assert!(condition.matches(&date_time(2022, 10, 17, 1, 30)));
+        assert!(condition.matches(&date_time(2022, 10, 17, 2, 0)));
+        assert!(!condition.matches(&date_time(2022, 10, 17, 12, 0)));
+    }
+
+    #[test]
+    fn time_condition_date_range_over_new_year() {
+        let mut condition = whole_day();
+        condition.set_date_range(NaiveDate::from_ymd(2022, 12, 20), NaiveDate::from_ymd(2023, 1, 10), false);
+        assert!(!condition.matches(&date_time(2022, 12, 19, 12, 0)));
+        assert!(condition.matches(&date_time(2022, 12, 20, 0, 0)));
+        assert!(condition.matches(&date_time(2022, 12, 31, 23, 59)));
+        assert!(condition.matches(&date_time(2023, 1, 1, 0, 0)));
+        assert!(condition.matches(&date_time(2023, 1, 10, 23, 59)));
+        assert!(!condition.matches(&date_time(2023, 1, 11, 0, 0)));
+        // the range is not repeated
+        assert!(!condition.matches(&date_time(2023, 12, 31, 12, 0)));
+    }
+
+    #[test]
+    fn time_condition_yearly_dates() {
+        // the winter from the first of November to the last of March, in every year
+        let mut condition = whole_day();
+        condition.set_date_range(NaiveDate::from_ymd(2000, 11, 1), NaiveDate::from_ymd(2001, 3, 31), true);
+        assert!(!condition.matches(&date_time(2030, 10, 31, 12, 0)));
+        assert!(condition.matches(&date_time(2030, 11, 1, 12, 0)));
+        assert!(condition.matches(&date_time(2030, 12, 31, 12, 0)));
+        assert!(condition.matches(&date_time(2031, 1, 1, 12, 0)));
+        assert!(condition.matches(&date_time(2031, 3, 31, 12, 0)));
+        assert!(!condition.matches(&date_time(2031, 4, 1, 12, 0)));
+
+        // a single day in every year
+        condition.set_date_range(NaiveDate::from_ymd(2000, 12, 24), NaiveDate::from_ymd(2000, 12, 24), true);
+        assert!(condition.matches(&date_time(2025, 12, 24, 12, 0)));
+        assert!(!condition.matches(&date_time(2025, 12, 25, 12, 0)));
+
+        // the summer, which does not last over the new year
+        condition.set_date_range(NaiveDate::from_ymd(2000, 6, 1), NaiveDate::from_ymd(2000, 8, 31), true);
+        assert!(condition.matches(&date_time(2024, 7, 15, 12, 0)));
+        assert!(!condition.matches(&date_time(2024, 1, 15, 12, 0)));
+    }
+
+    #[test]
+    fn time_condition_exclusions() {
+        let mut condition = whole_day();
+        condition.set_weekdays(vec![Weekday::Sun]);
+        condition.set_date_range(NaiveDate::from_ymd(2000, 12, 1), NaiveDate::from_ymd(2000, 12, 31), true);
+        // 2022-12-25 is a Sunday
+        assert!(condition.matches(&date_time(2022, 12, 25, 12, 0)));
+        condition.add_exclusion(NaiveDate::from_ymd(2022, 12, 25));
+        assert!(!condition.matches(&date_time(2022, 12, 25, 12, 0)));
+        // the exclusion is not repeated every year, 2033-12-25 is a Sunday as well
+        assert!(condition.matches(&date_time(2033, 12, 25, 12, 0)));
+        assert!(condition.matches(&date_time(2022, 12, 18, 12, 0)));
+    }
+
+    /// Checks that a computed sunrise or sunset is at most 2 minutes away from the published one.
+    fn assert_sun_event(date: NaiveDate, latitude: f64, longitude: f64, sunrise: bool, expected: NaiveTime) {
+        let event = sun_event(date, latitude, longitude, sunrise).unwrap();
+        let difference = (event.time() - expected).num_seconds().abs();
+        assert!(difference <= 120, "{} differs from {}", event.time(), expected);
+    }
+
+    #[test]
+    fn sun_events() {
+        // Berlin on the summer solstice: sunrise 4:43 and sunset 21:33 in CEST, i.e. UTC+2
+        let date = NaiveDate::from_ymd(2022, 6, 21);
+        assert_sun_event(date, 52.52, 13.405, true, NaiveTime::from_hms(2, 43, 0));
+        assert_sun_event(date, 52.52, 13.405, false, NaiveTime::from_hms(19, 33, 0));
+
+        // New York on the winter solstice: sunrise 7:17 and sunset 16:32 in EST, i.e. UTC-5
+        let date = NaiveDate::from_ymd(2022, 12, 21);
+        assert_sun_event(date, 40.7128, -74.006, true, NaiveTime::from_hms(12, 17, 0));
+        assert_sun_event(date, 40.7128, -74.006, false, NaiveTime::from_hms(21, 32, 0));
+    }
+
+    #[test]
+    fn sun_events_polar() {
+        // in Tromsø the sun does not set in the polar day and does not rise in the polar night
+        for date in [NaiveDate::from_ymd(2022, 6, 21), NaiveDate::from_ymd(2022, 12, 21)] {
+            assert_eq!(sun_event(date, 69.6492, 18.9553, true), None);
+            assert_eq!(sun_event(date, 69.6492, 18.9553, false), None);
+        }
+
+        // then a timespan until sunset is never fulfilled
+        let mut condition = whole_day();
+        condition.set_location(69.6492, 18.9553).unwrap();
+        condition.set_timespan(TimeBoundary::Time(NaiveTime::from_hms(0, 0, 0)), TimeBoundary::Sunset(0));
+        assert!(!condition.matches(&date_time(2022, 6, 21, 12, 0)));
+        assert!(condition.matches(&date_time(2022, 3, 21, 12, 0)));
+    }
+
+    #[test]
+    fn schedule_lists_ranges_and_steps() {
+        let schedule = Schedule::parse("0,15,45 9-11,14 1,15 JAN-MAR,12 MON-FRI").unwrap();
+        assert_eq!(schedule.minutes, vec![0, 15, 45]);
+        assert_eq!(schedule.hours, vec![9, 10, 11, 14]);
+        assert_eq!(schedule.days_of_month, vec![1, 15]);
+        assert_eq!(schedule.months, vec![1, 2, 3, 12]);
+        assert_eq!(schedule.days_of_week, vec![1, 2, 3, 4, 5]);
+
+        let schedule = Schedule::parse("*/15 */6 5/10 * 7").unwrap();
+        assert_eq!(schedule.minutes, vec![0, 15, 30, 45]);
+        assert_eq!(schedule.hours, vec![0, 6, 12, 18]);
+        assert_eq!(schedule.days_of_month, vec![5, 15, 25]);
+        assert_eq!(schedule.months.len(), 12);
+        // 7 is Sunday as well as 0
+        assert_eq!(schedule.days_of_week, vec![0]);
+
+        let schedule = Schedule::parse("10-30/10 0 * * sun,6").unwrap();
+        assert_eq!(schedule.minutes, vec![10, 20, 30]);
+        assert_eq!(schedule.days_of_week, vec![0, 6]);
+    }
+
+    #[test]
+    fn schedule_invalid() {
+        for expression in ["", "* * * *", "* * * * * *", "60 * * * *", "* 24 * * *", "* * 0 * *", "* * 32 * *",
+            "* * * 13 *", "* * * * 8", "*/0 * * * *", "5-1 * * * *", "a * * * *", "1,,2 * * * *", "* * * FOO *",
+            "0 0 31 2 *", "0 0 30,31 2 *", "0 0 31 4,6,9,11 *"] {
+            let result = Schedule::parse(expression);
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput, "{}", expression);
+        }
+        // possible in leap years, or on Mondays
+        assert!(Schedule::parse("0 0 29 2 *").is_ok());
+        assert!(Schedule::parse("0 0 31 2 MON").is_ok());
+    }
+
+    #[test]
+    fn schedule_next_after() {
+        let mut schedule = Schedule::parse("0 6 * * MON-FRI").unwrap();
+        schedule.set_timezone("UTC").unwrap();
+        // 2022-10-21 is a Friday
+        assert_eq!(schedule.next_after(date_time(2022, 10, 21, 5, 59)), Some(date_time(2022, 10, 21, 6, 0)));
+        assert_eq!(schedule.next_after(date_time(2022, 10, 21, 6, 0)), Some(date_time(2022, 10, 24, 6, 0)));
+
+        let mut schedule = Schedule::parse("0 0 29 2 *").unwrap();
+        schedule.set_timezone("UTC").unwrap();
+        assert_eq!(schedule.next_after(date_time(2022, 3, 1, 0, 0)), Some(date_time(2024, 2, 29, 0, 0)));
+
+        let mut schedule = Schedule::parse("59 23 31 12 *").unwrap();
+        schedule.set_timezone("UTC").unwrap();
+        assert_eq!(schedule.next_after(date_time(2022, 1, 1, 0, 0)), Some(date_time(2022, 12, 31, 23, 59)));
+    }
+
+    #[test]
+    fn schedule_daylight_saving_time() {
+        let mut schedule = Schedule::parse("30 2 * * *").unwrap();
+        schedule.set_timezone("Europe/Berlin").unwrap();
+        // on 2022-03-27 the clocks are put forward from 2:00 to 3:00, so 2:30 is left out
+        assert_eq!(schedule.next_after(date_time(2022, 3, 26, 3, 0)), Some(date_time(2022, 3, 28, 0, 30)));
+        // on 2022-10-30 the clocks are put back from 3:00 to 2:00, so 2:30 occurs twice
+        let first = schedule.next_after(date_time(2022, 10, 29, 12, 0)).unwrap();
+        assert_eq!(first, date_time(2022, 10, 30, 0, 30));
+        assert_eq!(schedule.next_after(first), Some(date_time(2022, 10, 30, 1, 30)));
+        assert_eq!(schedule.next_after(date_time(2022, 10, 30, 1, 30)), Some(date_time(2022, 10, 31, 1, 30)));
+    }
+
+    /// Starts a http server, which answers one request per connection with the `statuses`, one after another,
+    /// and returns its url and a handle, which returns the received requests.
+    fn stub_server(statuses: Vec<u16>) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/tickets", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+                // the head ends with an empty line, the body has the length of the header
+                loop {
+                    let read = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length = text.lines()
+                            .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|length| length.trim().parse::<usize>().unwrap()))
+                            .unwrap_or(0);
+                        if request.len() >= end + 4 + length || read == 0 {
+                            break
+                        }
+                    }
+                }
+                requests.push(String::from_utf8_lossy(&request).to_string());
+                let response = format!("HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+        return (url, server);
+    }
+
+    #[tokio::test]
+    async fn webhook_statuses() {
+        let (url, server) = stub_server(vec![200, 503, 429, 404]);
+        let mut webhook = WebhookAction::new(&url);
+        webhook.add_header("Authorization", "Bearer token");
+        let payload: Value = serde_json::from_str(r#"{"rule_id": 1}"#).unwrap();
+
+        assert!(WebhookAction::send(webhook.request(&payload)).await.is_ok());
+        // server errors and too many requests are tried again
+        let e = WebhookAction::send(webhook.request(&payload)).await.unwrap_err();
+        assert!(RetryPolicy::is_transient(&e), "{}", e);
+        let e = WebhookAction::send(webhook.request(&payload)).await.unwrap_err();
+        assert!(RetryPolicy::is_transient(&e), "{}", e);
+        // other statuses are not
+        let e = WebhookAction::send(webhook.request(&payload)).await.unwrap_err();
+        assert!(!RetryPolicy::is_transient(&e), "{}", e);
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 4);
+        assert!(requests[0].starts_with("POST /tickets "));
+        assert!(requests[0].to_lowercase().contains("authorization: bearer token"));
+        assert!(requests[0].ends_with(r#"{"rule_id":1}"#));
+
+        // the server is gone, which is an error of the connection
+        let e = WebhookAction::send(webhook.request(&payload)).await.unwrap_err();
+        assert!(RetryPolicy::is_transient(&e), "{}", e);
+    }
+
+    #[test]
+    fn mqtt_publish_render() {
+        let template = r#"{"rule": {{rule_id}}, "name": {{ sensor.name }}, "temperature": {{sensor.temperature}}}"#;
+        match Action::new_mqtt_publish(MqttPublisher::unconnected(), "alarm", template, 3) {
+            Err(e) => assert_eq!(e.kind(), ErrorKind::InvalidInput),
+            Ok(_) => panic!("The quality of service 3 was accepted"),
+        }
+        let action = Action::new_mqtt_publish(MqttPublisher::unconnected(), "alarm", template, 1).unwrap();
+        let publish = match action.get_kind() {
+            ActionKind::MqttPublish(publish) => publish,
+            _ => panic!("Unexpected kind of action"),
+        };
+
+        // a string value is given as JSON string and a missing value is replaced by null
+        let mut values = HashMap::new();
+        values.insert("rule_id".to_string(), "1".to_string());
+        values.insert("sensor.name".to_string(), Value::String("living \"room\"".to_string()).to_string());
+        let payload: Value = serde_json::from_str(&publish.render(&values).unwrap()).unwrap();
+        assert_eq!(payload["rule"], 1);
+        assert_eq!(payload["name"], "living \"room\"");
+        assert_eq!(payload["temperature"], Value::Null);
+
+        // a value which is no JSON results in an invalid message
+        values.insert("sensor.temperature".to_string(), "warm".to_string());
+        assert_eq!(publish.render(&values).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn status_errors() {
+        let e = RetryPolicy::status_to_error(Status::unavailable("no server"));
+        assert_eq!(e.kind(), ErrorKind::NotConnected);
+        assert!(RetryPolicy::is_transient(&e));
+        let e = RetryPolicy::status_to_error(Status::unknown("transport error"));
+        assert_eq!(e.kind(), ErrorKind::NotConnected);
+        let e = RetryPolicy::status_to_error(Status::not_found("object does not exist"));
+        assert_eq!(e.kind(), ErrorKind::Other);
+        assert!(!RetryPolicy::is_transient(&e));
+        assert!(e.to_string().starts_with("object does not exist ("), "{}", e);
+    }
+
+    #[test]
+    fn webhook_backoff() {
+        assert_eq!(WebhookAction::backoff(0), Duration::from_secs(1));
+        assert_eq!(WebhookAction::backoff(1), Duration::from_secs(2));
+        assert_eq!(WebhookAction::backoff(2), Duration::from_secs(4));
+    }
+
+    #[test]
+    fn dead_letters_without_multicast_group() {
+        // dead letters, which were stored before multicast downlinks were added, are sent to devices
+        let path = std::env::temp_dir().join(format!("elorapi_dead_letters_{}.json", std::process::id()));
+        std::fs::write(&path, r#"[{"rule_id": 1, "dev_eui": "0102030405060708", "f_port": 10, "message": "01ff", "json_object": "",
+            "confirmed": false, "error": "unavailable", "attempts": 4, "time": "2022-06-21T12:00:00+00:00"}]"#).unwrap();
+        let store = DeadLetterStore::open(&path).unwrap();
+        let dead_letters = store.get_dead_letters();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].get_dev_eui(), "0102030405060708");
+        assert_eq!(dead_letters[0].get_multicast_group_id(), "");
+        store.clear().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Asserts that the `airtime` is the `expected` one in milliseconds, rounded to 0.1 ms.
+    fn assert_airtime(airtime: Duration, expected: f64) {
+        assert!((airtime.as_secs_f64() * 1000.0 - expected).abs() < 0.1, "{:?} is not {} ms", airtime, expected);
+    }
+
+    #[test]
+    fn airtime() {
+        // the values of the Semtech LoRa calculator with coding rate 4/5, 8 preamble symbols, explicit header and without CRC
+        // for 10 bytes of payload and 13 bytes of the LoRaWAN frame
+        assert_airtime(AirtimeBudget::airtime(10, 12, 125), 1482.8);
+        assert_airtime(AirtimeBudget::airtime(10, 7, 125), 56.6);
+        assert_airtime(AirtimeBudget::airtime(0, 12, 125), 1155.1);
+    }
+
+    #[test]
+    fn airtime_modulation() {
+        assert_eq!(AirtimeBudget::modulation("EU868", 0), Some((12, 125)));
+        assert_eq!(AirtimeBudget::modulation("EU868", 5), Some((7, 125)));
+        assert_eq!(AirtimeBudget::modulation("EU868", 6), Some((7, 250)));
+        assert_eq!(AirtimeBudget::modulation("EU868", 7), None);
+        assert_eq!(AirtimeBudget::modulation("US915", 0), Some((10, 125)));
+        assert_eq!(AirtimeBudget::modulation("US915", 8), Some((12, 500)));
+        assert_eq!(AirtimeBudget::modulation("AU915", 6), Some((8, 500)));
+    }
+
+    #[test]
+    fn airtime_budget_exceeded() {
+        let window = Duration::from_secs(3600);
+        // one downlink of 10 bytes with SF12 takes 1.48 secs
+        let budget = AirtimeBudget::new(window, Duration::from_secs(2), Duration::from_secs(4), BudgetPolicy::Coalesce);
+        // checking uses no airtime, e.g. if the enqueueing fails afterwards
+        assert!(budget.check("0102030405060708", "profile", 10).is_ok());
+        assert!(budget.check("0102030405060708", "profile", 10).is_ok());
+        assert_eq!(budget.get_device_usage("0102030405060708"), Duration::ZERO);
+
+        budget.record("0102030405060708", "profile", 10);
+        let (reason, available) = budget.check("0102030405060708", "profile", 10).unwrap_err();
+        assert!(reason.contains("device 0102030405060708"), "{}", reason);
+        let available = available.unwrap();
+        assert!(available > Instant::now() + window - Duration::from_secs(1));
+        // it is delayed with Coalesce and dropped with Drop
+        assert_eq!(budget.defer_until(Some(available)), Some(available));
+        let drop_budget = AirtimeBudget::new(window, Duration::from_secs(2), Duration::from_secs(4), BudgetPolicy::Drop);
+        assert_eq!(drop_budget.defer_until(Some(available)), None);
+
+        // the gateway budget is shared by the devices of the gateway
+        let budget = AirtimeBudget::new(window, Duration::from_secs(4), Duration::from_secs(4), BudgetPolicy::Coalesce);
+        budget.usage.lock().unwrap().gateways.insert("0102030405060708".to_string(), "aabbccddeeff0011".to_string());
+        budget.usage.lock().unwrap().gateways.insert("0807060504030201".to_string(), "aabbccddeeff0011".to_string());
+        budget.record("0102030405060708", "profile", 10);
+        budget.record("0102030405060708", "profile", 10);
+        let (reason, _) = budget.check("0807060504030201", "profile", 10).unwrap_err();
+        assert!(reason.contains("gateway aabbccddeeff0011"), "{}", reason);
+        assert!(budget.get_gateway_usage("AABBCCDDEEFF0011") > Duration::from_secs(2));
+    }
+
+    #[test]
+    fn airtime_budget_downlink_too_long() {
+        let budget = AirtimeBudget::new(Duration::from_secs(3600), Duration::from_secs(1), Duration::from_secs(10), BudgetPolicy::Coalesce);
+        // a downlink which alone exceeds the limit is dropped even with Coalesce, instead of being delayed forever
+        let (reason, available) = budget.check("0102030405060708", "profile", 10).unwrap_err();
+        assert!(reason.contains("alone"), "{}", reason);
+        assert_eq!(available, None);
+        assert_eq!(budget.defer_until(available), None);
+    }
+
+    /// Creates an ack event message of the device with `dev_eui` in hex for the downlink `f_cnt`,
+    /// whose id is the frame counter with Chirpstack version 3 and `id-<f_cnt>` with version 4.
+    fn ack_message(dev_eui: &str, f_cnt: u32, acknowledged: bool) -> Message {
+        #[cfg(not(feature = "v4"))]
+        let payload = serde_json::json!({"devEUI": base64::encode(hex::decode(dev_eui).unwrap()), "acknowledged": acknowledged, "fCnt": f_cnt});
+        #[cfg(feature = "v4")]
+        let payload = serde_json::json!({"deviceInfo": {"devEui": dev_eui}, "acknowledged": acknowledged, "fCntDown": f_cnt, "queueItemId": format!("id-{}", f_cnt)});
+        return Message::new(format!("application/1/device/{}/event/ack", dev_eui), payload.to_string(), 0);
+    }
+
+    /// Gets the id of the downlink `f_cnt`, like in [`ack_message`].
+    fn downlink_id(f_cnt: u32) -> String {
+        #[cfg(not(feature = "v4"))]
+        return f_cnt.to_string();
+        #[cfg(feature = "v4")]
+        return format!("id-{}", f_cnt);
+    }
+
+    #[test]
+    fn ack_tracker_and_retries() {
+        let dev_eui = "0102030405060708";
+        let (sender, receiver) = mpsc::channel();
+        let tracker = AckTracker::start(receiver);
+        // acknowledgements without downlink id can not be matched and are skipped
+        sender.send(Message::new("application/1/device/0102030405060708/event/ack", r#"{"acknowledged": true}"#, 0)).unwrap();
+        sender.send(ack_message(dev_eui, 3, true)).unwrap();
+        sender.send(ack_message(dev_eui, 4, false)).unwrap();
+        // the downlink 5 of another device does not match the downlink 5 of the device
+        sender.send(ack_message("0807060504030201", 5, true)).unwrap();
+        let start = Instant::now();
+        while tracker.acks.lock().unwrap().len() < 3 && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(tracker.acks.lock().unwrap().len(), 3);
+
+        let device = Device::new(GetDeviceResponse {
+            device: Some(ChirpstackDevice { dev_eui: dev_eui.to_string(), ..Default::default() }),
+            ..Default::default()
+        });
+        let action = Action::new_confirmed(device, vec![0], "0801".to_string(), 55, tracker.clone(), Duration::from_secs(600), 1);
+        let arc_rule = Rule::new(Vec::new(), Vec::new(), vec![action]);
+        let mut rule = arc_rule.lock().unwrap();
+
+        // the retried downlink can not be enqueued, because there is no server
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let connection = ChirpstackConnection::new("token", "http://127.0.0.1:1");
+        let client = DeviceQueueServiceClient::new(connection.get_channel().unwrap());
+        let mut queue = ActionQueue::new(runtime.handle().clone(), client, connection);
+        let trigger = Arc::new(Trigger::new(rule.id, &[]));
+        let awaiting = |f_cnt: u32, deadline: Instant, attempt: u32| AwaitingAck {
+            index: 0,
+            dev_eui: dev_eui.to_string(),
+            downlink_id: downlink_id(f_cnt),
+            deadline,
+            attempt,
+            trigger: Arc::clone(&trigger),
+        };
+        let later = Instant::now() + Duration::from_secs(600);
+        queue.awaiting = vec![
+            awaiting(3, later, 1),
+            // the last attempt is not tried again
+            awaiting(4, later, 2),
+            awaiting(5, Instant::now(), 1),
+            awaiting(6, later, 1),
+        ];
+        queue.check_acks(&mut rule);
+
+        let reports = rule.get_ack_reports();
+        assert_eq!(reports.len(), 3);
+        assert_eq!((reports[0].get_f_cnt(), reports[0].get_status(), reports[0].get_attempt()), (3, AckStatus::Acknowledged, 1));
+        assert_eq!((reports[1].get_f_cnt(), reports[1].get_status(), reports[1].get_attempt()), (4, AckStatus::NotAcknowledged, 2));
+        assert_eq!(reports[2].get_status(), AckStatus::Timeout);
+        #[cfg(not(feature = "v4"))]
+        assert_eq!(reports[2].get_f_cnt(), 5);
+        // the frame counter of a downlink, which was not acknowledged in time, is unknown with Chirpstack version 4
+        #[cfg(feature = "v4")]
+        assert_eq!(reports[2].get_f_cnt(), 0);
+
+        // the downlink without acknowledgement keeps waiting
+        assert_eq!(queue.awaiting.len(), 1);
+        assert_eq!(queue.awaiting[0].downlink_id, downlink_id(6));
+        // the timed out downlink is enqueued again as second attempt, which is retried after the failed request
+        assert_eq!(queue.pending.len(), 1);
+        assert_eq!((queue.pending[0].ack_attempt, queue.pending[0].retry), (2, 1));
+        // the acknowledgement of the other device is left
+        assert_eq!(tracker.acks.lock().unwrap().len(), 1);
+        assert!(tracker.take("0807060504030201", &downlink_id(5)).is_some());
+    }
+
+    #[test]
+    fn downlink_raw_bytes() {
+        // the device receives the bytes of the message and not their base64 text
+        let item = RuleContainer::device_queue_item("0102030405060708".to_string(), 10, "01ff", String::new(), false).unwrap();
+        assert_eq!(item.data, vec![0x01, 0xff]);
+        assert_eq!(item.f_port, 10);
+        let e = RuleContainer::device_queue_item("0102030405060708".to_string(), 10, "0g", String::new(), false).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn regex_threshold() {
+        let regex = RefValue::regex("^E[0-9]+$").unwrap();
+        assert!(RuleContainer::compare_value(&Value::from("E42"), "matches".to_string(), &regex).unwrap());
+        assert!(!RuleContainer::compare_value(&Value::from("W42"), "matches".to_string(), &regex).unwrap());
+        assert_eq!(RuleContainer::compare_value(&Value::from(42), "matches".to_string(), &regex).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(RuleContainer::compare_value(&Value::from("E42"), "==".to_string(), &regex).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(RefValue::regex("E[0-9").err().unwrap().kind(), ErrorKind::InvalidInput);
+    }
+}